@@ -0,0 +1,73 @@
+//! Compares the hand-written parser of [Assembler::assemble] with the pest grammar on a large
+//! generated program. Run with `cargo bench`, setting `BENCH_RUNS` to change the number of runs
+//! timed.
+use lc3asm::Assembler;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+const LINES: usize = 50_000;
+const RUNS: usize = 5;
+
+/// Generates a program of given number of lines, with a label every 16 lines.
+fn program(lines: usize) -> String {
+    let mut source = String::from("; Generated program\n        .ORIG x0200\n");
+    for line in 0..lines {
+        let block = line / 16;
+        if line % 16 == 0 {
+            write!(source, "BLOCK{}  ", block).unwrap();
+        } else {
+            source += "        ";
+        }
+        match line % 8 {
+            0 => source += "ADD     R1, R1, #-1 ; count down\n",
+            1 => source += "AND     R2, R3, R4\n",
+            2 => writeln!(source, "BRnp    BLOCK{}", block).unwrap(),
+            3 => source += "LDR     R0, R6, #4\n",
+            4 => source += "NOT     R5, R5\n",
+            5 => writeln!(source, "LEA     R0, BLOCK{}", block.saturating_sub(1)).unwrap(),
+            6 => source += "TRAP    x21\n",
+            _ => source += ".FILL   xBEEF\n",
+        }
+    }
+    source + "        HALT\n        .END\n"
+}
+
+/// Returns the shortest of given number of runs.
+fn time<T>(runs: usize, mut f: impl FnMut() -> T) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let runs = std::env::var("BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(RUNS);
+    let source = program(LINES);
+    let assembler = Assembler::new();
+    let fast = assembler.assemble(&source).unwrap();
+    let pest = assembler
+        .assemble_pairs(lc3asm::parse(&source).unwrap().collect())
+        .unwrap();
+    assert_eq!(fast, pest, "Both parsers should produce the same output");
+
+    let pest_time = time(runs, || {
+        assembler
+            .assemble_pairs(lc3asm::parse(&source).unwrap().collect())
+            .unwrap()
+    });
+    let fast_time = time(runs, || assembler.assemble(&source).unwrap());
+    println!("{} lines, {} bytes", LINES, source.len());
+    println!("pest grammar:        {:>10.2?}", pest_time);
+    println!("hand-written parser: {:>10.2?}", fast_time);
+    println!(
+        "speedup:             {:>9.1}x",
+        pest_time.as_secs_f64() / fast_time.as_secs_f64()
+    );
+}
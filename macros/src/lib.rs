@@ -0,0 +1,244 @@
+//! Provides [lc3!] macro which assembles LC-3 code at compile time.
+extern crate proc_macro;
+
+use pest::error::LineColLocation;
+use proc_macro2::{Delimiter, Group, LineColumn, Literal, Span, TokenStream, TokenTree};
+use quote::{quote, quote_spanned};
+
+/// Assembles LC-3 code at compile time into its object image: the origin followed by each word.
+///
+/// The code is written as tokens, one statement per line. Assembly errors are reported as compile
+/// errors at the offending tokens, while warnings are ignored.
+///
+/// ```
+/// use lc3asm_macros::lc3;
+///
+/// const PROGRAM: &[u16] = lc3! {
+///     .ORIG x3000
+///     LOOP ADD R0, R0, #-1 // Rust comments are accepted
+///          BRp LOOP
+///          HALT
+///     .END
+/// };
+/// assert_eq!(PROGRAM, &[0x3000, 0x103F, 0x03FE, 0xF025]);
+/// ```
+///
+/// Every token must be a valid Rust token, so `;` comments may only contain Rust tokens.
+///
+/// ```compile_fail
+/// // error: Value 16 is out of range: expected -16..15 for imm5
+/// const PROGRAM: &[u16] = lc3asm_macros::lc3! {
+///     .ORIG x3000
+///     ADD R0, R0, #16
+///     .END
+/// };
+/// ```
+#[proc_macro]
+pub fn lc3(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into()).into()
+}
+
+fn expand(input: TokenStream) -> TokenStream {
+    let source = Source::new(input);
+    match lc3asm::Assembler::new().assemble(&source.text) {
+        Ok(output) => {
+            let words = output.segments.iter().flat_map(|segment| {
+                std::iter::once(segment.origin).chain(segment.words.iter().cloned())
+            });
+            quote! {
+                {
+                    const IMAGE: &[u16] = &[#(#words),*];
+                    IMAGE
+                }
+            }
+        }
+        Err(err) => {
+            // The error covers the tokens from the start of the path to the end of the group
+            let (message, start, end) = source.locate(&err);
+            let mut message = Literal::string(&message);
+            message.set_span(end);
+            let mut group = Group::new(Delimiter::Brace, TokenTree::from(message).into());
+            group.set_span(end);
+            quote_spanned! {start=> ::core::compile_error! #group}
+        }
+    }
+}
+
+/// Source code reconstructed from tokens, keeping their lines and columns.
+struct Source {
+    text: String,
+    /// Start and end of each token relative to the first line, with its span
+    tokens: Vec<(LineColumn, LineColumn, Span)>,
+    first_line: usize,
+    position: LineColumn,
+}
+
+impl Source {
+    fn new(input: TokenStream) -> Self {
+        let first_line = input
+            .clone()
+            .into_iter()
+            .next()
+            .map_or(1, |token| token.span().start().line);
+        let mut source = Source {
+            text: String::new(),
+            tokens: Vec::new(),
+            first_line,
+            position: LineColumn { line: 1, column: 0 },
+        };
+        source.push_stream(input);
+        source
+    }
+
+    fn push_stream(&mut self, input: TokenStream) {
+        for token in input {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.push_stream(group.stream());
+                    self.push(close, group.span_close());
+                }
+                token => {
+                    let text = token.span().source_text();
+                    self.push(&text.unwrap_or_else(|| token.to_string()), token.span());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        let start = self.relative(span.start());
+        while self.position.line < start.line {
+            self.text.push('\n');
+            self.position.line += 1;
+            self.position.column = 0;
+        }
+        if self.position.line == start.line && self.position.column < start.column {
+            let padding = start.column - self.position.column;
+            self.text.extend(std::iter::repeat_n(' ', padding));
+            self.position.column = start.column;
+        } else if (start.line, start.column) < (self.position.line, self.position.column)
+            && !self.text.ends_with(char::is_whitespace)
+        {
+            // Tokens without locations are kept apart
+            self.text.push(' ');
+            self.position.column += 1;
+        }
+        let start = self.position;
+        for c in text.chars() {
+            self.text.push(c);
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 0;
+            } else {
+                self.position.column += 1;
+            }
+        }
+        self.tokens.push((start, self.position, span));
+    }
+
+    fn relative(&self, position: LineColumn) -> LineColumn {
+        LineColumn {
+            line: (position.line + 1).saturating_sub(self.first_line),
+            column: position.column,
+        }
+    }
+
+    /// Returns the message of given error and the spans of the first and last tokens it refers to.
+    fn locate(&self, err: &lc3asm::Error) -> (String, Span, Span) {
+        match err {
+            lc3asm::Error::Pest(err) => {
+                let ((line, column), end) = match err.line_col {
+                    LineColLocation::Pos(start) => (start, start),
+                    LineColLocation::Span(start, (line, column)) => {
+                        (start, (line, column.saturating_sub(1).max(1)))
+                    }
+                };
+                let start = self.token_at(line, column - 1);
+                let end = self.token_at(end.0, end.1 - 1);
+                (err.variant.message().into_owned(), start, end)
+            }
+            err => (err.to_string(), Span::call_site(), Span::call_site()),
+        }
+    }
+
+    /// Returns the span of the token at given position, or the closest token before it.
+    fn token_at(&self, line: usize, column: usize) -> Span {
+        self.tokens
+            .iter()
+            .find(|(start, end, _)| {
+                (start.line, start.column) <= (line, column)
+                    && (line, column) < (end.line, end.column)
+            })
+            .or_else(|| {
+                self.tokens
+                    .iter()
+                    .rev()
+                    .find(|(start, _, _)| (start.line, start.column) <= (line, column))
+            })
+            .map_or_else(Span::call_site, |(_, _, span)| *span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(input: &str) -> Source {
+        Source::new(input.parse().unwrap())
+    }
+
+    #[test]
+    fn test_reconstruction() {
+        let source = source(".ORIG x3000\n  LOOP ADD R0, R0, #-1 // comment\n  BRp LOOP\n.END");
+        assert_eq!(
+            source.text,
+            ".ORIG x3000\n  LOOP ADD R0, R0, #-1\n  BRp LOOP\n.END"
+        );
+    }
+
+    fn locate_error(input: &str) -> (String, (usize, usize), (usize, usize)) {
+        let source = source(input);
+        let err = lc3asm::Assembler::new().assemble(&source.text).unwrap_err();
+        let (message, start, end) = source.locate(&err);
+        (
+            message,
+            (start.start().line, start.start().column),
+            (end.start().line, end.start().column),
+        )
+    }
+
+    #[test]
+    fn test_error_location() {
+        let (message, start, end) = locate_error(".ORIG x3000\nADD R0, R0, #16\nBRz MISSING\n.END");
+        assert_eq!(
+            message,
+            "Value 16 is out of range: expected -16..15 for imm5"
+        );
+        assert_eq!((start, end), ((2, 12), (2, 13)));
+        let (message, start, end) = locate_error(".ORIG x3000\nHALT\nBRz MISSING\n.END");
+        assert!(
+            message.starts_with("Cannot find symbol MISSING"),
+            "{}",
+            message
+        );
+        assert_eq!((start, end), ((3, 4), (3, 4)));
+    }
+
+    #[test]
+    fn test_expand() {
+        let output = expand(".ORIG x3000\nHALT\n.END".parse().unwrap()).to_string();
+        assert!(output.contains("12288u16 , 61477u16"), "{}", output);
+        let output = expand(".ORIG x3000\nFOO BAR\n.END".parse().unwrap()).to_string();
+        assert!(output.starts_with(":: core :: compile_error !"), "{}", output);
+    }
+}
@@ -0,0 +1,52 @@
+use lc3asm_macros::lc3;
+
+const ECHO: &[u16] = lc3! {
+    .ORIG x3000
+            LEA R0, PROMPT
+            PUTS
+    LOOP:   GETC
+            ADD R1, R0, #-10 ; stop at newline
+            BRz DONE
+            OUT
+            BRnzp LOOP
+    DONE    LD R2, MASK
+            AND R0, R0, R2
+            HALT
+    MASK    .FILL 0x00FF
+    CHAR    .FILL 'A'
+    PROMPT  .STRINGZ "Type: \"x\"\n"
+    .END
+};
+
+#[test]
+fn test_same_image_as_assembler() {
+    let source = r#".ORIG x3000
+            LEA R0, PROMPT
+            PUTS
+    LOOP:   GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            OUT
+            BRnzp LOOP
+    DONE    LD R2, MASK
+            AND R0, R0, R2
+            HALT
+    MASK    .FILL 0x00FF
+    CHAR    .FILL 'A'
+    PROMPT  .STRINGZ "Type: \"x\"\n"
+    .END"#;
+    let output = lc3asm::Assembler::new().assemble(source).unwrap();
+    let mut image = vec![output.segments[0].origin];
+    image.extend(&output.segments[0].words);
+    assert_eq!(ECHO, &image[..]);
+}
+
+#[test]
+fn test_expression() {
+    let image: &'static [u16] = lc3! {
+        .ORIG x4000
+        TRAP x25
+        .END
+    };
+    assert_eq!(image, &[0x4000, 0xF025]);
+}
@@ -126,3 +126,38 @@ REM	    .FILL x4003
     assert 0x4002 == 2,
     assert 0x4003 == 1,
 );
+
+asm_test!(
+    pseudo_instructions,
+    r#"
+.ORIG   x3000
+        LD      R6, STACK
+        CLR     R0
+        ADD     R0, R0, #9
+        PUSH    R0
+        MOV     R1, R0
+        DEC     R1
+        INC     R1
+        INC     R1      ; R1 = 10
+        NEG     R2, R0
+        ADD     R2, R2, R1  ; SUB needs a scratch register
+        NEG     R3, R2  ; R3 = -1
+        CALL    DOUBLE
+        POP     R4
+        ADD     R2, R2, R3  ; 1 + -1
+        ADD     R2, R2, R4  ; 0 + 9
+        STI     R1, DOUBLED
+        STI     R2, SUM
+        HALT
+DOUBLE  ADD     R1, R1, R1
+        RET
+STACK   .FILL   x4000
+DOUBLED .FILL   x4100
+SUM     .FILL   x4101
+.END
+    "#,
+    "",
+    "",
+    assert 0x4100 == 20,
+    assert 0x4101 == 9,
+);
@@ -0,0 +1,1020 @@
+//! Provides configurable [Assembler] and its structured [AssemblyOutput].
+use crate::builder::ProgramBuilder;
+use crate::dialect::Dialect;
+use crate::embed;
+use crate::error::Error;
+use crate::extension::{Extension, ExtensionError};
+use crate::image::Image;
+use crate::isa::Format;
+use crate::preprocess::{Expander, Preprocessed, INPUT};
+use crate::symbol_table::{check_label_name, format_symbols};
+use crate::target::Target;
+use crate::Rule;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use pest::iterators::Pair;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// How warnings produced while assembling are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarningLevel {
+    /// Warnings are discarded.
+    Allow,
+    /// Warnings are collected into [AssemblyOutput::diagnostics].
+    #[default]
+    Warn,
+    /// Any warning aborts assembling with an error.
+    Deny,
+}
+
+/// Serialization format of [AssemblyOutput::to_bytes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum OutputFormat {
+    /// Object file of the [Target], see [AssemblyOutput::object_bytes].
+    #[default]
+    Object,
+    /// Text file with one hexadecimal word per line, origin first.
+    Hex,
+    /// Text file with one 16-digit binary word per line, origin first.
+    Binary,
+}
+
+/// Severity of a [Diagnostic].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    /// Error which aborted assembling, see [Error::diagnostic]
+    Error,
+    Warning,
+    /// Information about a change made by the assembler, e.g. a relaxed branch
+    Note,
+}
+
+/// Position of a [Diagnostic] or of the statement a [Word](crate::Word) was assembled from in
+/// the source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Location {
+    /// Byte offset of the start of the span
+    pub start: usize,
+    /// Byte offset of the end of the span
+    pub end: usize,
+    /// 1-based line number in the file
+    pub line: usize,
+    /// 1-based column number
+    pub column: usize,
+    /// Index of the file holding the span in [AssemblyOutput::files], 0 for the assembled
+    /// source. Locations of errors are in the file named by [Diagnostic::file].
+    pub file: usize,
+}
+
+impl<'i> From<&Pair<'i, Rule>> for Location {
+    fn from(pair: &Pair<'i, Rule>) -> Self {
+        let span = pair.as_span();
+        let (line, column) = span.start_pos().line_col();
+        Location {
+            start: span.start(),
+            end: span.end(),
+            line,
+            column,
+            file: 0,
+        }
+    }
+}
+
+/// Message produced while assembling which does not abort assembling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+    /// Name of the included file holding the location, `None` for the assembled source
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub file: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message)?,
+            Severity::Warning => write!(f, "warning: {}", self.message)?,
+            Severity::Note => write!(f, "note: {}", self.message)?,
+        }
+        if let Some(location) = self.location {
+            match &self.file {
+                Some(file) => write!(
+                    f,
+                    " (in {}, line {}, column {})",
+                    file, location.line, location.column
+                )?,
+                None => write!(f, " (line {}, column {})", location.line, location.column)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Source statement with the words it was assembled into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ListingLine {
+    /// Address of the first word
+    pub address: u16,
+    pub words: Vec<u16>,
+    /// 1-based line number in the file the statement was read from
+    pub line: usize,
+    /// Source line of the statement
+    pub source: String,
+    /// Instruction written as each word, if the statement was expanded by a pseudo-instruction
+    /// or relaxation
+    pub expansion: Vec<String>,
+}
+
+/// Contiguous block of assembled words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Segment {
+    /// Address of the first word
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+/// Result of [Assembler::assemble].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AssemblyOutput {
+    pub segments: Vec<Segment>,
+    /// Absolute address of each label
+    pub symbols: BTreeMap<String, u16>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Each statement of the source code, empty for [ProgramBuilder] outputs
+    pub listing: Vec<ListingLine>,
+    /// Vector of each trap alias defined with `.TRAPDEF` or [Assembler::trap_alias], keyed by
+    /// its name in uppercase
+    pub trap_aliases: BTreeMap<String, u8>,
+    /// Words with the statement each was assembled from, from which the segments, listing and
+    /// object file are derived
+    pub image: Image,
+    /// Name of each file the source code was read from, indexed by [Location::file]: the
+    /// assembled source followed by included files, e.g. `lib.asm` or `<std/io>`
+    pub files: Vec<String>,
+    pub(crate) format: OutputFormat,
+    pub(crate) target: Target,
+}
+
+impl AssemblyOutput {
+    /// Returns the object file contents: origin of the first segment followed by all words.
+    ///
+    /// LC-3 object files hold big-endian words, while LC-3b object files are text with one
+    /// hexadecimal word per line, e.g. `0x3000`, as read by `lc3bsim`.
+    pub fn object_bytes(&self) -> Vec<u8> {
+        match self.target {
+            Target::Lc3 => self
+                .image
+                .object_words()
+                .flat_map(|word| word.to_be_bytes().to_vec())
+                .collect(),
+            Target::Lc3b => self
+                .image
+                .object_words()
+                .map(|word| format!("0x{:04X}\n", word))
+                .collect::<String>()
+                .into_bytes(),
+        }
+    }
+
+    /// Returns the symbol table in `.sym` file format.
+    ///
+    /// Trap aliases are listed at the address of their entry in the trap vector table.
+    pub fn symbol_table(&self) -> Result<String, Error> {
+        let mut symbols = self.symbols.clone();
+        symbols.extend(
+            self.trap_aliases
+                .iter()
+                .map(|(name, vector)| (name.clone(), u16::from(*vector))),
+        );
+        Ok(format_symbols(&symbols)?)
+    }
+
+    /// Returns the listing as text: address, word, line number and source of each statement.
+    ///
+    /// Words of expanded statements follow their source with the instruction they encode:
+    ///
+    /// ```text
+    /// x3000  x1021     2  ADD R0, R0, #1
+    ///                  3  PUSH R0
+    /// x3001  x1DBF        ; ADD R6, R6, #-1
+    /// x3002  x7180        ; STR R0, R6, #0
+    /// ```
+    pub fn format_listing(&self) -> String {
+        let mut text = String::new();
+        for line in &self.listing {
+            let mut rows = line
+                .words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| {
+                    format!(
+                        "x{:04X}  x{:04X}",
+                        line.address.wrapping_add(i as u16),
+                        word
+                    )
+                })
+                .collect::<Vec<_>>()
+                .into_iter();
+            if line.expansion.is_empty() {
+                let first = rows.next().unwrap_or_default();
+                text += &format!("{:12}  {:>4}  {}\n", first, line.line, line.source);
+                for row in rows {
+                    text += &format!("{}\n", row);
+                }
+            } else {
+                text += &format!("{:12}  {:>4}  {}\n", "", line.line, line.source);
+                for (row, instruction) in rows.zip(&line.expansion) {
+                    text += &format!("{}        ; {}\n", row, instruction);
+                }
+            }
+        }
+        text
+    }
+
+    /// Returns the source map as text: address, line and column of the statement each word was
+    /// assembled from, preceded by the name of the file for included files.
+    ///
+    /// ```text
+    /// x3000  2:1
+    /// x3001  3:3
+    /// x3002  <std/io>:12:9
+    /// ```
+    pub fn format_source_map(&self) -> String {
+        self.image
+            .source_map()
+            .into_iter()
+            .map(|(address, location)| match self.files.get(location.file) {
+                Some(file) if location.file != 0 => format!(
+                    "x{:04X}  {}:{}:{}\n",
+                    address, file, location.line, location.column
+                ),
+                _ => format!("x{:04X}  {}:{}\n", address, location.line, location.column),
+            })
+            .collect()
+    }
+
+    /// Returns a C header declaring the words as `static const uint16_t name[]`, with the origin,
+    /// size and address of each label as `#define`s prefixed by `NAME_`.
+    ///
+    /// `name` is converted into a C identifier, e.g. `hello-world` becomes `hello_world`.
+    pub fn format_c_header(&self, name: &str) -> Result<String, Error> {
+        Ok(embed::c_header(self, name)?)
+    }
+
+    /// Returns a Rust module declaring `ORIGIN`, the words as `WORDS: [u16; N]` and a `symbols`
+    /// module holding the address of each label.
+    ///
+    /// Unlike the `IMAGE` of [build](crate::build) modules, `WORDS` does not start with the
+    /// origin.
+    pub fn format_rust_module(&self) -> Result<String, Error> {
+        Ok(embed::rust_module(self)?)
+    }
+
+    /// Serializes the image in the [OutputFormat] configured with [Assembler::output_format].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.format {
+            OutputFormat::Object => self.object_bytes(),
+            OutputFormat::Hex => self
+                .image
+                .object_words()
+                .map(|word| format!("{:04X}\n", word))
+                .collect::<String>()
+                .into_bytes(),
+            OutputFormat::Binary => self
+                .image
+                .object_words()
+                .map(|word| format!("{:016b}\n", word))
+                .collect::<String>()
+                .into_bytes(),
+        }
+    }
+}
+
+/// Error returned when a trap alias added with [Assembler::trap_alias] cannot be defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapAliasError {
+    pub name: String,
+    pub message: String,
+}
+
+impl fmt::Display for TrapAliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TrapAliasError {}
+
+/// Trap alias or extension which could not be added to an [Assembler].
+#[derive(Debug, Clone)]
+pub(crate) enum Rejected {
+    TrapAlias(TrapAliasError),
+    Extension(ExtensionError),
+}
+
+/// Configurable assembler.
+///
+/// ```
+/// use lc3asm::{Assembler, WarningLevel};
+///
+/// let output = Assembler::new()
+///     .warnings(WarningLevel::Deny)
+///     .define("COUNT", 3)
+///     .assemble(".ORIG x3000\nADD R0, R0, COUNT\nHALT\n.END")
+///     .unwrap();
+/// assert_eq!(output.segments[0].words, vec![0x1023, 0xF025]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    pub(crate) warnings: WarningLevel,
+    pub(crate) dialect: Dialect,
+    pub(crate) strict: bool,
+    pub(crate) case_insensitive_labels: bool,
+    pub(crate) unicode_labels: bool,
+    pub(crate) origin: Option<u16>,
+    #[cfg(feature = "std")]
+    pub(crate) include_paths: Vec<PathBuf>,
+    pub(crate) constants: BTreeMap<String, i64>,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) relax_branches: bool,
+    pub(crate) scratch_register: Option<u8>,
+    pub(crate) target: Target,
+    pub(crate) extensions: Vec<Extension>,
+    pub(crate) trap_aliases: BTreeMap<String, u8>,
+    /// First trap alias or extension which could not be added, returned when assembling
+    pub(crate) rejected: Option<Rejected>,
+}
+
+impl Assembler {
+    /// Creates an assembler with default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how warnings are treated. Defaults to [WarningLevel::Warn].
+    pub fn warnings(mut self, level: WarningLevel) -> Self {
+        self.warnings = level;
+        self
+    }
+
+    /// Sets the accepted dialect. Defaults to [Dialect::Lc3asm].
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets whether constructs not accepted by every [Dialect] produce warnings. Defaults to `false`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets whether label names are case sensitive. Defaults to `true`.
+    pub fn case_sensitive_labels(mut self, case_sensitive: bool) -> Self {
+        self.case_insensitive_labels = !case_sensitive;
+        self
+    }
+
+    /// Sets whether labels may contain non-ASCII Unicode letters. Defaults to `false`.
+    pub fn unicode_labels(mut self, unicode: bool) -> Self {
+        self.unicode_labels = unicode;
+        self
+    }
+
+    /// Overrides the address given by `.ORIG`.
+    pub fn origin(mut self, origin: u16) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Adds a directory searched by `.INCLUDE` directives.
+    #[cfg(feature = "std")]
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    /// Predefines a constant usable wherever a label or number literal is accepted.
+    pub fn define(mut self, name: impl Into<String>, value: i64) -> Self {
+        self.constants.insert(name.into(), value);
+        self
+    }
+
+    /// Sets the format of [AssemblyOutput::to_bytes]. Defaults to [OutputFormat::Object].
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Sets the architecture to assemble for. Defaults to [Target::Lc3].
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets whether PC-relative `BR`, `JSR`, `LD`, `LDI` and `LEA` instructions whose label is
+    /// out of range are rewritten into longer sequences. Defaults to `false`.
+    /// Only applies to [Target::Lc3].
+    ///
+    /// Each rewritten instruction is reported with a [Severity::Note] diagnostic. A relaxed `BR`
+    /// jumps through the register set with [Assembler::scratch_register], overwriting it, so
+    /// relaxing a branch fails unless the register is set explicitly.
+    ///
+    /// ```
+    /// use lc3asm::Assembler;
+    ///
+    /// let source = ".ORIG x3000\nBRz FAR\n.BLKW 300\nFAR HALT\n.END";
+    /// assert!(Assembler::new().assemble(source).is_err());
+    /// assert!(Assembler::new().relax_branches(true).assemble(source).is_err());
+    /// let output = Assembler::new()
+    ///     .relax_branches(true)
+    ///     .scratch_register(7)
+    ///     .assemble(source)
+    ///     .unwrap();
+    /// assert_eq!(output.segments[0].words[..4], [0x0A03, 0x2E01, 0xC1C0, 0x3130]);
+    /// assert_eq!(output.symbols["FAR"], 0x3130);
+    /// ```
+    pub fn relax_branches(mut self, relax: bool) -> Self {
+        self.relax_branches = relax;
+        self
+    }
+
+    /// Sets the register which relaxed branches and the `SUB` pseudo-instruction overwrite.
+    ///
+    /// Both require it to be set, as `R7` often holds a return address.
+    ///
+    /// Panics if the register is not in `0..=7`.
+    pub fn scratch_register(mut self, register: u8) -> Self {
+        assert!(register < 8, "Invalid register R{}", register);
+        self.scratch_register = Some(register);
+        self
+    }
+
+    /// Defines a mnemonic for `TRAP` with given vector, as `.TRAPDEF` does.
+    ///
+    /// Assembling fails if the name is a reserved word or not a valid label.
+    ///
+    /// ```
+    /// use lc3asm::Assembler;
+    ///
+    /// let output = Assembler::new()
+    ///     .trap_alias("PUTNUM", 0x26)
+    ///     .assemble(".ORIG x3000\nPUTNUM\n.END")
+    ///     .unwrap();
+    /// assert_eq!(output.segments[0].words, vec![0xF026]);
+    /// ```
+    pub fn trap_alias(mut self, name: impl Into<String>, vector: u8) -> Self {
+        let name = name.into();
+        match check_label_name(&name, &self) {
+            Ok(()) => {
+                self.trap_aliases.insert(name.to_ascii_uppercase(), vector);
+            }
+            Err(message) => self.reject(Rejected::TrapAlias(TrapAliasError { name, message })),
+        }
+        self
+    }
+
+    /// Adds an instruction encoded with the reserved opcode `1101`, see [crate::extension].
+    /// Only applies to [Target::Lc3], as LC-3b uses the opcode for shifts.
+    ///
+    /// Assembling fails if it conflicts with an extension already added.
+    ///
+    /// ```
+    /// use lc3asm::Assembler;
+    ///
+    /// let mul = "MUL DR:reg SR1:reg 000 SR2:reg".parse().unwrap();
+    /// let output = Assembler::new()
+    ///     .extension(mul)
+    ///     .assemble(".ORIG x3000\nMUL R0, R1, R2\n.END")
+    ///     .unwrap();
+    /// assert_eq!(output.segments[0].words, vec![0xD042]);
+    /// ```
+    pub fn extension(mut self, extension: Extension) -> Self {
+        if let Some(message) = self
+            .extensions
+            .iter()
+            .find_map(|other| extension.conflict(other))
+        {
+            self.reject(Rejected::Extension(ExtensionError {
+                line: None,
+                message,
+            }));
+        } else {
+            self.extensions.push(extension);
+        }
+        self
+    }
+
+    /// Adds every given extension, e.g. read with [crate::extension::parse].
+    pub fn extensions(self, extensions: impl IntoIterator<Item = Extension>) -> Self {
+        extensions.into_iter().fold(self, Self::extension)
+    }
+
+    /// Records why an option could not be set, unless an earlier option was rejected.
+    fn reject(&mut self, rejected: Rejected) {
+        self.rejected.get_or_insert(rejected);
+    }
+
+    /// Returns the error of the first trap alias or extension which could not be added.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        match &self.rejected {
+            Some(Rejected::TrapAlias(err)) => Err(err.clone().into()),
+            Some(Rejected::Extension(err)) => Err(err.clone().into()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn scratch(&self) -> u8 {
+        self.scratch_register.unwrap_or(7)
+    }
+
+    /// Returns the formats of the extensions available on the target.
+    pub(crate) fn extension_formats(&self) -> impl Iterator<Item = &Format> {
+        self.extensions
+            .iter()
+            .filter(move |_| self.target == Target::Lc3)
+            .map(Extension::format)
+    }
+
+    /// Assembles given source code.
+    ///
+    /// Without the `std` feature there is no filesystem to read `.INCLUDE`d files from, and
+    /// only modules of the [stdlib](crate::stdlib) may be included.
+    pub fn assemble(&self, input: impl AsRef<str>) -> Result<AssemblyOutput, Error> {
+        let source = Expander::new(self, INPUT.into(), input.as_ref().to_owned()).run(None)?;
+        self.assemble_preprocessed(&source)
+    }
+
+    /// Reads and assembles given file.
+    /// Relative `.INCLUDE` paths are resolved against the directory of the file first.
+    #[cfg(feature = "std")]
+    pub fn assemble_file(&self, path: impl AsRef<Path>) -> Result<AssemblyOutput, Error> {
+        let (source, _) = self.preprocess_file(path.as_ref())?;
+        self.assemble_preprocessed(&source)
+    }
+
+    /// Assembles already parsed pairs. `.INCLUDE` directives are not processed.
+    pub fn assemble_pairs(&self, pairs: Vec<Pair<Rule>>) -> Result<AssemblyOutput, Error> {
+        self.check()?;
+        crate::assemble_with(self, pairs)
+    }
+
+    /// Creates a [ProgramBuilder] for a program starting at given address, built with these options.
+    pub fn builder(&self, origin: u16) -> ProgramBuilder {
+        ProgramBuilder::with_options(self.clone(), origin)
+    }
+
+    /// Expands `.INCLUDE` directives of given source code, of files and of modules of the
+    /// [stdlib](crate::stdlib), e.g. `.INCLUDE <std/io>`.
+    ///
+    /// Relative paths are resolved against `base_dir` first.
+    #[cfg(feature = "std")]
+    pub fn preprocess(&self, input: &str, base_dir: Option<&Path>) -> Result<Preprocessed, Error> {
+        Expander::new(self, INPUT.into(), input.to_owned()).run(base_dir)
+    }
+
+    /// Reads given file and expands its `.INCLUDE` directives.
+    /// Returns the expanded source with the path of every included file.
+    #[cfg(feature = "std")]
+    pub(crate) fn preprocess_file(
+        &self,
+        path: &Path,
+    ) -> Result<(Preprocessed, Vec<PathBuf>), Error> {
+        let input = fs::read_to_string(path)?;
+        Expander::new(self, path.display().to_string(), input).run_with_included(path.parent())
+    }
+
+    /// Assembles source code whose `.INCLUDE` directives are expanded by
+    /// [Assembler::preprocess], reporting positions in the file each statement was read from.
+    pub fn assemble_preprocessed(&self, source: &Preprocessed) -> Result<AssemblyOutput, Error> {
+        self.assemble_text(source.text())
+            .map(|output| source.map_output(output))
+            .map_err(|err| source.map_error(err))
+    }
+
+    /// Assembles source code without `.INCLUDE` directives.
+    ///
+    /// Plain LC-3 programs are read by a hand-written parser, which is several times faster
+    /// than the grammar on large inputs. Other programs, and programs with errors, are read by
+    /// [AsmParser](crate::AsmParser) with the same result as [Assembler::assemble_pairs].
+    pub(crate) fn assemble_text(&self, source: &str) -> Result<AssemblyOutput, Error> {
+        self.check()?;
+        if let Some(output) = crate::fast::assemble(self, source) {
+            return Ok(output);
+        }
+        let pairs = crate::parse(source)?.collect();
+        self.assemble_pairs(pairs)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn find_include(&self, target: &str, base_dir: Option<&Path>) -> Option<PathBuf> {
+        base_dir
+            .into_iter()
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(target))
+            .find(|path| path.is_file())
+            .map(|path| path.canonicalize().unwrap_or(path))
+    }
+
+    /// Returns the key used to store given label name in symbol tables.
+    pub(crate) fn symbol_key(&self, name: &str) -> String {
+        if self.case_insensitive_labels {
+            name.to_uppercase()
+        } else {
+            name.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = r#"
+.ORIG x3000
+loop    ADD R0, R0, STEP
+        BRp LOOP
+        .FILL LOOP
+        .FILL STEP
+        BR loop
+.END
+"#;
+
+    #[test]
+    fn test_output_structure() -> Result<(), Error> {
+        let output = Assembler::new()
+            .case_sensitive_labels(false)
+            .define("step", -1)
+            .origin(0x4000)
+            .assemble(PROGRAM)?;
+        assert_eq!(
+            output.segments,
+            vec![Segment {
+                origin: 0x4000,
+                words: vec![0x103F, 0x03FE, 0x4000, 0xFFFF, 0x0FFB],
+            }]
+        );
+        assert_eq!(output.symbols.get("loop"), Some(&0x4000));
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].location.unwrap().line, 7);
+        assert_eq!(
+            output.object_bytes(),
+            vec![0x40, 0x00, 0x10, 0x3F, 0x03, 0xFE, 0x40, 0x00, 0xFF, 0xFF, 0x0F, 0xFB]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_sensitive_labels() {
+        Assembler::new()
+            .define("STEP", 1)
+            .assemble(PROGRAM)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_blkw_count() -> Result<(), Error> {
+        let output = Assembler::new()
+            .define("SIZE", 2)
+            .assemble(".ORIG x3000\nL .BLKW SIZE\nM .BLKW 1\n.END")?;
+        assert_eq!(output.symbols["M"], 0x3002);
+        for (source, line, message) in [
+            (
+                ".ORIG x3000\nL .BLKW 1\nM .BLKW L\n.END",
+                3,
+                "Count of .BLKW must be a number or a constant, found label L",
+            ),
+            (
+                ".ORIG x3000\nM .BLKW L\nL .BLKW 1\n.END",
+                2,
+                "Cannot find symbol L",
+            ),
+        ] {
+            let err = Assembler::new().assemble(source).unwrap_err();
+            let diagnostic = err.diagnostic();
+            assert_eq!(diagnostic.location.unwrap().line, line);
+            assert!(
+                diagnostic.message.contains(message),
+                "{}",
+                diagnostic.message
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_warning_levels() -> Result<(), Error> {
+        let source = ".ORIG x3000\nA BR A\n.END";
+        let output = Assembler::new()
+            .warnings(WarningLevel::Allow)
+            .assemble(source)?;
+        assert!(output.diagnostics.is_empty());
+        Assembler::new()
+            .warnings(WarningLevel::Deny)
+            .assemble(source)
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_formats() -> Result<(), Error> {
+        let source = ".ORIG x3000\nHALT\n.END";
+        let hex = Assembler::new()
+            .output_format(OutputFormat::Hex)
+            .assemble(source)?;
+        assert_eq!(hex.to_bytes(), b"3000\nF025\n".to_vec());
+        let bin = Assembler::new()
+            .output_format(OutputFormat::Binary)
+            .assemble(source)?;
+        assert_eq!(
+            bin.to_bytes(),
+            b"0011000000000000\n1111000000100101\n".to_vec()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_include() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("lc3asm_test_include_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("lib.asm"), "PRINT PUTS\n RET\n")?;
+        let output = Assembler::new()
+            .include_path(&dir)
+            .assemble(".ORIG x3000\n JSR PRINT\n HALT\n .include \"lib.asm\"\n.END")?;
+        assert_eq!(
+            output.segments[0].words,
+            vec![0x4801, 0xF025, 0xF022, 0xC1C0]
+        );
+        assert_eq!(output.symbols.get("PRINT"), Some(&0x3002));
+        Assembler::new()
+            .assemble(".ORIG x3000\n.INCLUDE \"lib.asm\"\n.END")
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_locations() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!(
+            "lc3asm_test_include_locations_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("lib.asm"),
+            "; print\nPRINT PUTS\n BR PRINT\n RET\n",
+        )?;
+        let assembler = Assembler::new().include_path(&dir);
+
+        let err = assembler
+            .assemble(".ORIG x3000\n.INCLUDE \"lib.asm\"\nADD R0, R0, #99\n.END")
+            .unwrap_err();
+        let location = err.diagnostic().location.unwrap();
+        assert_eq!((location.line, location.column), (3, 13));
+        assert!(err.to_string().contains("3:13"), "{}", err);
+
+        let err = assembler
+            .assemble(".ORIG x3000\n.INCLUDE \"lib.asm\"\nADD R0, R0\n.END")
+            .unwrap_err();
+        assert_eq!(err.diagnostic().location.unwrap().line, 3);
+
+        let output = assembler.assemble(".ORIG x3000\n.INCLUDE \"lib.asm\"\nA BRnzp A\n.END")?;
+        assert_eq!(output.files, ["<input>", "lib.asm"]);
+        let warning = &output.diagnostics[0];
+        assert_eq!(warning.file.as_deref(), Some("lib.asm"));
+        assert_eq!(warning.location.map(|location| location.line), Some(3));
+        assert!(warning
+            .to_string()
+            .ends_with("(in lib.asm, line 3, column 2)"));
+        let lines = output
+            .listing
+            .iter()
+            .map(|line| line.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [1, 2, 3, 4, 3]);
+        assert_eq!(
+            output.format_source_map(),
+            "x3000  lib.asm:2:7\nx3001  lib.asm:3:2\nx3002  lib.asm:4:2\nx3003  3:3\n"
+        );
+
+        fs::write(dir.join("nested.asm"), "HALT\n.INCLUDE \"missing.asm\"\n")?;
+        let err = assembler
+            .assemble(".ORIG x3000\n.INCLUDE \"nested.asm\"\n.END")
+            .unwrap_err();
+        let diagnostic = err.diagnostic();
+        assert_eq!(diagnostic.file.as_deref(), Some("nested.asm"));
+        assert_eq!(diagnostic.location.unwrap().line, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relax_branches() -> Result<(), Error> {
+        // Relaxing BRz moves B out of range of BRp
+        let source = ".ORIG x3000\nA BRp B\nBRz FAR\n.BLKW 253\nB LD R1, FAR\n.BLKW 300\n\
+                      FAR JSR SUBR\n.BLKW 2100\nSUBR RET\n.END";
+        Assembler::new().assemble(source).unwrap_err();
+        let output = Assembler::new()
+            .relax_branches(true)
+            .scratch_register(5)
+            .assemble(source)?;
+        assert_eq!(output.symbols["B"], 0x3105);
+        assert_eq!(output.symbols["FAR"], 0x3105 + 4 + 300);
+        let words = &output.segments[0].words;
+        assert_eq!(
+            words[..8],
+            [0x0C03, 0x2A01, 0xC140, 0x3105, 0x0A03, 0x2A01, 0xC140, 0x3235]
+        );
+        assert_eq!(words[261..265], [0x2202, 0x6240, 0x0E01, 0x3235]);
+        assert_eq!(words[565..569], [0x2E02, 0x41C0, 0x0E01, 0x3A6D]);
+        let notes = output
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Note)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(notes.len(), 4);
+        assert_eq!(
+            notes[0],
+            "note: Relaxed out-of-range BRp B into 4 words, overwriting R5 (line 2, column 3)"
+        );
+        assert_eq!(
+            notes[2],
+            "note: Relaxed out-of-range LD R1, FAR into 4 words (line 5, column 3)"
+        );
+
+        // Branches overwrite the scratch register, which must be chosen
+        let err = Assembler::new()
+            .relax_branches(true)
+            .assemble(source)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Relaxing out-of-range BRp B overwrites a scratch register"),
+            "{}",
+            err
+        );
+        Assembler::new()
+            .relax_branches(true)
+            .assemble(".ORIG x3000\nLD R1, FAR\n.BLKW 300\nFAR .FILL 1\n.END")?;
+
+        // Offsets which are not labels are never relaxed
+        Assembler::new()
+            .relax_branches(true)
+            .assemble(".ORIG x3000\nBRz #300\n.END")
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing() -> Result<(), Error> {
+        let output = Assembler::new()
+            .scratch_register(5)
+            .assemble(".ORIG x3000\nLOOP ADD R0, R0, #1 ; step\nSUB R0, R0, R1\n.BLKW 2\n.END")?;
+        assert_eq!(output.listing.len(), 4);
+        assert_eq!(output.listing[2].expansion[0], "NOT R5, R1");
+        assert_eq!(
+            output.format_listing(),
+            "                 1  .ORIG x3000\n\
+             x3000  x1021     2  LOOP ADD R0, R0, #1 ; step\n\
+             \x20                3  SUB R0, R0, R1\n\
+             x3001  x9A7F        ; NOT R5, R1\n\
+             x3002  x1B61        ; ADD R5, R5, #1\n\
+             x3003  x1005        ; ADD R0, R0, R5\n\
+             x3004  x0000     4  .BLKW 2\n\
+             x3005  x0000\n"
+        );
+        assert_eq!(
+            output.format_source_map(),
+            "x3000  2:6\nx3001  3:1\nx3002  3:1\nx3003  3:1\nx3004  4:1\nx3005  4:1\n"
+        );
+        Assembler::new()
+            .dialect(Dialect::Lc3as)
+            .assemble(".ORIG x3000\nPUSH R0\n.END")
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_diagnostic() {
+        let err = Assembler::new()
+            .assemble(".ORIG x3000\nADD R0, R0, #99\n.END")
+            .unwrap_err();
+        let diagnostic = err.diagnostic();
+        assert_eq!(diagnostic.severity, Severity::Error);
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (2, 13));
+        assert_eq!(location.end - location.start, 3);
+        assert!(diagnostic.to_string().starts_with("error: "));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() -> Result<(), Error> {
+        let output = Assembler::new().assemble(".ORIG x3000\nBR DONE\nDONE HALT\n.END")?;
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["symbols"]["DONE"], 0x3001);
+        assert_eq!(json["segments"][0]["words"][1], 0xF025);
+        assert_eq!(json["diagnostics"][0]["severity"], "warning");
+        assert_eq!(json["diagnostics"][0]["location"]["line"], 2);
+        assert_eq!(
+            json["image"]["segments"][0]["words"][1]["kind"],
+            "instruction"
+        );
+        assert_eq!(json["listing"][2]["source"], "DONE HALT");
+        let deserialized: AssemblyOutput = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, output);
+
+        let nodes = crate::Node::tree(crate::parse(".ORIG x3000\nHALT\n.END")?);
+        let json = serde_json::to_value(&nodes).unwrap();
+        assert_eq!(json[0]["rule"], "orig");
+        assert_eq!(json[0]["children"][0]["text"], "x3000");
+        Ok(())
+    }
+
+    #[test]
+    fn test_trap_aliases() -> Result<(), Error> {
+        let output = Assembler::new().trap_alias("stop", 0x25).assemble(
+            "\
+.ORIG x3000
+.TRAPDEF PUTNUM, x26
+        PUTNUM
+LOOP    PUTNUM      ; prints R0
+        BRnzp   LOOP
+DONE:   putnum
+        STOP
+.END",
+        )?;
+        assert_eq!(
+            output.segments[0].words,
+            vec![0xF026, 0xF026, 0x0FFE, 0xF026, 0xF025]
+        );
+        assert_eq!(output.symbols.keys().collect::<Vec<_>>(), ["DONE", "LOOP"]);
+        assert_eq!(output.trap_aliases["PUTNUM"], 0x26);
+        assert!(output
+            .symbol_table()?
+            .contains("PUTNUM                  0026"));
+        assert_eq!(output.listing[2].source, "LOOP    PUTNUM      ; prints R0");
+
+        for (source, message) in [
+            (
+                "LOOP HALT\n.TRAPDEF LOOP, x26",
+                "Symbol LOOP is already defined as a label",
+            ),
+            (
+                ".TRAPDEF PUTNUM, x26\nPUTNUM ADD R0, R0, #1",
+                "Symbol PUTNUM is already defined as a trap alias",
+            ),
+            (".TRAPDEF ADD, x26", "reserved word"),
+            (".TRAPDEF BIG, x100", "expected 0..255 for trapvect8"),
+            ("PUTNUM\n.TRAPDEF PUTNUM, x26", "already defined as a label"),
+        ] {
+            let err = Assembler::new()
+                .assemble(format!(".ORIG x3000\n{}\n.END", source))
+                .unwrap_err();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+
+        let options = Assembler::new()
+            .trap_alias("ADD", 0x26)
+            .trap_alias("2X", 0x27);
+        for err in [
+            options.assemble(".ORIG x3000\nHALT\n.END").unwrap_err(),
+            options.builder(0x3000).build().unwrap_err(),
+        ] {
+            match err {
+                Error::TrapAlias(err) => {
+                    assert_eq!(err.name, "ADD");
+                    assert_eq!(
+                        err.message,
+                        "ADD is a reserved word and cannot be used as a label"
+                    );
+                }
+                err => panic!("Unexpected error {}", err),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! Provides [Node], the syntax tree of parsed pairs owning its text.
+//!
+//! Pairs borrow the source code they were parsed from, while nodes may outlive it, e.g. to be
+//! serialized with the `serde` feature.
+use crate::assembler::Location;
+use crate::image::Locator;
+use crate::Rule;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pest::iterators::{Pair, Pairs};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+
+/// Node of the syntax tree, with the [Rule] which produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Node {
+    /// Rule, serialized as its name, e.g. `"add_immd"`
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_rule"))]
+    pub rule: Rule,
+    pub location: Location,
+    /// Source code of the node
+    pub text: String,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Returns the nodes of given pairs, e.g. returned by [parse](crate::parse).
+    pub fn tree(pairs: Pairs<Rule>) -> Vec<Node> {
+        let mut locator = pairs
+            .peek()
+            .map(|pair| Locator::new(pair.as_span().get_input()));
+        match &mut locator {
+            Some(locator) => pairs.map(|pair| Node::new(pair, locator)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Reads pairs in order of their start, as [Locator] requires.
+    fn new(pair: Pair<Rule>, locator: &mut Locator) -> Node {
+        let span = pair.as_span();
+        let location = locator.locate(span.start(), span.end());
+        Node {
+            rule: pair.as_rule(),
+            location,
+            text: pair.as_str().to_owned(),
+            children: pair
+                .into_inner()
+                .map(|pair| Node::new(pair, locator))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_rule<S: Serializer>(rule: &Rule, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", rule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree() {
+        let source = ".ORIG x3000\nLOOP ADD R0, R0, #1\n.END";
+        let nodes = Node::tree(crate::parse(source).unwrap());
+        let add = nodes
+            .iter()
+            .find(|node| node.rule == Rule::instruction)
+            .unwrap();
+        assert_eq!(add.text, "ADD R0, R0, #1");
+        assert_eq!((add.location.line, add.location.column), (2, 6));
+        let operands = &add.children[0].children;
+        assert_eq!(
+            operands.iter().map(|node| node.rule).collect::<Vec<_>>(),
+            [Rule::register, Rule::register, Rule::number]
+        );
+        assert_eq!(operands[2].location.start, 29);
+    }
+}
@@ -1,23 +1,302 @@
+use serde::Serialize;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use structopt::clap::{Error as ClapError, ErrorKind as ClapErrorKind};
 use structopt::StructOpt;
 
+use lc3asm::{Assembler, Diagnostic, Dialect, Node, OutputFormat, Severity, Target};
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "lc3asm", about = "LC-3 assembly assembler")]
 struct Opt {
-    /// Input file
+    /// Input file, `-` to read from standard input
     #[structopt(parse(from_os_str))]
     input: PathBuf,
-    /// Output file, <filename_of_input>.asm if not present
+    /// Output file, `-` to write to standard output.
+    /// <filename_of_input>.obj if not present, standard output if reading from standard input
     #[structopt(parse(from_os_str))]
     output: Option<PathBuf>,
+    /// Output file, same as the positional output argument
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        conflicts_with = "output"
+    )]
+    output_option: Option<PathBuf>,
+    /// Symbol table output file, `-` to write to standard output.
+    /// <filename_of_output>.sym if not present
+    #[structopt(long = "sym", parse(from_os_str))]
+    sym_output: Option<PathBuf>,
+    /// Listing output file, `-` to write to standard output
+    #[structopt(long = "listing", parse(from_os_str))]
+    listing_output: Option<PathBuf>,
+    /// Source map output file, `-` to write to standard output
+    #[structopt(long = "source-map", parse(from_os_str))]
+    source_map_output: Option<PathBuf>,
+    /// C header output file, `-` to write to standard output.
+    /// The array is named after the header, e.g. `prog` for prog.h
+    #[structopt(long = "c-header", parse(from_os_str))]
+    c_header_output: Option<PathBuf>,
+    /// Rust module output file, `-` to write to standard output
+    #[structopt(long = "rust-module", parse(from_os_str))]
+    rust_module_output: Option<PathBuf>,
+    /// Do not write the symbol table
+    #[structopt(long = "no-sym", conflicts_with = "sym_output")]
+    no_sym: bool,
     /// Enable backtrace(RUSTC_BACKTRACE=1). Convenience option for debugging.
     #[structopt(short = "b", long = "backtrace")]
     backtrace: bool,
     /// Show parsed structure before assembling
     #[structopt(short = "s", long = "structure")]
     print_pairs: bool,
+    /// Add a directory searched by .INCLUDE directives
+    #[structopt(
+        short = "I",
+        long = "include",
+        parse(from_os_str),
+        number_of_values = 1
+    )]
+    include_paths: Vec<PathBuf>,
+    /// Predefine a constant, e.g. `-D STACK=xFE00`
+    #[structopt(
+        short = "D",
+        long = "define",
+        parse(try_from_str = "parse_define"),
+        number_of_values = 1
+    )]
+    defines: Vec<(String, i64)>,
+    /// Define a mnemonic for a trap vector, e.g. `--trap PUTNUM=x26`
+    #[structopt(
+        long = "trap",
+        parse(try_from_str = "parse_trap"),
+        number_of_values = 1
+    )]
+    traps: Vec<(String, u8)>,
+    /// Override the address given by .ORIG, e.g. `--origin x4000`
+    #[structopt(long = "origin", parse(try_from_str = "parse_origin"))]
+    origin: Option<u16>,
+    /// Treat label names case-insensitively
+    #[structopt(long = "ignore-case")]
+    ignore_case: bool,
+    /// Allow non-ASCII Unicode letters in labels
+    #[structopt(long = "unicode-labels")]
+    unicode_labels: bool,
+    /// Accepted dialect: lc3asm, lc3as, lc3tools or laser
+    #[structopt(
+        long = "dialect",
+        default_value = "lc3asm",
+        parse(try_from_str = "parse_dialect")
+    )]
+    dialect: Dialect,
+    /// Warn about constructs not accepted by every dialect
+    #[structopt(long = "strict")]
+    strict: bool,
+    /// Output format: obj, hex or bin
+    #[structopt(
+        long = "format",
+        default_value = "obj",
+        parse(try_from_str = "parse_format")
+    )]
+    format: OutputFormat,
+    /// Target architecture: lc3 or lc3b
+    #[structopt(
+        long = "target",
+        default_value = "lc3",
+        parse(try_from_str = "parse_target")
+    )]
+    target: Target,
+    /// Rewrite out-of-range BR, JSR, LD, LDI and LEA into longer sequences
+    #[structopt(long = "relax")]
+    relax: bool,
+    /// Register overwritten by relaxed branches and SUB, e.g. `--scratch R5`.
+    /// Required to relax branches and to use SUB
+    #[structopt(long = "scratch", parse(try_from_str = "parse_register"))]
+    scratch: Option<u8>,
+    /// File of instructions added to the reserved opcode 1101, one definition per line
+    #[structopt(long = "extensions", parse(from_os_str))]
+    extensions: Option<PathBuf>,
+    /// Format of diagnostics: human, or json to write one JSON object per line to standard
+    /// output, ending with a summary
+    #[structopt(
+        long = "message-format",
+        default_value = "human",
+        parse(try_from_str = "parse_message_format")
+    )]
+    message_format: MessageFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageFormat {
+    Human,
+    Json,
+}
+
+/// Line written by `--message-format=json`.
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Message<'a> {
+    /// Diagnostic produced while assembling, or the error aborting it
+    Diagnostic {
+        file: &'a str,
+        #[serde(flatten)]
+        diagnostic: &'a Diagnostic,
+    },
+    /// Syntax tree shown by `--structure`
+    Ast { file: &'a str, nodes: Vec<Node> },
+    /// Last line, counting the diagnostics of each severity
+    Finished {
+        file: &'a str,
+        success: bool,
+        errors: usize,
+        warnings: usize,
+        notes: usize,
+    },
+}
+
+/// Writes diagnostics in the configured [MessageFormat].
+struct Reporter<'a> {
+    format: MessageFormat,
+    file: &'a str,
+}
+
+impl Reporter<'_> {
+    fn emit(&self, message: &Message) {
+        println!("{}", serde_json::to_string(message).unwrap());
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic) {
+        match self.format {
+            MessageFormat::Human => eprintln!("{}: {}", self.file, diagnostic),
+            // Diagnostics of included files are reported with the name of the file
+            MessageFormat::Json => self.emit(&Message::Diagnostic {
+                file: diagnostic.file.as_deref().unwrap_or(self.file),
+                diagnostic: &Diagnostic {
+                    file: None,
+                    ..diagnostic.clone()
+                },
+            }),
+        }
+    }
+
+    /// Reports the error aborting the work described by `context`, e.g. `Cannot assemble`.
+    fn error(&self, context: &str, err: lc3asm::Error) -> lc3asm::Error {
+        match self.format {
+            MessageFormat::Human => eprintln!("{} {}\n{}", context, self.file, err),
+            MessageFormat::Json => {
+                self.diagnostic(&err.diagnostic());
+                self.finish(&[err.diagnostic()]);
+            }
+        }
+        err
+    }
+
+    fn finish(&self, diagnostics: &[Diagnostic]) {
+        if self.format == MessageFormat::Json {
+            let count = |severity| {
+                diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.severity == severity)
+                    .count()
+            };
+            self.emit(&Message::Finished {
+                file: self.file,
+                success: count(Severity::Error) == 0,
+                errors: count(Severity::Error),
+                warnings: count(Severity::Warning),
+                notes: count(Severity::Note),
+            });
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Result<i64, String> {
+    lc3asm::parse_number_literal(s).map_err(|err| format!("{}: {}", s, err))
+}
+
+fn parse_define(s: &str) -> Result<(String, i64), String> {
+    match s.find('=') {
+        Some(idx) => Ok((s[..idx].to_owned(), parse_number(&s[idx + 1..])?)),
+        None => Ok((s.to_owned(), 1)),
+    }
+}
+
+fn parse_trap(s: &str) -> Result<(String, u8), String> {
+    let (name, vector) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VECTOR, found {}", s))?;
+    // The name is checked by Assembler::trap_alias
+    match parse_number(vector)? {
+        vector @ 0..=0xFF => Ok((name.to_owned(), vector as u8)),
+        _ => Err(format!("Trap vector {} is out of range", vector)),
+    }
+}
+
+fn parse_origin(s: &str) -> Result<u16, String> {
+    let origin = parse_number(s)?;
+    if !(0..=0xFFFF).contains(&origin) {
+        return Err(format!("Origin {} is out of the address space", s));
+    }
+    Ok(origin as u16)
+}
+
+fn parse_register(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [r, n @ b'0'..=b'7'] if r.eq_ignore_ascii_case(&b'r') => Ok(n - b'0'),
+        _ => Err(format!("Invalid register {}", s)),
+    }
+}
+
+fn parse_format(s: &str) -> Result<OutputFormat, String> {
+    match s {
+        "obj" => Ok(OutputFormat::Object),
+        "hex" => Ok(OutputFormat::Hex),
+        "bin" => Ok(OutputFormat::Binary),
+        _ => Err(format!("Unknown output format {}", s)),
+    }
+}
+
+fn parse_dialect(s: &str) -> Result<Dialect, String> {
+    match s {
+        "lc3asm" => Ok(Dialect::Lc3asm),
+        "lc3as" => Ok(Dialect::Lc3as),
+        "lc3tools" => Ok(Dialect::Lc3tools),
+        "laser" => Ok(Dialect::Laser),
+        _ => Err(format!("Unknown dialect {}", s)),
+    }
+}
+
+fn parse_message_format(s: &str) -> Result<MessageFormat, String> {
+    match s {
+        "human" => Ok(MessageFormat::Human),
+        "json" => Ok(MessageFormat::Json),
+        _ => Err(format!("Unknown message format {}", s)),
+    }
+}
+
+fn parse_target(s: &str) -> Result<Target, String> {
+    match s {
+        "lc3" => Ok(Target::Lc3),
+        "lc3b" => Ok(Target::Lc3b),
+        _ => Err(format!("Unknown target {}", s)),
+    }
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn write_output(path: &Path, data: &[u8]) -> io::Result<()> {
+    if is_stdio(path) {
+        let stdout = io::stdout();
+        let mut lock = stdout.lock();
+        lock.write_all(data)?;
+        lock.flush()
+    } else {
+        fs::write(path, data)
+    }
 }
 
 fn main() -> Result<(), lc3asm::Error> {
@@ -26,27 +305,168 @@ fn main() -> Result<(), lc3asm::Error> {
         env::set_var("RUST_BACKTRACE", "1");
     }
 
-    let raw_data = &fs::read(&opt.input)?;
-    let input_str = opt.input.clone().into_os_string().into_string().unwrap();
-    let pairs = lc3asm::parse(std::str::from_utf8(raw_data)?).map_err(|err| {
-        eprintln!("Cannot parse {}\n{}", input_str, err);
-        err
-    })?;
+    let (raw_data, input_str) = if is_stdio(&opt.input) {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        (buf, String::from("<stdin>"))
+    } else {
+        (
+            fs::read(&opt.input)?,
+            opt.input.clone().into_os_string().into_string().unwrap(),
+        )
+    };
 
+    let obj_output_path = match opt.output.or(opt.output_option) {
+        Some(path) => path,
+        None if is_stdio(&opt.input) => PathBuf::from("-"),
+        None => {
+            let mut default_out_path = opt.input.clone();
+            default_out_path.set_extension("obj");
+            default_out_path
+        }
+    };
+    let sym_output_path = match opt.sym_output {
+        _ if opt.no_sym => None,
+        Some(path) => Some(path),
+        None if is_stdio(&obj_output_path) => None,
+        None => {
+            let mut default_sym_path = obj_output_path.clone();
+            default_sym_path.set_extension("sym");
+            Some(default_sym_path)
+        }
+    };
+    let stdout_outputs = [
+        ("object", Some(&obj_output_path)),
+        ("symbol table", sym_output_path.as_ref()),
+        ("listing", opt.listing_output.as_ref()),
+        ("source map", opt.source_map_output.as_ref()),
+        ("C header", opt.c_header_output.as_ref()),
+        ("Rust module", opt.rust_module_output.as_ref()),
+    ]
+    .iter()
+    .filter(|(_, path)| path.is_some_and(|path| is_stdio(path)))
+    .map(|(name, _)| *name)
+    .collect::<Vec<_>>();
+    if let [first, second, ..] = stdout_outputs[..] {
+        ClapError::with_description(
+            &format!(
+                "Cannot write both {} and {} to standard output",
+                first, second
+            ),
+            ClapErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.message_format == MessageFormat::Json
+        && std::iter::once(&obj_output_path)
+            .chain(&sym_output_path)
+            .chain(&opt.listing_output)
+            .chain(&opt.source_map_output)
+            .chain(&opt.c_header_output)
+            .chain(&opt.rust_module_output)
+            .any(|path| is_stdio(path))
+    {
+        ClapError::with_description(
+            "Cannot write both JSON messages and outputs to standard output",
+            ClapErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    let reporter = Reporter {
+        format: opt.message_format,
+        file: &input_str,
+    };
+
+    let mut assembler = Assembler::new()
+        .dialect(opt.dialect)
+        .target(opt.target)
+        .strict(opt.strict)
+        .case_sensitive_labels(!opt.ignore_case)
+        .unicode_labels(opt.unicode_labels)
+        .output_format(opt.format)
+        .relax_branches(opt.relax);
+    if let Some(scratch) = opt.scratch {
+        assembler = assembler.scratch_register(scratch);
+    }
+    for path in opt.include_paths {
+        assembler = assembler.include_path(path);
+    }
+    for (name, value) in opt.defines {
+        assembler = assembler.define(name, value);
+    }
+    for (name, vector) in opt.traps {
+        assembler = assembler.trap_alias(name, vector);
+    }
+    if let Some(origin) = opt.origin {
+        assembler = assembler.origin(origin);
+    }
+    if let Some(path) = opt.extensions {
+        let extensions = lc3asm::extension::parse(&fs::read_to_string(&path)?).map_err(|err| {
+            Reporter {
+                file: &path.display().to_string(),
+                ..reporter
+            }
+            .error("Cannot read", err.into())
+        })?;
+        assembler = assembler.extensions(extensions);
+    }
+
+    let base_dir = if is_stdio(&opt.input) {
+        None
+    } else {
+        opt.input.parent()
+    };
+    let source = assembler
+        .preprocess(std::str::from_utf8(&raw_data)?, base_dir)
+        .map_err(|err| reporter.error("Cannot read", err))?;
     if opt.print_pairs {
-        eprintln!("{:#?}", pairs)
+        let pairs = lc3asm::parse(source.text())
+            .map_err(|err| reporter.error("Cannot parse", err.into()))?;
+        match opt.message_format {
+            MessageFormat::Human => eprintln!("{:#?}", pairs),
+            MessageFormat::Json => reporter.emit(&Message::Ast {
+                file: &input_str,
+                nodes: Node::tree(pairs),
+            }),
+        }
     }
 
-    let (assembled, symbol_table) = lc3asm::assemble_from_pairs(pairs.collect()).map_err(|err| {
-        eprintln!("Cannot assemble {}\n{}", input_str, err);
-        err
-    })?;
-    let mut default_out_path = opt.input;
-    default_out_path.set_extension("obj");
-    let obj_output_path = opt.output.unwrap_or(default_out_path);
-    let mut sym_output_path = obj_output_path.clone();
-    sym_output_path.set_extension("sym");
-    fs::write(obj_output_path, assembled)?;
-    fs::write(sym_output_path, symbol_table)?;
+    let output = assembler
+        .assemble_preprocessed(&source)
+        .map_err(|err| reporter.error("Cannot assemble", err))?;
+    for diagnostic in &output.diagnostics {
+        reporter.diagnostic(diagnostic);
+    }
+    write_output(&obj_output_path, &output.to_bytes())?;
+    if let Some(sym_output_path) = sym_output_path {
+        write_output(&sym_output_path, output.symbol_table()?.as_bytes())?;
+    }
+    if let Some(listing_output_path) = opt.listing_output {
+        write_output(&listing_output_path, output.format_listing().as_bytes())?;
+    }
+    if let Some(source_map_output_path) = opt.source_map_output {
+        write_output(
+            &source_map_output_path,
+            output.format_source_map().as_bytes(),
+        )?;
+    }
+    if let Some(c_header_output_path) = opt.c_header_output {
+        let name = [&c_header_output_path, &opt.input]
+            .iter()
+            .filter(|path| !is_stdio(path))
+            .find_map(|path| path.file_stem())
+            .map_or("prog".into(), |stem| stem.to_string_lossy());
+        write_output(
+            &c_header_output_path,
+            output.format_c_header(&name)?.as_bytes(),
+        )?;
+    }
+    if let Some(rust_module_output_path) = opt.rust_module_output {
+        write_output(
+            &rust_module_output_path,
+            output.format_rust_module()?.as_bytes(),
+        )?;
+    }
+    reporter.finish(&output.diagnostics);
     Ok(())
 }
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use structopt::StructOpt;
+
+use lc3asm::{MergeInput, ObjectFile};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "lc3obj", about = "LC-3 object file utilities")]
+enum Opt {
+    /// Show each word of an object file with its address
+    #[structopt(name = "dump")]
+    Dump {
+        /// Object file, of several segments if its extension is .lc3m
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Show words in hexadecimal only, without disassembling them
+        #[structopt(long = "hex")]
+        hex: bool,
+    },
+    /// Show the words which differ between two object files, exiting with 1 if any does
+    #[structopt(name = "diff")]
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+    /// Combine object files and the symbol tables next to them into one memory image
+    #[structopt(name = "merge")]
+    Merge {
+        /// Object files, e.g. the trap vector table, the OS and user programs
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+        /// Output file, e.g. all.lc3m as an object file of several segments, or all.obj with
+        /// --full
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+        /// Symbol table output file. <filename_of_output>.sym if not present
+        #[structopt(long = "sym", parse(from_os_str))]
+        sym_output: Option<PathBuf>,
+        /// Do not write the symbol table
+        #[structopt(long = "no-sym", conflicts_with = "sym_output")]
+        no_sym: bool,
+        /// Write the whole 64K memory as one segment at x0000, instead of an object file of
+        /// several segments
+        #[structopt(long = "full")]
+        full: bool,
+    },
+}
+
+fn read(path: &Path) -> Result<ObjectFile, lc3asm::Error> {
+    ObjectFile::read(path).map_err(|err| {
+        eprintln!("Cannot read {}\n{}", path.display(), err);
+        err
+    })
+}
+
+fn main() -> Result<(), lc3asm::Error> {
+    match Opt::from_args() {
+        Opt::Dump { input, hex } => {
+            let object = read(&input)?;
+            if hex {
+                print!("{}", object.format_hex());
+            } else {
+                print!("{}", object.format_disassembly());
+            }
+        }
+        Opt::Diff { old, new } => {
+            let diff = read(&old)?.diff(&read(&new)?);
+            for word in &diff {
+                println!("{}", word);
+            }
+            if !diff.is_empty() {
+                process::exit(1);
+            }
+        }
+        Opt::Merge {
+            inputs,
+            output,
+            sym_output,
+            no_sym,
+            full,
+        } => {
+            let inputs = inputs
+                .iter()
+                .map(|path| {
+                    MergeInput::read(path).map_err(|err| {
+                        eprintln!("Cannot read {}\n{}", path.display(), err);
+                        err
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let merged = lc3asm::merge(&inputs).map_err(|err| {
+                eprintln!("Cannot merge\n{}", err);
+                err
+            })?;
+            for warning in &merged.warnings {
+                eprintln!("warning: {}", warning);
+            }
+            let object = if full {
+                merged.full_image()
+            } else {
+                merged.object.clone()
+            };
+            object.write(&output).map_err(|err| {
+                eprintln!("Cannot write {}\n{}", output.display(), err);
+                err
+            })?;
+            if !no_sym {
+                let sym_output = sym_output.unwrap_or_else(|| output.with_extension("sym"));
+                fs::write(sym_output, merged.symbol_table()?)?;
+            }
+        }
+    }
+    Ok(())
+}
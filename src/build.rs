@@ -0,0 +1,174 @@
+//! Provides helpers which assemble programs from build scripts.
+//!
+//! ```no_run
+//! // In `main` of build.rs
+//! lc3asm::build::assemble_dir("asm/").unwrap();
+//! ```
+//!
+//! ```ignore
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/lc3asm_programs.rs"));
+//!
+//! // asm/hello.asm becomes module `hello`
+//! let image: &[u16] = hello::IMAGE;
+//! let entry: u16 = hello::symbols::MAIN;
+//! ```
+use crate::assembler::{Assembler, AssemblyOutput};
+use crate::embed::rust_identifier;
+use crate::error::Error;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the generated module file in `OUT_DIR`.
+pub const MODULE_FILE: &str = "lc3asm_programs.rs";
+
+/// Assembles every `.asm` file directly in given directory with default options.
+///
+/// See [assemble_dir_with].
+pub fn assemble_dir(dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+    assemble_dir_with(&Assembler::new(), dir)
+}
+
+/// Assembles every `.asm` file directly in given directory, to be called from a build script.
+///
+/// Each `name.asm` is written to `OUT_DIR/name.obj` and `OUT_DIR/name.sym`, and
+/// `OUT_DIR/lc3asm_programs.rs` declares module `name` with the object image(`IMAGE`, the origin
+/// followed by each word), its `ORIGIN` and a `symbols` module holding the address of each label.
+/// Files in subdirectories are not assembled, so they may be used with `.INCLUDE`.
+///
+/// Cargo is asked to rerun the build script when the directory, a source or an included file
+/// changes. Returns the path of the generated module.
+pub fn assemble_dir_with(assembler: &Assembler, dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+    let out_dir = env::var_os("OUT_DIR").map(PathBuf::from).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "OUT_DIR is not set, assemble_dir must be called from a build script",
+        )
+    })?;
+    let stdout = io::stdout();
+    let mut lock = stdout.lock();
+    assemble_dir_into(assembler, dir.as_ref(), &out_dir, &mut lock)
+}
+
+fn assemble_dir_into(
+    assembler: &Assembler,
+    dir: &Path,
+    out_dir: &Path,
+    cargo: &mut impl Write,
+) -> Result<PathBuf, Error> {
+    writeln!(cargo, "cargo:rerun-if-changed={}", dir.display())?;
+    let mut sources = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    sources.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "asm"));
+    sources.sort();
+
+    let mut module = String::from("// Generated by lc3asm::build, do not edit.\n");
+    for source in sources {
+        writeln!(cargo, "cargo:rerun-if-changed={}", source.display())?;
+        let (text, included) = assembler.preprocess_file(&source)?;
+        for path in included {
+            writeln!(cargo, "cargo:rerun-if-changed={}", path.display())?;
+        }
+        let output = assembler
+            .assemble_preprocessed(&text)
+            .map_err(|err| match err {
+                Error::Pest(err) if err.path().is_none() => {
+                    Error::Pest(err.with_path(&source.display().to_string()))
+                }
+                err => err,
+            })?;
+
+        let stem = source.file_stem().unwrap().to_string_lossy();
+        fs::write(out_dir.join(format!("{}.obj", stem)), output.object_bytes())?;
+        fs::write(
+            out_dir.join(format!("{}.sym", stem)),
+            output.symbol_table()?,
+        )?;
+        write_module(&mut module, &rust_identifier(&stem), &output)?;
+    }
+
+    let path = out_dir.join(MODULE_FILE);
+    fs::write(&path, module)?;
+    Ok(path)
+}
+
+fn write_module(module: &mut String, name: &str, output: &AssemblyOutput) -> Result<(), Error> {
+    let image = output
+        .segments
+        .iter()
+        .flat_map(|segment| std::iter::once(segment.origin).chain(segment.words.iter().cloned()))
+        .map(|word| format!("0x{:04X}", word))
+        .collect::<Vec<_>>();
+    writeln!(module)?;
+    writeln!(module, "#[allow(dead_code)]")?;
+    writeln!(module, "pub mod {} {{", name)?;
+    writeln!(
+        module,
+        "    pub const ORIGIN: u16 = 0x{:04X};",
+        output.segments.first().map_or(0, |segment| segment.origin)
+    )?;
+    writeln!(module, "    pub const IMAGE: &[u16] = &[")?;
+    for line in image.chunks(8) {
+        writeln!(module, "        {},", line.join(", "))?;
+    }
+    writeln!(module, "    ];")?;
+    writeln!(module)?;
+    writeln!(module, "    #[allow(non_upper_case_globals)]")?;
+    writeln!(module, "    pub mod symbols {{")?;
+    for (symbol, address) in &output.symbols {
+        writeln!(
+            module,
+            "        pub const {}: u16 = 0x{:04X};",
+            rust_identifier(symbol),
+            address
+        )?;
+    }
+    writeln!(module, "    }}")?;
+    writeln!(module, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_dir() -> Result<(), Error> {
+        let dir = env::temp_dir().join(format!("lc3asm_test_build_{}", std::process::id()));
+        let out_dir = dir.join("out");
+        fs::create_dir_all(dir.join("lib"))?;
+        fs::create_dir_all(&out_dir)?;
+        fs::write(dir.join("lib").join("print.asm"), "PRINT PUTS\nRET\n")?;
+        fs::write(
+            dir.join("hello-world.asm"),
+            ".ORIG x3000\nloop LEA R0, MSG\nJSR PRINT\nHALT\n.INCLUDE \"lib/print.asm\"\nMSG .STRINGZ \"Hi\"\n.END",
+        )?;
+        fs::write(dir.join("README.md"), "not assembled")?;
+
+        let mut cargo = Vec::new();
+        let path = assemble_dir_into(&Assembler::new(), &dir, &out_dir, &mut cargo)?;
+        let cargo = String::from_utf8(cargo).unwrap();
+        let lines = cargo.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("hello-world.asm"), "{}", cargo);
+        assert!(lines[2].ends_with("print.asm"), "{}", cargo);
+
+        let module = fs::read_to_string(path)?;
+        assert!(module.contains("pub mod hello_world {"), "{}", module);
+        assert!(module.contains("pub const ORIGIN: u16 = 0x3000;"));
+        assert!(module.contains(
+            "0x3000, 0xE004, 0x4801, 0xF025, 0xF022, 0xC1C0, 0x0048, 0x0069,\n        0x0000,\n"
+        ));
+        assert!(module.contains("pub const r#loop: u16 = 0x3000;"));
+        assert!(module.contains("pub const PRINT: u16 = 0x3003;"));
+        assert_eq!(
+            fs::read(out_dir.join("hello-world.obj"))?[..4],
+            [0x30, 0x00, 0xE0, 0x04]
+        );
+        Ok(())
+    }
+}
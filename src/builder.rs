@@ -0,0 +1,649 @@
+//! Provides [ProgramBuilder] which generates programs from Rust code.
+use crate::assembler::{Assembler, AssemblyOutput};
+use crate::error::Error;
+use crate::field;
+use crate::image::{Image, ImageSegment, StatementKind};
+use crate::isa::{self, Format, OperandKind};
+use crate::resolve::{self, misaligned, Symbols, Unresolved};
+use crate::symbol_table::check_label_name;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
+/// Operand of a statement appended to [ProgramBuilder].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    /// Register number, immediate value or explicit PC offset
+    Value(i64),
+    /// Label or constant, resolved when the program is built
+    Symbol(String),
+}
+
+impl From<i32> for Operand {
+    fn from(value: i32) -> Self {
+        Operand::Value(value.into())
+    }
+}
+
+impl From<i64> for Operand {
+    fn from(value: i64) -> Self {
+        Operand::Value(value)
+    }
+}
+
+impl From<u16> for Operand {
+    fn from(value: u16) -> Self {
+        Operand::Value(value.into())
+    }
+}
+
+impl From<&str> for Operand {
+    fn from(name: &str) -> Self {
+        Operand::Symbol(name.to_owned())
+    }
+}
+
+impl From<String> for Operand {
+    fn from(name: String) -> Self {
+        Operand::Symbol(name)
+    }
+}
+
+/// Error returned by [ProgramBuilder::build].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    /// 0-based index of the statement in the order they were appended, labels included, or
+    /// `None` if the options cannot be used to build programs
+    pub statement: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.statement {
+            Some(statement) => write!(f, "Statement {}: {}", statement, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Label(String),
+    Instruction(Format, Vec<Operand>),
+    Fill(Operand),
+    Blkw(u16),
+    Stringz(String),
+}
+
+/// Builds a program statement by statement, as an alternative to assembling source code.
+///
+/// Labels may be referenced before they are declared, and instructions must be of the target of
+/// the options, e.g. [isa::LDW] instead of [isa::LDR] for [Target::Lc3b](crate::Target::Lc3b).
+/// [ProgramBuilder::build] produces the same image and symbols as assembling the equivalent
+/// source code, with the same range checks. Branch relaxation, trap aliases and extensions are
+/// not supported and fail the build:
+///
+/// ```
+/// use lc3asm::ProgramBuilder;
+///
+/// let mut builder = ProgramBuilder::new(0x3000);
+/// builder
+///     .ld(0, "COUNT")
+///     .label("LOOP")
+///     .add_imm(0, 0, -1)
+///     .br("p", "LOOP")
+///     .instruction(lc3asm::isa::HALT, vec![])
+///     .label("COUNT")
+///     .fill(5);
+/// let output = builder.build().unwrap();
+/// assert_eq!(output.segments[0].words, vec![0x2003, 0x103F, 0x03FE, 0xF025, 0x0005]);
+/// assert_eq!(output.symbols.get("LOOP"), Some(&0x3001));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProgramBuilder {
+    options: Assembler,
+    origin: u16,
+    statements: Vec<Statement>,
+}
+
+impl ProgramBuilder {
+    /// Creates a builder for a program starting at given address, with default options.
+    pub fn new(origin: u16) -> Self {
+        Assembler::new().builder(origin)
+    }
+
+    pub(crate) fn with_options(options: Assembler, origin: u16) -> Self {
+        ProgramBuilder {
+            options,
+            origin,
+            statements: Vec::new(),
+        }
+    }
+
+    /// Declares a label at the address of the next statement.
+    pub fn label(&mut self, name: impl Into<String>) -> &mut Self {
+        self.statements.push(Statement::Label(name.into()));
+        self
+    }
+
+    /// Appends an instruction of given format, with operands in the order they are written.
+    pub fn instruction(&mut self, format: Format, operands: Vec<Operand>) -> &mut Self {
+        self.statements
+            .push(Statement::Instruction(format, operands));
+        self
+    }
+
+    /// Appends a `.FILL` pseudo-operation.
+    pub fn fill(&mut self, value: impl Into<Operand>) -> &mut Self {
+        self.statements.push(Statement::Fill(value.into()));
+        self
+    }
+
+    /// Appends a `.BLKW` pseudo-operation.
+    pub fn blkw(&mut self, count: u16) -> &mut Self {
+        self.statements.push(Statement::Blkw(count));
+        self
+    }
+
+    /// Appends a `.STRINGZ` pseudo-operation. Each byte of the string occupies a word.
+    pub fn stringz(&mut self, string: impl Into<String>) -> &mut Self {
+        self.statements.push(Statement::Stringz(string.into()));
+        self
+    }
+
+    pub fn add(&mut self, dr: u8, sr1: u8, sr2: u8) -> &mut Self {
+        self.instruction(isa::ADD, vec![register(dr), register(sr1), register(sr2)])
+    }
+
+    pub fn add_imm(&mut self, dr: u8, sr1: u8, imm5: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::ADD_IMM, vec![register(dr), register(sr1), imm5.into()])
+    }
+
+    pub fn and(&mut self, dr: u8, sr1: u8, sr2: u8) -> &mut Self {
+        self.instruction(isa::AND, vec![register(dr), register(sr1), register(sr2)])
+    }
+
+    pub fn and_imm(&mut self, dr: u8, sr1: u8, imm5: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::AND_IMM, vec![register(dr), register(sr1), imm5.into()])
+    }
+
+    pub fn not(&mut self, dr: u8, sr: u8) -> &mut Self {
+        self.instruction(isa::NOT, vec![register(dr), register(sr)])
+    }
+
+    /// Appends a branch taken on given condition codes, e.g. `"nz"`.
+    /// An empty condition is unconditional like `BR`, without the warning.
+    ///
+    /// Panics if the condition contains characters other than `n`, `z` and `p`.
+    pub fn br(&mut self, condition: &str, target: impl Into<Operand>) -> &mut Self {
+        let condition = condition
+            .chars()
+            .map(|flag| match flag {
+                'n' => 0b100,
+                'z' => 0b010,
+                'p' => 0b001,
+                _ => panic!("Invalid condition code {}", flag),
+            })
+            .fold(0, |condition, flag| condition | flag);
+        let condition = if condition == 0 { 0b111 } else { condition };
+        self.instruction(isa::BR, vec![Operand::Value(condition), target.into()])
+    }
+
+    pub fn jmp(&mut self, base_r: u8) -> &mut Self {
+        self.instruction(isa::JMP, vec![register(base_r)])
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.instruction(isa::RET, vec![])
+    }
+
+    pub fn jsr(&mut self, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::JSR, vec![target.into()])
+    }
+
+    pub fn jsrr(&mut self, base_r: u8) -> &mut Self {
+        self.instruction(isa::JSRR, vec![register(base_r)])
+    }
+
+    pub fn ld(&mut self, dr: u8, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::LD, vec![register(dr), target.into()])
+    }
+
+    pub fn ldi(&mut self, dr: u8, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::LDI, vec![register(dr), target.into()])
+    }
+
+    pub fn ldr(&mut self, dr: u8, base_r: u8, offset6: impl Into<Operand>) -> &mut Self {
+        self.instruction(
+            isa::LDR,
+            vec![register(dr), register(base_r), offset6.into()],
+        )
+    }
+
+    pub fn lea(&mut self, dr: u8, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::LEA, vec![register(dr), target.into()])
+    }
+
+    pub fn st(&mut self, sr: u8, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::ST, vec![register(sr), target.into()])
+    }
+
+    pub fn sti(&mut self, sr: u8, target: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::STI, vec![register(sr), target.into()])
+    }
+
+    pub fn str(&mut self, sr: u8, base_r: u8, offset6: impl Into<Operand>) -> &mut Self {
+        self.instruction(
+            isa::STR,
+            vec![register(sr), register(base_r), offset6.into()],
+        )
+    }
+
+    pub fn rti(&mut self) -> &mut Self {
+        self.instruction(isa::RTI, vec![])
+    }
+
+    /// Appends a `TRAP` instruction. Trap aliases like `HALT` are appended with
+    /// [ProgramBuilder::instruction], e.g. `instruction(isa::HALT, vec![])`.
+    pub fn trap(&mut self, trapvect8: impl Into<Operand>) -> &mut Self {
+        self.instruction(isa::TRAP, vec![trapvect8.into()])
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.instruction(isa::NOP, vec![])
+    }
+
+    /// Resolves labels and encodes every statement.
+    pub fn build(&self) -> Result<AssemblyOutput, Error> {
+        self.options.check()?;
+        let unsupported = if self.options.relax_branches {
+            Some("Branch relaxation")
+        } else if !self.options.trap_aliases.is_empty() {
+            Some("Trap aliases")
+        } else if !self.options.extensions.is_empty() {
+            Some("Extensions")
+        } else {
+            None
+        };
+        if let Some(option) = unsupported {
+            return Err(BuildError {
+                statement: None,
+                message: format!("{} cannot be used with ProgramBuilder", option),
+            }
+            .into());
+        }
+        let target = self.options.target;
+        let origin = self.options.origin.unwrap_or(self.origin);
+        if !usize::from(origin).is_multiple_of(target.word_size()) {
+            return Err(BuildError {
+                statement: None,
+                message: format!("Origin of {} program must be aligned to a word", target),
+            }
+            .into());
+        }
+        let mut symbols = Symbols::new(&self.options);
+        symbols.origin = usize::from(origin);
+        let mut offset = 0;
+        for (index, statement) in self.statements.iter().enumerate() {
+            let error = |message: String| BuildError {
+                statement: Some(index),
+                message,
+            };
+            match statement {
+                Statement::Label(name) => {
+                    check_label_name(name, &self.options).map_err(error)?;
+                    let key = self.options.symbol_key(name);
+                    if symbols.constants.contains_key(&key) {
+                        return Err(error(format!(
+                            "Symbol {} is already defined as a constant",
+                            name
+                        ))
+                        .into());
+                    }
+                    if symbols
+                        .labels
+                        .insert(key, (offset, name.as_str()))
+                        .is_some()
+                    {
+                        return Err(error(format!("Duplicate symbol definition {}", name)).into());
+                    }
+                }
+                Statement::Instruction(..) | Statement::Fill(_) => offset += 1,
+                Statement::Blkw(count) => offset += usize::from(*count),
+                Statement::Stringz(string) => offset += resolve::string_size(string.len(), target),
+            }
+        }
+
+        let mut segment = ImageSegment {
+            origin,
+            location: None,
+            words: Vec::with_capacity(offset),
+        };
+        for (index, statement) in self.statements.iter().enumerate() {
+            let error = |message: String| BuildError {
+                statement: Some(index),
+                message,
+            };
+            match statement {
+                Statement::Label(_) => (),
+                Statement::Instruction(format, operands) => {
+                    if !target
+                        .formats(&format.mnemonic)
+                        .any(|known| known == format)
+                    {
+                        return Err(error(format!(
+                            "{} is not an instruction of {}",
+                            format.mnemonic, target
+                        ))
+                        .into());
+                    }
+                    if operands.len() != format.operands().count() {
+                        return Err(error(format!(
+                            "Expected {} operands for {}",
+                            format.operands().count(),
+                            format
+                        ))
+                        .into());
+                    }
+                    let values = format
+                        .operands()
+                        .zip(operands)
+                        .map(|((kind, _), operand)| {
+                            resolve_operand(&symbols, kind, operand, segment.words.len())
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(error)?;
+                    let word = format.encode(&values).map_err(|e| error(e.to_string()))?;
+                    segment.push(word, StatementKind::Instruction, None);
+                }
+                Statement::Fill(operand) => {
+                    let value = resolve_operand(
+                        &symbols,
+                        OperandKind::Immediate,
+                        operand,
+                        segment.words.len(),
+                    )
+                    .map_err(error)?;
+                    let word = field::FILL
+                        .encode(value)
+                        .map_err(|e| error(e.to_string()))?;
+                    segment.push(word, StatementKind::Fill, None);
+                }
+                Statement::Blkw(count) => {
+                    for _ in 0..*count {
+                        segment.push(0, StatementKind::Blkw, None);
+                    }
+                }
+                Statement::Stringz(string) => {
+                    for word in resolve::string_words(string.as_bytes(), target) {
+                        segment.push(word, StatementKind::Stringz, None);
+                    }
+                }
+            }
+        }
+
+        let image = Image {
+            segments: vec![segment],
+            target,
+        };
+        Ok(AssemblyOutput {
+            segments: image.segments(),
+            symbols: symbols.absolute(),
+            diagnostics: Vec::new(),
+            listing: Vec::new(),
+            image,
+            trap_aliases: BTreeMap::new(),
+            files: Vec::new(),
+            format: self.options.output_format,
+            target,
+        })
+    }
+}
+
+fn register(register: u8) -> Operand {
+    Operand::Value(register.into())
+}
+
+/// Resolves an operand of the statement at given offset, the same way the assembler resolves
+/// operand pairs.
+fn resolve_operand(
+    symbols: &Symbols<&str>,
+    kind: OperandKind,
+    operand: &Operand,
+    offset: usize,
+) -> Result<i64, String> {
+    let name = match operand {
+        Operand::Value(value) => return Ok(*value),
+        Operand::Symbol(name) => name,
+    };
+    let missing = || format!("Cannot find symbol {}", name);
+    match kind {
+        OperandKind::PcOffset => symbols.pc_offset(name, offset).map_err(|err| match err {
+            Unresolved::Missing => missing(),
+            Unresolved::Misaligned(address) => misaligned(name, address),
+        }),
+        OperandKind::Register | OperandKind::Condition if symbols.label(name).is_some() => {
+            Err(format!("Expected a value instead of symbol {}", name))
+        }
+        _ => symbols.value(name).ok_or_else(missing),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::Target;
+
+    #[test]
+    fn test_same_output_as_assembler() -> Result<(), Error> {
+        let source = r#".ORIG x3000
+        LEA R0, MESSAGE
+        PUTS
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        LDR R2, R6, #-3
+        STR R2, R6, #4
+        AND R3, R3, R2
+        NOT R4, R3
+        JSR SUBR
+        HALT
+SUBR    RET
+COUNT   .FILL #10
+ADDRESS .FILL LOOP
+BUFFER  .BLKW #3
+MESSAGE .STRINGZ "Hi"
+.END"#;
+        let mut builder = ProgramBuilder::new(0x3000);
+        builder
+            .lea(0, "MESSAGE")
+            .instruction(isa::PUTS, vec![])
+            .ld(1, "COUNT")
+            .label("LOOP")
+            .add_imm(1, 1, -1)
+            .br("p", "LOOP")
+            .ldr(2, 6, -3)
+            .str(2, 6, 4)
+            .and(3, 3, 2)
+            .not(4, 3)
+            .jsr("SUBR")
+            .instruction(isa::HALT, vec![])
+            .label("SUBR")
+            .ret()
+            .label("COUNT")
+            .fill(10)
+            .label("ADDRESS")
+            .fill("LOOP")
+            .label("BUFFER")
+            .blkw(3)
+            .label("MESSAGE")
+            .stringz("Hi");
+        let built = builder.build()?;
+        let (object, symbols) = crate::assemble(source)?;
+        assert_eq!(built.object_bytes(), object);
+        assert_eq!(built.symbol_table()?.into_bytes(), symbols);
+        Ok(())
+    }
+
+    #[test]
+    fn test_options() -> Result<(), Error> {
+        let mut builder = Assembler::new()
+            .case_sensitive_labels(false)
+            .define("STEP", 2)
+            .origin(0x4000)
+            .builder(0x3000);
+        builder.label("loop").add_imm(0, 0, "step").br("", "LOOP");
+        let output = builder.build()?;
+        assert_eq!(output.segments[0].origin, 0x4000);
+        assert_eq!(output.segments[0].words, vec![0x1022, 0x0FFE]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lc3b() -> Result<(), Error> {
+        let source = r#".ORIG x3000
+        LEA R0, MESSAGE
+        LDW R1, R0, #1
+        STI R1, R0, #2
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        LSHF R2, R1, #3
+        JSR SUBR
+        HALT
+SUBR    RET
+ADDRESS .FILL LOOP
+MESSAGE .STRINGZ "Hi!"
+.END"#;
+        let options = Assembler::new().target(Target::Lc3b);
+        let mut builder = options.builder(0x3000);
+        builder
+            .lea(0, "MESSAGE")
+            .ldr(1, 0, 1)
+            .instruction(isa::STI_BASE, vec![register(1), register(0), 2.into()])
+            .label("LOOP")
+            .add_imm(1, 1, -1)
+            .br("p", "LOOP")
+            .instruction(isa::LSHF, vec![register(2), register(1), 3.into()])
+            .jsr("SUBR")
+            .instruction(isa::HALT, vec![])
+            .label("SUBR")
+            .ret()
+            .label("ADDRESS")
+            .fill("LOOP")
+            .label("MESSAGE")
+            .stringz("Hi!");
+        assert_eq!(
+            builder.build().unwrap_err().to_string(),
+            "Statement 1: LDR is not an instruction of LC-3b"
+        );
+
+        let mut builder = options.builder(0x3000);
+        builder
+            .lea(0, "MESSAGE")
+            .instruction(isa::LDW, vec![register(1), register(0), 1.into()])
+            .instruction(isa::STI_BASE, vec![register(1), register(0), 2.into()])
+            .label("LOOP")
+            .add_imm(1, 1, -1)
+            .br("p", "LOOP")
+            .instruction(isa::LSHF, vec![register(2), register(1), 3.into()])
+            .jsr("SUBR")
+            .instruction(isa::HALT, vec![])
+            .label("SUBR")
+            .ret()
+            .label("ADDRESS")
+            .fill("LOOP")
+            .label("MESSAGE")
+            .stringz("Hi!");
+        let built = builder.build()?;
+        let assembled = options.assemble(source)?;
+        assert_eq!(built.segments, assembled.segments);
+        assert_eq!(built.symbols, assembled.symbols);
+        assert_eq!(built.symbols["MESSAGE"], 0x3014);
+        assert_eq!(built.target, Target::Lc3b);
+        assert_eq!(
+            options
+                .builder(0x3001)
+                .nop()
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "Origin of LC-3b program must be aligned to a word"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_options() {
+        for (options, message) in [
+            (
+                Assembler::new().relax_branches(true),
+                "Branch relaxation cannot be used with ProgramBuilder",
+            ),
+            (
+                Assembler::new().trap_alias("PUTNUM", 0x26),
+                "Trap aliases cannot be used with ProgramBuilder",
+            ),
+        ] {
+            let err = options.builder(0x3000).nop().build().unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases: Vec<(ProgramBuilder, &str)> = vec![
+            (
+                ProgramBuilder::new(0x3000).add_imm(0, 0, 16).clone(),
+                "Statement 0: Value 16 is out of range: expected -16..15 for imm5",
+            ),
+            (
+                ProgramBuilder::new(0x3000).add(0, 8, 0).clone(),
+                "Statement 0: Value 8 is out of range: expected 0..7 for SR1",
+            ),
+            (
+                ProgramBuilder::new(0x3000).nop().br("z", "MISSING").clone(),
+                "Statement 1: Cannot find symbol MISSING",
+            ),
+            (
+                ProgramBuilder::new(0x3000)
+                    .label("A")
+                    .nop()
+                    .label("A")
+                    .clone(),
+                "Statement 2: Duplicate symbol definition A",
+            ),
+            (
+                ProgramBuilder::new(0x3000).label("ADD").clone(),
+                "Statement 0: ADD is a reserved word and cannot be used as a label",
+            ),
+            (
+                ProgramBuilder::new(0x3000).label("1st").clone(),
+                "Statement 0: 1st is not a valid label",
+            ),
+            (
+                ProgramBuilder::new(0x3000)
+                    .ld(0, "FAR")
+                    .blkw(300)
+                    .label("FAR")
+                    .clone(),
+                "Statement 0: Value 300 is out of range: expected -256..255 for PCoffset9",
+            ),
+            (
+                ProgramBuilder::new(0x3000)
+                    .instruction(isa::NOT, vec![Operand::Value(0)])
+                    .clone(),
+                "Statement 0: Expected 2 operands for NOT DR, SR",
+            ),
+        ];
+        for (builder, message) in cases {
+            assert_eq!(builder.build().unwrap_err().to_string(), message);
+        }
+    }
+}
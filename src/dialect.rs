@@ -0,0 +1,295 @@
+//! Provides [Dialect] compatibility modes and the non-portable [Construct]s they accept.
+use crate::Rule;
+use core::fmt;
+use pest::iterators::Pair;
+
+/// Assembly language dialect accepted by the assembler.
+///
+/// Every dialect shares the syntax described by the textbook, and differs in which
+/// non-portable [Construct]s it accepts:
+///
+/// | Construct                       | `Lc3asm` | `Lc3as` | `Lc3tools` | `Laser` |
+/// |---------------------------------|:--------:|:-------:|:----------:|:-------:|
+/// | [Construct::ColonLabel]         | yes      |         | yes        | yes     |
+/// | [Construct::External]           | yes      | yes     |            |         |
+/// | [Construct::BareDecimal]        | yes      | yes     | yes        |         |
+/// | [Construct::ZeroXHex]           | yes      |         | yes        | yes     |
+/// | [Construct::LowercaseDirective] | yes      | yes     | yes        |         |
+/// | [Construct::TextAfterEnd]       | yes      | yes     |            |         |
+/// | [Construct::BinaryLiteral]      | yes      |         |            |         |
+/// | [Construct::OctalLiteral]       | yes      |         |            |         |
+/// | [Construct::CharacterLiteral]   | yes      |         |            |         |
+/// | [Construct::DigitSeparator]     | yes      |         |            |         |
+/// | [Construct::PseudoInstruction]  | yes      |         |            |         |
+/// | [Construct::TrapDef]            | yes      |         |            |         |
+/// | [Construct::TrapAlias]          | yes      |         |            |         |
+/// | [Construct::Extension]          | yes      |         |            |         |
+/// | [Construct::Include]            | yes      |         |            |         |
+///
+/// With [Assembler::strict](crate::Assembler::strict), every construct produces a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// Syntax accepted by this crate, a superset of every other dialect.
+    #[default]
+    Lc3asm,
+    /// `lc3as` distributed with the textbook.
+    Lc3as,
+    /// `lc3tools` assembler.
+    Lc3tools,
+    /// Laser LC-3 assembler.
+    Laser,
+}
+
+impl Dialect {
+    /// Returns whether the dialect accepts given construct.
+    pub fn accepts(self, construct: Construct) -> bool {
+        use Construct::*;
+        match self {
+            Dialect::Lc3asm => true,
+            Dialect::Lc3as => matches!(
+                construct,
+                External | BareDecimal | LowercaseDirective | TextAfterEnd
+            ),
+            Dialect::Lc3tools => matches!(
+                construct,
+                ColonLabel | BareDecimal | ZeroXHex | LowercaseDirective
+            ),
+            Dialect::Laser => matches!(construct, ColonLabel | ZeroXHex),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::Lc3asm => "lc3asm",
+            Dialect::Lc3as => "lc3as",
+            Dialect::Lc3tools => "lc3tools",
+            Dialect::Laser => "laser",
+        })
+    }
+}
+
+/// Syntax construct which is not accepted by every [Dialect].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+    /// Label declaration followed by a colon, e.g. `LOOP:`
+    ColonLabel,
+    /// `.EXTERNAL` pseudo-operation
+    External,
+    /// Decimal literal without `#`, e.g. `ADD R0, R0, 1`
+    BareDecimal,
+    /// Hexadecimal literal with `0x` prefix, e.g. `0x3000`
+    ZeroXHex,
+    /// Pseudo-operation not written in uppercase, e.g. `.orig`
+    LowercaseDirective,
+    /// Text following `.END`
+    TextAfterEnd,
+    /// Binary literal, e.g. `b0101`
+    BinaryLiteral,
+    /// Octal literal, e.g. `o17`
+    OctalLiteral,
+    /// Character literal, e.g. `'A'`
+    CharacterLiteral,
+    /// Underscore between digits, e.g. `x30_00`
+    DigitSeparator,
+    /// Pseudo-instruction expanding into several instructions, e.g. `PUSH R0`
+    PseudoInstruction,
+    /// `.TRAPDEF` pseudo-operation
+    TrapDef,
+    /// Trap alias defined with `.TRAPDEF` or [Assembler::trap_alias], e.g. `PUTNUM`
+    ///
+    /// [Assembler::trap_alias]: crate::Assembler::trap_alias
+    TrapAlias,
+    /// Instruction added with [Assembler::extension], e.g. `MUL R0, R1, R2`
+    ///
+    /// [Assembler::extension]: crate::Assembler::extension
+    Extension,
+    /// `.INCLUDE` pseudo-operation
+    Include,
+}
+
+impl Construct {
+    /// Returns the construct used by given pair, if any.
+    pub(crate) fn of(pair: &Pair<Rule>) -> Option<Construct> {
+        let s = pair.as_str();
+        match pair.as_rule() {
+            Rule::label_decl if s.ends_with(':') => Some(Construct::ColonLabel),
+            Rule::external => Some(Construct::External),
+            Rule::number => Construct::of_literal(s),
+            Rule::trapdef => Some(Construct::TrapDef),
+            Rule::orig | Rule::end | Rule::blkw | Rule::fill | Rule::stringz => {
+                let directive = s.split_whitespace().next().unwrap_or(s);
+                if directive.chars().any(|c| c.is_ascii_lowercase()) {
+                    Some(Construct::LowercaseDirective)
+                } else {
+                    None
+                }
+            }
+            Rule::text_after_end => Some(Construct::TextAfterEnd),
+            Rule::pseudo_instruction => Some(Construct::PseudoInstruction),
+            _ => None,
+        }
+    }
+
+    /// Returns the construct used by given number literal, if any.
+    fn of_literal(s: &str) -> Option<Construct> {
+        if s.starts_with('\'') {
+            return Some(Construct::CharacterLiteral);
+        }
+        if s.contains('_') {
+            return Some(Construct::DigitSeparator);
+        }
+        let unprefixed = s.strip_prefix('#').unwrap_or(s);
+        match unprefixed.as_bytes() {
+            [b'0', b'x', ..] | [b'0', b'X', ..] => Some(Construct::ZeroXHex),
+            [b'x', ..] | [b'X', ..] => None,
+            [b'0', b'b', ..] | [b'0', b'B', ..] | [b'b', ..] | [b'B', ..] => {
+                Some(Construct::BinaryLiteral)
+            }
+            [b'0', b'o', ..] | [b'0', b'O', ..] | [b'o', ..] | [b'O', ..] => {
+                Some(Construct::OctalLiteral)
+            }
+            _ if s.starts_with('#') => None,
+            _ => Some(Construct::BareDecimal),
+        }
+    }
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Construct::ColonLabel => "Colon-terminated label",
+            Construct::External => ".EXTERNAL pseudo-operation",
+            Construct::BareDecimal => "Decimal literal without #",
+            Construct::ZeroXHex => "Hexadecimal literal with 0x prefix",
+            Construct::LowercaseDirective => "Lowercase pseudo-operation",
+            Construct::TextAfterEnd => "Text after .END",
+            Construct::BinaryLiteral => "Binary literal",
+            Construct::OctalLiteral => "Octal literal",
+            Construct::CharacterLiteral => "Character literal",
+            Construct::DigitSeparator => "Digit separator",
+            Construct::PseudoInstruction => "Pseudo-instruction",
+            Construct::TrapDef => ".TRAPDEF pseudo-operation",
+            Construct::TrapAlias => "Trap alias",
+            Construct::Extension => "Extension instruction",
+            Construct::Include => ".INCLUDE pseudo-operation",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+
+    fn assemble(dialect: Dialect, source: &str) -> Result<Vec<u16>, Error> {
+        let output = Assembler::new().dialect(dialect).assemble(source)?;
+        Ok(output.segments[0].words.clone())
+    }
+
+    #[test]
+    fn test_colon_label() -> Result<(), Error> {
+        let source = ".ORIG x3000\nLOOP: BRnzp LOOP\n.END";
+        assert_eq!(assemble(Dialect::Lc3tools, source)?, vec![0x0FFF]);
+        assemble(Dialect::Lc3as, source).unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_literals() -> Result<(), Error> {
+        let source = ".ORIG 0x3000\n.FILL 0x1F\n.FILL 10\n.END";
+        assert_eq!(assemble(Dialect::Lc3tools, source)?, vec![0x1F, 10]);
+        assemble(Dialect::Lc3as, source).unwrap_err();
+        assemble(Dialect::Laser, source).unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_after_end() -> Result<(), Error> {
+        let source = ".ORIG x3000\nHALT\n.END\nleftover notes";
+        assert_eq!(assemble(Dialect::Lc3as, source)?, vec![0xF025]);
+        assemble(Dialect::Lc3tools, source).unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_lowercase_directive() {
+        let source = ".orig x3000\nHALT\n.END";
+        assemble(Dialect::Lc3tools, source).unwrap();
+        assemble(Dialect::Laser, source).unwrap_err();
+    }
+
+    #[test]
+    fn test_external() {
+        let source = ".ORIG x3000\n.EXTERNAL PRINT\nJSR PRINT\n.END";
+        let err = assemble(Dialect::Lc3as, source).unwrap_err();
+        assert!(err.to_string().contains("external symbol PRINT"));
+        assemble(Dialect::Lc3tools, ".ORIG x3000\n.EXTERNAL PRINT\n.END").unwrap_err();
+    }
+
+    #[test]
+    fn test_strict() -> Result<(), Error> {
+        let source = ".orig x3000\nA: ADD R0, R0, 1\n.FILL #1\n.END";
+        let output = Assembler::new().strict(true).assemble(source)?;
+        let messages = output
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "Lowercase pseudo-operation is not portable",
+                "Colon-terminated label is not portable",
+                "Decimal literal without # is not portable",
+            ]
+        );
+        assert!(Assembler::new().assemble(source)?.diagnostics.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_literal_constructs() -> Result<(), Error> {
+        let source = ".ORIG x3000\n.FILL b01\n.FILL o7\n.FILL 'A'\n.FILL x30_00\n.END";
+        assemble(Dialect::Lc3tools, source).unwrap_err();
+        let output = Assembler::new().strict(true).assemble(source)?;
+        assert_eq!(output.diagnostics.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_assembler_constructs() -> Result<(), Error> {
+        let mul = "MUL DR:reg SR1:reg 000 SR2:reg".parse().unwrap();
+        let options = Assembler::new().trap_alias("PUTNUM", 0x26).extension(mul);
+        for (source, message) in [
+            (
+                ".ORIG x3000\n.INCLUDE <std/io>\n.END",
+                ".INCLUDE pseudo-operation",
+            ),
+            (".ORIG x3000\nPUTNUM\n.END", "Trap alias"),
+            (".ORIG x3000\nLOOP PUTNUM\n.END", "Trap alias"),
+            (".ORIG x3000\nMUL R0, R1, R2\n.END", "Extension instruction"),
+        ] {
+            for dialect in [Dialect::Lc3as, Dialect::Lc3tools, Dialect::Laser] {
+                let err = options
+                    .clone()
+                    .dialect(dialect)
+                    .assemble(source)
+                    .unwrap_err();
+                let expected = format!("{} is not accepted by {} dialect", message, dialect);
+                assert!(err.to_string().contains(&expected), "{}", err);
+            }
+            let output = options.clone().strict(true).assemble(source)?;
+            let expected = format!("{} is not portable", message);
+            let warning = output
+                .diagnostics
+                .iter()
+                .find(|diagnostic| diagnostic.message == expected)
+                .unwrap();
+            assert_eq!(warning.location.unwrap().line, 2);
+            assert!(options.assemble(source)?.diagnostics.is_empty());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Provides writers embedding assembled programs in C and Rust source code, see
+//! [AssemblyOutput::format_c_header] and [AssemblyOutput::format_rust_module].
+use crate::assembler::AssemblyOutput;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+    "abstract", "become", "box", "do", "final", "gen", "macro", "override", "priv", "try",
+    "typeof", "unsized", "virtual", "yield",
+];
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+/// Words per line of generated arrays.
+const WORDS_PER_LINE: usize = 8;
+
+/// Returns the C header declaring the words of given output as array `name`.
+pub(crate) fn c_header(output: &AssemblyOutput, name: &str) -> Result<String, core::fmt::Error> {
+    let name = c_identifier(name);
+    let prefix = name.to_uppercase();
+    let words = words(output);
+    let mut header = String::new();
+    writeln!(header, "/* Generated by lc3asm, do not edit. */")?;
+    writeln!(header, "#ifndef {}_H", prefix)?;
+    writeln!(header, "#define {}_H", prefix)?;
+    writeln!(header)?;
+    writeln!(header, "#include <stdint.h>")?;
+    writeln!(header)?;
+    writeln!(header, "#define {}_ORIGIN 0x{:04X}", prefix, origin(output))?;
+    writeln!(header, "#define {}_SIZE {}", prefix, words.len())?;
+    for (symbol, address) in &output.symbols {
+        writeln!(
+            header,
+            "#define {}_{} 0x{:04X}",
+            prefix,
+            c_identifier(symbol),
+            address
+        )?;
+    }
+    writeln!(header)?;
+    writeln!(header, "static const uint16_t {}[] = {{", name)?;
+    for line in words.chunks(WORDS_PER_LINE) {
+        writeln!(header, "    {},", line.join(", "))?;
+    }
+    writeln!(header, "}};")?;
+    writeln!(header)?;
+    writeln!(header, "#endif")?;
+    Ok(header)
+}
+
+/// Returns the Rust module declaring the words and symbols of given output.
+pub(crate) fn rust_module(output: &AssemblyOutput) -> Result<String, core::fmt::Error> {
+    let words = words(output);
+    let mut module = String::new();
+    writeln!(module, "// Generated by lc3asm, do not edit.")?;
+    writeln!(module, "pub const ORIGIN: u16 = 0x{:04X};", origin(output))?;
+    writeln!(module, "pub const WORDS: [u16; {}] = [", words.len())?;
+    for line in words.chunks(WORDS_PER_LINE) {
+        writeln!(module, "    {},", line.join(", "))?;
+    }
+    writeln!(module, "];")?;
+    writeln!(module)?;
+    writeln!(module, "#[allow(non_upper_case_globals)]")?;
+    writeln!(module, "pub mod symbols {{")?;
+    for (symbol, address) in &output.symbols {
+        writeln!(
+            module,
+            "    pub const {}: u16 = 0x{:04X};",
+            rust_identifier(symbol),
+            address
+        )?;
+    }
+    writeln!(module, "}}")?;
+    Ok(module)
+}
+
+/// Returns the origin of the first segment, as object files do.
+fn origin(output: &AssemblyOutput) -> u16 {
+    output.segments.first().map_or(0, |segment| segment.origin)
+}
+
+/// Returns the words of every segment in hexadecimal, as object files hold them after the origin.
+fn words(output: &AssemblyOutput) -> Vec<String> {
+    output
+        .segments
+        .iter()
+        .flat_map(|segment| &segment.words)
+        .map(|word| format!("0x{:04X}", word))
+        .collect()
+}
+
+/// Replaces characters which may not appear in identifiers with `_`, and prefixes names
+/// starting with a digit.
+fn sanitize(name: &str, valid: impl Fn(char) -> bool) -> String {
+    let mut identifier = name
+        .chars()
+        .map(|c| if valid(c) { c } else { '_' })
+        .collect::<String>();
+    if identifier.chars().next().is_none_or(|c| c.is_numeric()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+/// Converts given name into a Rust identifier.
+pub(crate) fn rust_identifier(name: &str) -> String {
+    let identifier = sanitize(name, char::is_alphanumeric);
+    match identifier.as_str() {
+        "self" | "Self" | "super" | "crate" | "_" => identifier + "_",
+        keyword if RUST_KEYWORDS.contains(&keyword) => format!("r#{}", identifier),
+        _ => identifier,
+    }
+}
+
+/// Converts given name into a C identifier, which is ASCII.
+fn c_identifier(name: &str) -> String {
+    let identifier = sanitize(name, |c| c.is_ascii_alphanumeric());
+    if C_KEYWORDS.contains(&identifier.as_str()) {
+        identifier + "_"
+    } else {
+        identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+
+    const PROGRAM: &str =
+        ".ORIG x3000\nloop LEA R0, MSG\nPUTS\nBRnzp loop\nMSG .STRINGZ \"Hi\"\n.END";
+
+    #[test]
+    fn test_c_header() -> Result<(), Error> {
+        let output = Assembler::new().assemble(PROGRAM)?;
+        assert_eq!(
+            output.format_c_header("hello-world")?,
+            "/* Generated by lc3asm, do not edit. */\n\
+             #ifndef HELLO_WORLD_H\n\
+             #define HELLO_WORLD_H\n\
+             \n\
+             #include <stdint.h>\n\
+             \n\
+             #define HELLO_WORLD_ORIGIN 0x3000\n\
+             #define HELLO_WORLD_SIZE 6\n\
+             #define HELLO_WORLD_MSG 0x3003\n\
+             #define HELLO_WORLD_loop 0x3000\n\
+             \n\
+             static const uint16_t hello_world[] = {\n\
+             \x20   0xE002, 0xF022, 0x0FFD, 0x0048, 0x0069, 0x0000,\n\
+             };\n\
+             \n\
+             #endif\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rust_module() -> Result<(), Error> {
+        let output = Assembler::new().assemble(PROGRAM)?;
+        assert_eq!(
+            output.format_rust_module()?,
+            "// Generated by lc3asm, do not edit.\n\
+             pub const ORIGIN: u16 = 0x3000;\n\
+             pub const WORDS: [u16; 6] = [\n\
+             \x20   0xE002, 0xF022, 0x0FFD, 0x0048, 0x0069, 0x0000,\n\
+             ];\n\
+             \n\
+             #[allow(non_upper_case_globals)]\n\
+             pub mod symbols {\n\
+             \x20   pub const MSG: u16 = 0x3003;\n\
+             \x20   pub const r#loop: u16 = 0x3000;\n\
+             }\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(rust_identifier("hello-world"), "hello_world");
+        assert_eq!(rust_identifier("2048"), "_2048");
+        assert_eq!(rust_identifier("type"), "r#type");
+        assert_eq!(rust_identifier("self"), "self_");
+        assert_eq!(rust_identifier("LOOP"), "LOOP");
+        assert_eq!(c_identifier("résumé"), "r_sum_");
+        assert_eq!(c_identifier("int"), "int_");
+        assert_eq!(c_identifier("2048"), "_2048");
+    }
+}
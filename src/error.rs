@@ -1,19 +1,71 @@
 //! Provides [Error] type for error handling.
 use super::Rule;
-use pest::error::Error as PestError;
-use std::fmt::Error as FmtError;
+use crate::assembler::{Diagnostic, Location, Severity, TrapAliasError};
+use crate::builder::BuildError;
+use crate::extension::ExtensionError;
+use crate::merge::MergeError;
+use crate::object::ObjectError;
+use crate::util::LiteralError;
+use alloc::string::ToString;
+use core::fmt::Error as FmtError;
+use core::num::ParseIntError;
+use core::str::Utf8Error;
+use pest::error::{Error as PestError, InputLocation, LineColLocation};
+#[cfg(feature = "std")]
 use std::io::Error as IOError;
-use std::num::ParseIntError;
-use std::str::Utf8Error;
 
 /// Assembler-related error type.
 #[derive(Debug)]
 pub enum Error {
     Pest(PestError<Rule>),
     ParseInt(ParseIntError),
+    Literal(LiteralError),
+    #[cfg(feature = "std")]
     Io(IOError),
     Utf8(Utf8Error),
     Fmt(FmtError),
+    Build(BuildError),
+    Extension(ExtensionError),
+    TrapAlias(TrapAliasError),
+    Object(ObjectError),
+    Merge(MergeError),
+}
+
+impl Error {
+    /// Returns the error as a [Severity::Error] diagnostic, located in the source code for
+    /// syntax and assembly errors.
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Pest(err) => {
+                let (start, end) = match err.location {
+                    InputLocation::Pos(pos) => (pos, pos),
+                    InputLocation::Span(span) => span,
+                };
+                let (line, column) = match err.line_col {
+                    LineColLocation::Pos(pos) => pos,
+                    LineColLocation::Span(start, _) => start,
+                };
+                Diagnostic {
+                    severity: Severity::Error,
+                    message: err.variant.message().into_owned(),
+                    location: Some(Location {
+                        start,
+                        end,
+                        line,
+                        column,
+                        file: 0,
+                    }),
+                    file: err.path().map(Into::into),
+                }
+            }
+            err => Diagnostic {
+                severity: Severity::Error,
+                message: err.to_string(),
+                location: None,
+                file: None,
+            },
+        }
+    }
 }
 
 impl From<PestError<Rule>> for Error {
@@ -28,6 +80,13 @@ impl From<ParseIntError> for Error {
     }
 }
 
+impl From<LiteralError> for Error {
+    fn from(e: LiteralError) -> Error {
+        Error::Literal(e)
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<IOError> for Error {
     fn from(e: IOError) -> Error {
         Error::Io(e)
@@ -46,15 +105,52 @@ impl From<FmtError> for Error {
     }
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Error {
+        Error::Build(e)
+    }
+}
+
+impl From<ExtensionError> for Error {
+    fn from(e: ExtensionError) -> Error {
+        Error::Extension(e)
+    }
+}
+
+impl From<TrapAliasError> for Error {
+    fn from(e: TrapAliasError) -> Error {
+        Error::TrapAlias(e)
+    }
+}
+
+impl From<ObjectError> for Error {
+    fn from(e: ObjectError) -> Error {
+        Error::Object(e)
+    }
+}
+
+impl From<MergeError> for Error {
+    fn from(e: MergeError) -> Error {
+        Error::Merge(e)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             // FIXME: why intellij-rust tries to match fmt with Debug when err.fmt(f)?
-            Error::Pest(err) => std::fmt::Display::fmt(err, f),
+            Error::Pest(err) => core::fmt::Display::fmt(err, f),
             Error::ParseInt(err) => err.fmt(f),
+            Error::Literal(err) => err.fmt(f),
+            #[cfg(feature = "std")]
             Error::Io(err) => err.fmt(f),
             Error::Utf8(err) => err.fmt(f),
             Error::Fmt(err) => err.fmt(f),
+            Error::Build(err) => err.fmt(f),
+            Error::Extension(err) => err.fmt(f),
+            Error::TrapAlias(err) => err.fmt(f),
+            Error::Object(err) => err.fmt(f),
+            Error::Merge(err) => err.fmt(f),
         }
     }
 }
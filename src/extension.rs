@@ -0,0 +1,535 @@
+//! Provides [Extension]s, custom instructions encoded with the reserved opcode `1101`.
+//!
+//! An extension is described by its mnemonic and the [Slot]s of the lower 12 bits of its words,
+//! from the most significant bit like [Format]s of the [isa](crate::isa). Operands are written in
+//! the order of their slots. [parse] reads definitions from text, one per line, where each slot
+//! is either fixed binary digits or `name:kind`:
+//!
+//! | Kind  | Slot                                  | Written as               |
+//! |-------|---------------------------------------|--------------------------|
+//! | `reg` | 3-bit register                        | `R0`..`R7`               |
+//! | `uN`  | `N`-bit unsigned immediate            | number or constant       |
+//! | `sN`  | `N`-bit signed immediate              | number or constant       |
+//! | `pcN` | `N`-bit offset from the incremented PC | label or number         |
+//!
+//! ```text
+//! ; Multiply and shift, written as `MUL R0, R1, R2` and `SHL R0, R1, #4`
+//! MUL   DR:reg SR1:reg 000 SR2:reg
+//! SHL   DR:reg SR:reg 01 amount:u4
+//! ```
+//!
+//! [Assembler::extension] makes the assembler accept them, and a [Dispatcher] lets a simulator
+//! execute them through handlers.
+//!
+//! [Assembler::extension]: crate::Assembler::extension
+use crate::field::{Field, Signedness};
+use crate::isa::{Format, OperandKind, Slot};
+use crate::symbol_table::RESERVED_WORDS;
+use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Opcode shared by every extension.
+pub const OPCODE: u16 = 0b1101;
+
+pub(crate) const PSEUDO_INSTRUCTIONS: &[&str] = &[
+    "PUSH", "POP", "MOV", "CLR", "INC", "DEC", "SUB", "NEG", "CALL",
+];
+
+/// Instruction added to the reserved opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    format: Format,
+}
+
+impl Extension {
+    /// Describes an extension with given mnemonic and slots covering the lower 12 bits.
+    ///
+    /// ```
+    /// use lc3asm::extension::Extension;
+    /// use lc3asm::isa::{OperandKind, Slot};
+    /// use lc3asm::Field;
+    ///
+    /// const SLOTS: &[Slot] = &[
+    ///     Slot::Operand(OperandKind::Register, Field::unsigned("DR", 3)),
+    ///     Slot::Fixed { bits: 1, value: 1 },
+    ///     Slot::Operand(OperandKind::Immediate, Field::signed("imm8", 8)),
+    /// ];
+    /// let extension = Extension::new("LDIMM", SLOTS).unwrap();
+    /// assert_eq!(extension.format().to_string(), "LDIMM DR, imm8");
+    /// ```
+    pub fn new(
+        mnemonic: impl Into<Cow<'static, str>>,
+        slots: impl Into<Cow<'static, [Slot]>>,
+    ) -> Result<Self, ExtensionError> {
+        let (mnemonic, slots) = (mnemonic.into(), slots.into());
+        check(&mnemonic, &slots).map_err(|message| ExtensionError {
+            line: None,
+            message,
+        })?;
+        Ok(Extension {
+            format: Format {
+                mnemonic,
+                opcode: OPCODE,
+                slots,
+            },
+        })
+    }
+
+    /// Returns the encoding of the extension.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// Returns why both extensions cannot be used together, if they cannot.
+    pub(crate) fn conflict(&self, other: &Extension) -> Option<String> {
+        let registers = |format: &Format| {
+            format
+                .operands()
+                .map(|(kind, _)| kind == OperandKind::Register)
+                .collect::<Vec<_>>()
+        };
+        let (mask, value) = fixed_bits(&self.format);
+        let (other_mask, other_value) = fixed_bits(&other.format);
+        if (value ^ other_value) & mask & other_mask == 0 {
+            Some(format!(
+                "Encoding of {} overlaps with {}",
+                self.format, other.format
+            ))
+        } else if self
+            .format
+            .mnemonic
+            .eq_ignore_ascii_case(&other.format.mnemonic)
+            && registers(&self.format) == registers(&other.format)
+        {
+            Some(format!(
+                "{} is already defined with the same operands",
+                self.format
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads a definition such as `MUL DR:reg SR1:reg 000 SR2:reg`.
+impl FromStr for Extension {
+    type Err = ExtensionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message| ExtensionError {
+            line: None,
+            message,
+        };
+        let mut words = s.split_whitespace();
+        let mnemonic = words
+            .next()
+            .ok_or_else(|| error("Expected a mnemonic".to_owned()))?;
+        let slots = words.map(parse_slot).collect::<Result<Vec<_>, _>>();
+        Extension::new(mnemonic.to_owned(), slots.map_err(error)?)
+    }
+}
+
+/// Reads extension definitions, one per line. Text following `;` is a comment.
+pub fn parse(text: &str) -> Result<Vec<Extension>, ExtensionError> {
+    let mut extensions: Vec<Extension> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let definition = line.split(';').next().unwrap().trim();
+        if definition.is_empty() {
+            continue;
+        }
+        let with_line = |message| ExtensionError {
+            line: Some(index + 1),
+            message,
+        };
+        let extension = definition
+            .parse::<Extension>()
+            .map_err(|e| with_line(e.message))?;
+        if let Some(message) = extensions
+            .iter()
+            .find_map(|other| extension.conflict(other))
+        {
+            return Err(with_line(message));
+        }
+        extensions.push(extension);
+    }
+    Ok(extensions)
+}
+
+/// Handler of an extension, called with the state of the simulator and the operand values.
+pub type Handler<M> = Box<dyn Fn(&mut M, &[i64])>;
+
+/// Executes extensions in a simulator whose state is `M`.
+///
+/// ```
+/// use lc3asm::extension::Dispatcher;
+///
+/// let mul = "MUL DR:reg SR1:reg 000 SR2:reg".parse().unwrap();
+/// let dispatcher = Dispatcher::new().on(mul, |registers: &mut [u16; 8], operands| {
+///     let (dr, sr1, sr2) = (operands[0] as usize, operands[1] as usize, operands[2] as usize);
+///     registers[dr] = registers[sr1].wrapping_mul(registers[sr2]);
+/// });
+/// let mut registers = [0, 6, 7, 0, 0, 0, 0, 0];
+/// assert!(dispatcher.execute(&mut registers, 0xD042));
+/// assert_eq!(registers[0], 42);
+/// assert!(!dispatcher.execute(&mut registers, 0xD008));
+/// ```
+pub struct Dispatcher<M> {
+    handlers: Vec<(Extension, Handler<M>)>,
+}
+
+impl<M> Dispatcher<M> {
+    /// Creates a dispatcher without extensions.
+    pub fn new() -> Self {
+        Dispatcher {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Adds an extension executed by given handler.
+    ///
+    /// Panics if it conflicts with an extension already added.
+    pub fn on(mut self, extension: Extension, handler: impl Fn(&mut M, &[i64]) + 'static) -> Self {
+        if let Some(message) = self
+            .handlers
+            .iter()
+            .find_map(|(other, _)| extension.conflict(other))
+        {
+            panic!("{}", message);
+        }
+        self.handlers.push((extension, Box::new(handler)));
+        self
+    }
+
+    /// Decodes given word into the extension encoding it and its operand values.
+    pub fn decode(&self, word: u16) -> Option<(&Extension, Vec<i64>)> {
+        self.handlers
+            .iter()
+            .find(|(extension, _)| extension.format.matches(word))
+            .map(|(extension, _)| (extension, extension.format.decode(word)))
+    }
+
+    /// Runs the handler of the extension encoding given word, which the simulator has already
+    /// fetched and incremented the PC past.
+    ///
+    /// Returns `false` if no extension encodes the word.
+    pub fn execute(&self, machine: &mut M, word: u16) -> bool {
+        match self
+            .handlers
+            .iter()
+            .find(|(extension, _)| extension.format.matches(word))
+        {
+            Some((extension, handler)) => {
+                handler(machine, &extension.format.decode(word));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<M> Default for Dispatcher<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned when an extension cannot be defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionError {
+    /// 1-based line of the definition, if it was read with [parse]
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ExtensionError {}
+
+fn check(mnemonic: &str, slots: &[Slot]) -> Result<(), String> {
+    let mut chars = mnemonic.chars();
+    if !chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("Invalid mnemonic {:?}", mnemonic));
+    }
+    let upper = mnemonic.to_ascii_uppercase();
+    let branch = upper
+        .strip_prefix("BR")
+        .is_some_and(|flags| flags.chars().all(|c| "NZP".contains(c)));
+    if branch || RESERVED_WORDS.contains(&upper.as_str()) {
+        return Err(format!("{} is already an instruction", mnemonic));
+    }
+    for slot in slots {
+        match slot {
+            Slot::Fixed { bits, value } if *bits == 0 || u32::from(*value) >> bits != 0 => {
+                return Err(format!(
+                    "Fixed value {:b} does not fit in {} bits",
+                    value, bits
+                ))
+            }
+            Slot::Operand(OperandKind::Condition, field) => {
+                return Err(format!("Operand {} cannot be condition codes", field.name))
+            }
+            Slot::Operand(OperandKind::Register, field) if field.bits != 3 => {
+                return Err(format!("Register {} must have 3 bits", field.name))
+            }
+            Slot::Operand(_, field) if field.bits == 0 => {
+                return Err(format!("Operand {} must have at least 1 bit", field.name))
+            }
+            _ => (),
+        }
+    }
+    let bits: u32 = slots.iter().map(Slot::bits).sum();
+    if bits != 12 {
+        return Err(format!(
+            "Slots of {} cover {} bits, expected 12",
+            mnemonic, bits
+        ));
+    }
+    Ok(())
+}
+
+fn parse_slot(token: &str) -> Result<Slot, String> {
+    if token.chars().all(|c| c == '0' || c == '1') {
+        if token.len() > 12 {
+            return Err(format!("Fixed bits {} are longer than 12 bits", token));
+        }
+        return Ok(Slot::Fixed {
+            bits: token.len() as u32,
+            value: u16::from_str_radix(token, 2).unwrap(),
+        });
+    }
+    let (name, kind) = token
+        .split_once(':')
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| {
+            format!(
+                "Invalid slot {}, expected binary digits or name:kind",
+                token
+            )
+        })?;
+    let field = |signedness, bits| Field {
+        name: Cow::Owned(name.to_owned()),
+        bits,
+        signedness,
+    };
+    let kind = kind.to_ascii_lowercase();
+    let width = |digits: &str| {
+        digits
+            .parse::<u32>()
+            .ok()
+            .filter(|bits| (1..=12).contains(bits))
+            .ok_or_else(|| format!("Invalid width of slot {}, expected 1..12 bits", token))
+    };
+    Ok(match kind.as_str() {
+        "reg" => Slot::Operand(OperandKind::Register, field(Signedness::Unsigned, 3)),
+        _ if kind.starts_with("pc") => Slot::Operand(
+            OperandKind::PcOffset,
+            field(Signedness::Signed, width(&kind[2..])?),
+        ),
+        _ if kind.starts_with('s') => Slot::Operand(
+            OperandKind::Immediate,
+            field(Signedness::Signed, width(&kind[1..])?),
+        ),
+        _ if kind.starts_with('u') => Slot::Operand(
+            OperandKind::Immediate,
+            field(Signedness::Unsigned, width(&kind[1..])?),
+        ),
+        _ => {
+            return Err(format!(
+                "Unknown kind {} of slot {}, expected reg, uN, sN or pcN",
+                kind, token
+            ))
+        }
+    })
+}
+
+/// Returns the mask and value of the fixed bits of given format, opcode excluded.
+fn fixed_bits(format: &Format) -> (u16, u16) {
+    let mut shift = 12;
+    let (mut mask, mut value) = (0, 0);
+    for slot in format.slots.iter() {
+        shift -= slot.bits();
+        if let Slot::Fixed { bits, value: fixed } = slot {
+            mask |= (((1u32 << bits) - 1) as u16) << shift;
+            value |= fixed << shift;
+        }
+    }
+    (mask, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error, Target};
+
+    const DEFINITIONS: &str = "\
+; Arithmetic
+MUL   0 DR:reg SR1:reg 00 SR2:reg
+SHL   0 DR:reg SR:reg 1 amount:u4
+JZ    1 DR:reg pc8:pc8      ; Jumps if DR is zero
+";
+
+    #[test]
+    fn test_parse() {
+        let extensions = parse(DEFINITIONS).unwrap();
+        let syntax = extensions
+            .iter()
+            .map(|extension| extension.format().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            syntax,
+            ["MUL DR, SR1, SR2", "SHL DR, SR, amount", "JZ DR, pc8"]
+        );
+        let error = |text| parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("MUL DR:reg SR:reg"),
+            "Line 1: Slots of MUL cover 6 bits, expected 12"
+        );
+        assert_eq!(
+            error("ADD DR:reg imm9:s9"),
+            "Line 1: ADD is already an instruction"
+        );
+        assert_eq!(
+            error("SQR DR:reg SR:reg 000 imm:x3"),
+            "Line 1: Unknown kind x3 of slot imm:x3, expected reg, uN, sN or pcN"
+        );
+        assert_eq!(
+            error("MUL DR:reg SR1:reg 000 SR2:reg\n\nSQR DR:reg SR:reg 000000"),
+            "Line 3: Encoding of SQR DR, SR overlaps with MUL DR, SR1, SR2"
+        );
+    }
+
+    #[test]
+    fn test_assemble() -> Result<(), Error> {
+        let assembler = Assembler::new().extensions(parse(DEFINITIONS).unwrap());
+        let output = assembler.assemble(
+            "\
+.ORIG x3000
+        MUL     R0, R1, R2
+LOOP    SHL     R3, R3, #15
+        JZ      R3, LOOP
+.END",
+        )?;
+        assert_eq!(output.segments[0].words, [0xD022, 0xD37F, 0xDBFE]);
+        let err = assembler
+            .assemble(".ORIG x3000\nSHL R0, R1, #16\n.END")
+            .unwrap_err();
+        assert!(err.to_string().contains("for amount"), "{}", err);
+        let err = assembler
+            .assemble(".ORIG x3000\nDIV R0, R1, R2\n.END")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("DIV is not an instruction of LC-3"));
+        for (source, message) in [
+            (
+                "ADDD R1, R1, #1",
+                "ADDD is not an instruction of LC-3, did you mean ADD?",
+            ),
+            (
+                "MULL R0, R1, R2",
+                "MULL is not an instruction of LC-3, did you mean MUL?",
+            ),
+            ("FROB R0, R1, R2", "FROB is not an instruction of LC-3"),
+        ] {
+            let err = assembler
+                .assemble(format!(".ORIG x3000\nLOOP {}\n.END", source))
+                .unwrap_err();
+            let diagnostic = err.diagnostic();
+            assert_eq!(diagnostic.message, message);
+            let location = diagnostic.location.unwrap();
+            assert_eq!((location.line, location.column), (2, 6));
+            assert_eq!(location.end - location.start, 4);
+        }
+        let err = assembler
+            .clone()
+            .target(Target::Lc3b)
+            .assemble(".ORIG x3000\nMUL R0, R1, R2\n.END")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("MUL is not an instruction of LC-3b"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict() {
+        let mul: Extension = "MUL DR:reg SR1:reg 000 SR2:reg".parse().unwrap();
+        let err = Assembler::new()
+            .extension(mul.clone())
+            .extension(mul)
+            .assemble(".ORIG x3000\nHALT\n.END")
+            .unwrap_err();
+        assert!(matches!(err, Error::Extension(_)));
+        assert_eq!(
+            err.to_string(),
+            "Encoding of MUL DR, SR1, SR2 overlaps with MUL DR, SR1, SR2"
+        );
+    }
+
+    #[test]
+    fn test_without_operands() -> Result<(), Error> {
+        let flush = "FLUSH 111111111111".parse().unwrap();
+        let output = Assembler::new()
+            .extension(flush)
+            .assemble(".ORIG x3000\nFLUSH\nDONE FLUSH\nBRnzp DONE\n.END")?;
+        assert_eq!(output.segments[0].words, [0xDFFF, 0xDFFF, 0x0FFE]);
+        assert_eq!(output.symbols["DONE"], 0x3001);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dispatch() -> Result<(), Error> {
+        use lc3::vm::VM;
+
+        let mul = parse(DEFINITIONS).unwrap().remove(0);
+        let output = Assembler::new().extension(mul.clone()).assemble(
+            "\
+.ORIG x3000
+        LD      R1, SIX
+        LD      R2, SEVEN
+        MUL     R0, R1, R2
+        ST      R0, RESULT
+        HALT
+SIX     .FILL   6
+SEVEN   .FILL   7
+RESULT  .BLKW   1
+.END",
+        )?;
+        let dispatcher = Dispatcher::new().on(mul, |vm: &mut VM, operands| {
+            let register = |i: usize| operands[i] as usize;
+            vm.register[register(0)] =
+                vm.register[register(1)].wrapping_mul(vm.register[register(2)]);
+        });
+        let mut vm = VM::new();
+        vm.load_u8(&output.object_bytes());
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        while vm.mem[0xFFFE] >> 15 > 0 {
+            let word = vm.mem[usize::from(vm.pc)];
+            if dispatcher.decode(word).is_some() {
+                vm.pc += 1;
+                dispatcher.execute(&mut vm, word);
+            } else {
+                vm.step(&mut input, &mut output);
+            }
+        }
+        assert_eq!(vm.mem[0x3007], 42);
+        Ok(())
+    }
+}
@@ -0,0 +1,867 @@
+//! Provides the hand-written parser which [Assembler::assemble] tries before the pest grammar.
+//!
+//! Large generated programs spend most of their assembling time building pest pairs, so plain
+//! LC-3 programs are read line by line and encoded directly into words. The parser accepts the
+//! language of [AsmParser](crate::AsmParser) restricted to LC-3 instructions, labels, `.ORIG`,
+//! `.FILL`, `.BLKW`, `.STRINGZ` and `.END`, with the default dialect and target.
+//!
+//! Anything else declines the program, including every program with an error, so the grammar
+//! reads it instead and errors are reported as before. Both paths produce identical outputs,
+//! which the tests check on generated programs.
+use crate::assembler::{Assembler, AssemblyOutput, Diagnostic, Location, Severity, WarningLevel};
+use crate::image::{Image, ImageSegment, StatementKind};
+use crate::isa::{Format, OperandKind};
+use crate::resolve::Symbols;
+use crate::symbol_table::check_label_name;
+use crate::util::unescape;
+use crate::{field, parse_number_literal, Dialect, Target};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Mnemonics starting a statement of the grammar, besides `BR` with condition codes.
+const KEYWORDS: &[&str] = &[
+    "ADD", "AND", "NOT", "JMP", "JSRR", "JSR", "LDI", "LDR", "LDB", "LDW", "LD", "STI", "STR",
+    "STB", "STW", "ST", "LEA", "RTI", "RET", "TRAP", "NOP", "XOR", "LSHF", "RSHFL", "RSHFA",
+    "PUSH", "POP", "MOV", "CLR", "INC", "DEC", "SUB", "NEG", "CALL", "HALT", "IN", "OUT", "PUTSP",
+    "PUTS", "GETC",
+];
+
+/// Assembles given program, or returns `None` if it must be read by the grammar.
+pub(crate) fn assemble(options: &Assembler, input: &str) -> Option<AssemblyOutput> {
+    if options.target != Target::Lc3
+        || options.dialect != Dialect::Lc3asm
+        || options.strict
+        || options.relax_branches
+        || !options.extensions.is_empty()
+        || !options.trap_aliases.is_empty()
+    {
+        return None;
+    }
+    let mut program = Program::new(options);
+    let mut line_start = 0;
+    for (index, raw) in input.split('\n').enumerate() {
+        let text = raw.strip_suffix('\r').unwrap_or(raw);
+        // A lone carriage return ends a line for the grammar
+        if text.contains('\r') {
+            return None;
+        }
+        let line = Line {
+            text,
+            start: line_start,
+            number: index + 1,
+        };
+        line_start += raw.len() + 1;
+        if program.read(line)? {
+            return program.encode(input);
+        }
+    }
+    None
+}
+
+/// Source line with its position in the program.
+#[derive(Clone, Copy)]
+struct Line<'i> {
+    /// Text without the line ending
+    text: &'i str,
+    /// Byte offset of the line
+    start: usize,
+    /// 1-based line number
+    number: usize,
+}
+
+impl Line<'_> {
+    /// Returns the location of given span within the line, whose text before a statement is
+    /// ASCII so columns are byte offsets.
+    fn locate(&self, start: usize, end: usize) -> Location {
+        Location {
+            start: self.start + start,
+            end: self.start + end,
+            line: self.number,
+            column: start + 1,
+            file: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand<'i> {
+    Register(u8),
+    Number(i64),
+    Label(&'i str),
+}
+
+enum Kind<'i> {
+    Instruction {
+        format: &'static Format,
+        operands: [Operand<'i>; 3],
+        /// Whether the instruction is a `BR` without condition codes
+        bare_branch: bool,
+    },
+    Fill(Operand<'i>),
+    Blkw(Operand<'i>),
+    Stringz(Vec<u8>),
+}
+
+struct Statement<'i> {
+    kind: Kind<'i>,
+    location: Location,
+    /// Offset of the first word from the origin
+    offset: usize,
+    size: usize,
+}
+
+struct Program<'a, 'i> {
+    options: &'a Assembler,
+    /// Labels with their declared names, and constants
+    symbols: Symbols<'a, &'i str>,
+    /// Location of `.ORIG`, once read
+    orig: Option<Location>,
+    statements: Vec<Statement<'i>>,
+    offset: usize,
+}
+
+impl<'a, 'i> Program<'a, 'i> {
+    fn new(options: &'a Assembler) -> Self {
+        Program {
+            options,
+            symbols: Symbols::new(options),
+            orig: None,
+            statements: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Reads a line, returning whether it ends the program.
+    fn read(&mut self, line: Line<'i>) -> Option<bool> {
+        let mut cursor = Cursor::new(line.text);
+        if cursor.at_end() {
+            return Some(false);
+        }
+        if self.orig.is_none() {
+            return self.read_orig(line, cursor).map(|_| false);
+        }
+        if cursor.at_directive("END")? {
+            return Some(true);
+        }
+        let mut attempt = cursor;
+        if let Some((kind, end)) = statement(&mut attempt) {
+            self.push(kind, line.locate(cursor.pos, end))?;
+            return Some(false);
+        }
+        // The first word is a label only if the line cannot be read as a statement
+        let label = cursor.word()?;
+        if !is_label(label) || is_keyword(label) {
+            return None;
+        }
+        self.declare(label)?;
+        cursor.eat(b':');
+        if cursor.at_end() {
+            return Some(false);
+        }
+        if cursor.at_directive("END")? {
+            return Some(true);
+        }
+        let start = cursor.pos;
+        let (kind, end) = statement(&mut cursor)?;
+        self.push(kind, line.locate(start, end))?;
+        Some(false)
+    }
+
+    fn read_orig(&mut self, line: Line<'i>, mut cursor: Cursor<'i>) -> Option<()> {
+        let start = cursor.pos;
+        if !cursor.eat(b'.') || !cursor.word()?.eq_ignore_ascii_case("ORIG") {
+            return None;
+        }
+        if !cursor.skip_whitespace() {
+            return None;
+        }
+        let address = number(cursor.token())?;
+        let address = field::ORIG.encode(address).ok()?;
+        let end = cursor.pos;
+        if !cursor.at_end() {
+            return None;
+        }
+        self.symbols.origin = usize::from(self.options.origin.unwrap_or(address));
+        self.orig = Some(line.locate(start, end));
+        Some(())
+    }
+
+    fn declare(&mut self, name: &'i str) -> Option<()> {
+        check_label_name(name, self.options).ok()?;
+        let key = self.options.symbol_key(name);
+        if self.symbols.constants.contains_key(&key) || self.symbols.labels.contains_key(&key) {
+            return None;
+        }
+        self.symbols.labels.insert(key, (self.offset, name));
+        Some(())
+    }
+
+    fn push(&mut self, kind: Kind<'i>, location: Location) -> Option<()> {
+        let size = match &kind {
+            Kind::Instruction { .. } | Kind::Fill(_) => 1,
+            Kind::Blkw(Operand::Number(count)) => usize::from(field::BLKW.encode(*count).ok()?),
+            // Labels declared so far have no address yet
+            Kind::Blkw(Operand::Label(name)) if self.symbols.label(name).is_none() => {
+                usize::from(field::BLKW.encode(self.symbols.constant(name)?).ok()?)
+            }
+            Kind::Blkw(_) => return None,
+            Kind::Stringz(bytes) => bytes.len() + 1,
+        };
+        self.statements.push(Statement {
+            kind,
+            location,
+            offset: self.offset,
+            size,
+        });
+        self.offset += size;
+        Some(())
+    }
+
+    /// Resolves an operand into its value. Labels are resolved into their address.
+    fn value(&self, operand: Operand) -> Option<i64> {
+        match operand {
+            Operand::Register(register) => Some(i64::from(register)),
+            Operand::Number(value) => Some(value),
+            Operand::Label(name) => self.symbols.value(name),
+        }
+    }
+
+    /// Resolves a PC-relative operand of the instruction at given offset.
+    fn pc_offset(&self, operand: Operand, offset: usize) -> Option<i64> {
+        match operand {
+            Operand::Label(name) => self.symbols.pc_offset(name, offset).ok(),
+            operand => self.value(operand),
+        }
+    }
+
+    fn encode(self, input: &str) -> Option<AssemblyOutput> {
+        let mut segment = ImageSegment {
+            origin: self.symbols.origin as u16,
+            location: Some(self.orig?),
+            words: Vec::with_capacity(self.offset),
+        };
+        let mut diagnostics = Vec::new();
+        for statement in &self.statements {
+            let location = Some(statement.location);
+            match &statement.kind {
+                Kind::Instruction {
+                    format,
+                    operands,
+                    bare_branch,
+                } => {
+                    let mut values = [0; 3];
+                    let count = format.operands().count();
+                    for (value, ((kind, _), operand)) in
+                        values.iter_mut().zip(format.operands().zip(operands))
+                    {
+                        *value = match kind {
+                            OperandKind::PcOffset => self.pc_offset(*operand, statement.offset)?,
+                            _ => self.value(*operand)?,
+                        };
+                    }
+                    if *bare_branch {
+                        match self.options.warnings {
+                            WarningLevel::Allow => (),
+                            WarningLevel::Warn => diagnostics.push(Diagnostic {
+                                severity: Severity::Warning,
+                                message: "Use BRnzp instead of BR for clarity".into(),
+                                location,
+                                file: None,
+                            }),
+                            WarningLevel::Deny => return None,
+                        }
+                    }
+                    let word = format.encode(&values[..count]).ok()?;
+                    segment.push(word, StatementKind::Instruction, location);
+                }
+                Kind::Fill(operand) => {
+                    let word = field::FILL.encode(self.value(*operand)?).ok()?;
+                    segment.push(word, StatementKind::Fill, location);
+                }
+                Kind::Blkw(_) => {
+                    for _ in 0..statement.size {
+                        segment.push(0, StatementKind::Blkw, location);
+                    }
+                }
+                Kind::Stringz(bytes) => {
+                    for byte in bytes.iter().chain(core::iter::once(&0)) {
+                        segment.push(u16::from(*byte), StatementKind::Stringz, location);
+                    }
+                }
+            }
+        }
+
+        let image = Image {
+            segments: vec![segment],
+            target: self.options.target,
+        };
+        Some(AssemblyOutput {
+            symbols: self.symbols.absolute(),
+            segments: image.segments(),
+            diagnostics,
+            listing: image.listing(input),
+            image,
+            trap_aliases: Default::default(),
+            files: vec![crate::preprocess::INPUT.into()],
+            format: self.options.output_format,
+            target: self.options.target,
+        })
+    }
+}
+
+/// Reads a statement, which must end its line, returning it with the end of its span.
+fn statement<'i>(cursor: &mut Cursor<'i>) -> Option<(Kind<'i>, usize)> {
+    if cursor.eat(b'.') {
+        let directive = cursor.word()?;
+        if !cursor.skip_whitespace() {
+            return None;
+        }
+        let kind = if directive.eq_ignore_ascii_case("FILL") {
+            Kind::Fill(value(cursor.token())?)
+        } else if directive.eq_ignore_ascii_case("BLKW") {
+            Kind::Blkw(value(cursor.token())?)
+        } else if directive.eq_ignore_ascii_case("STRINGZ") {
+            Kind::Stringz(cursor.string()?)
+        } else {
+            return None;
+        };
+        let end = cursor.pos;
+        return Some((kind, end)).filter(|_| cursor.at_end());
+    }
+
+    let mnemonic = cursor.word()?;
+    if let Some(flags) = branch_flags(mnemonic) {
+        let condition = match flags {
+            "n" => 0b100,
+            "z" => 0b010,
+            "p" => 0b001,
+            "nz" => 0b110,
+            "np" => 0b101,
+            "zp" => 0b011,
+            "" | "nzp" => 0b111,
+            _ => return None,
+        };
+        if !cursor.skip_whitespace() {
+            return None;
+        }
+        let target = addressable(cursor.token())?;
+        let kind = Kind::Instruction {
+            format: Target::Lc3.formats("BR").next()?,
+            operands: [Operand::Number(condition), target, Operand::Number(0)],
+            bare_branch: flags.is_empty(),
+        };
+        let end = cursor.pos;
+        return Some((kind, end)).filter(|_| cursor.at_end());
+    }
+
+    let mut tokens = [""; 3];
+    let mut count = 0;
+    let mut end = cursor.pos;
+    let spaced = cursor.skip_whitespace();
+    if !cursor.at_end() {
+        // Mnemonics with operands are followed by a space or a tab
+        if !spaced {
+            return None;
+        }
+        loop {
+            cursor.skip_whitespace();
+            let token = cursor.token();
+            if token.is_empty() || count == tokens.len() {
+                return None;
+            }
+            tokens[count] = token;
+            count += 1;
+            end = cursor.pos;
+            cursor.skip_whitespace();
+            if !cursor.eat(b',') {
+                break;
+            }
+        }
+        if !cursor.at_end() {
+            return None;
+        }
+    }
+    let tokens = &tokens[..count];
+    let format = Target::Lc3.formats(mnemonic).find(|format| {
+        format.operands().count() == tokens.len()
+            && format
+                .operands()
+                .zip(tokens)
+                .all(|((kind, _), token)| (kind == OperandKind::Register) == is_register(token))
+    })?;
+    let mut operands = [Operand::Number(0); 3];
+    for (operand, ((kind, _), token)) in operands.iter_mut().zip(format.operands().zip(tokens)) {
+        *operand = match kind {
+            OperandKind::Register => Operand::Register(token.as_bytes()[1] - b'0'),
+            OperandKind::PcOffset => addressable(token)?,
+            _ => value(token)?,
+        };
+    }
+    let kind = Kind::Instruction {
+        format,
+        operands,
+        bare_branch: false,
+    };
+    Some((kind, end))
+}
+
+/// Position within a line.
+#[derive(Clone, Copy)]
+struct Cursor<'i> {
+    text: &'i str,
+    pos: usize,
+}
+
+impl<'i> Cursor<'i> {
+    fn new(text: &'i str) -> Self {
+        Cursor { text, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let eaten = self.peek() == Some(byte);
+        self.pos += eaten as usize;
+        eaten
+    }
+
+    /// Skips spaces and tabs, returning whether there were any.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b' ') | Some(b'\t')) {
+            self.pos += 1;
+        }
+        self.pos != start
+    }
+
+    /// Returns whether only whitespace and a comment remain.
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        matches!(self.peek(), None | Some(b';'))
+    }
+
+    /// Reads ASCII letters, digits and underscores. Fails if they are followed by a non-ASCII
+    /// character, which may continue an identifier.
+    fn word(&mut self) -> Option<&'i str> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(byte) if byte.is_ascii_alphanumeric() || byte == b'_') {
+            self.pos += 1;
+        }
+        if self.peek().is_some_and(|byte| !byte.is_ascii()) {
+            return None;
+        }
+        Some(&self.text[start..self.pos])
+    }
+
+    /// Reads an operand, up to whitespace, a comma or a comment.
+    fn token(&mut self) -> &'i str {
+        let start = self.pos;
+        while !matches!(
+            self.peek(),
+            None | Some(b' ') | Some(b'\t') | Some(b',') | Some(b';')
+        ) {
+            self.pos += 1;
+        }
+        &self.text[start..self.pos]
+    }
+
+    /// Returns whether given pseudo-operation starts here, which ends the word.
+    fn at_directive(&self, name: &str) -> Option<bool> {
+        let rest = &self.text.as_bytes()[self.pos..];
+        if rest.first() != Some(&b'.')
+            || !rest
+                .get(1..=name.len())
+                .is_some_and(|word| word.eq_ignore_ascii_case(name.as_bytes()))
+        {
+            return Some(false);
+        }
+        match rest.get(name.len() + 1) {
+            Some(byte) if !byte.is_ascii() => None,
+            Some(byte) => Some(!byte.is_ascii_alphanumeric() && *byte != b'_'),
+            None => Some(true),
+        }
+    }
+
+    /// Reads a quoted string with the escape sequences of the grammar, returning its bytes.
+    fn string(&mut self) -> Option<Vec<u8>> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let start = self.pos;
+        loop {
+            match self.peek()? {
+                b'"' => break,
+                b'\\' => match self.text.as_bytes().get(self.pos + 1)? {
+                    b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => self.pos += 2,
+                    b'u' => {
+                        let digits = self.text.as_bytes().get(self.pos + 2..self.pos + 6)?;
+                        if !digits.iter().all(u8::is_ascii_hexdigit) {
+                            return None;
+                        }
+                        self.pos += 6;
+                    }
+                    _ => return None,
+                },
+                _ => self.pos += 1,
+            }
+        }
+        let inner = &self.text[start..self.pos];
+        self.pos += 1;
+        unescape(inner).map(String::into_bytes)
+    }
+}
+
+/// Returns the condition codes following `BR`, if given word starts with it.
+fn branch_flags(word: &str) -> Option<&str> {
+    word.get(..2)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("BR"))
+        .map(|_| &word[2..])
+}
+
+fn is_keyword(word: &str) -> bool {
+    branch_flags(word).is_some_and(|flags| {
+        flags
+            .bytes()
+            .all(|flag| matches!(flag.to_ascii_lowercase(), b'n' | b'z' | b'p'))
+    }) || KEYWORDS
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+fn is_label(token: &str) -> bool {
+    let mut bytes = token.bytes();
+    bytes
+        .next()
+        .is_some_and(|byte| byte.is_ascii_alphabetic() || byte == b'_')
+        && bytes.all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+fn is_register(token: &str) -> bool {
+    matches!(token.as_bytes(), [b'r' | b'R', b'0'..=b'9'])
+}
+
+/// Reads an operand where the grammar expects a `value`, trying a number first.
+fn value(token: &str) -> Option<Operand<'_>> {
+    match number(token) {
+        Some(value) => Some(Operand::Number(value)),
+        None if is_label(token) => Some(Operand::Label(token)),
+        None => None,
+    }
+}
+
+/// Reads an operand where the grammar expects an `addressable`, trying a label first.
+/// A token starting like a label is never read as a number, e.g. `x-2`.
+fn addressable(token: &str) -> Option<Operand<'_>> {
+    match token.bytes().next() {
+        Some(byte) if byte.is_ascii_alphabetic() || byte == b'_' => {
+            Some(Operand::Label(token)).filter(|_| is_label(token))
+        }
+        _ => number(token).map(Operand::Number),
+    }
+}
+
+/// Parses a number literal if the `number` rule of the grammar matches the whole token.
+/// Character literals are left to the grammar.
+fn number(token: &str) -> Option<i64> {
+    let bytes = token.as_bytes();
+    let len = [
+        (b'x', u8::is_ascii_hexdigit as fn(&u8) -> bool),
+        (b'b', |digit: &u8| matches!(digit, b'0' | b'1')),
+        (b'o', |digit: &u8| matches!(digit, b'0'..=b'7')),
+    ]
+    .iter()
+    .find_map(|(prefix, is_digit)| {
+        let mut pos = (bytes.first() == Some(&b'#')) as usize;
+        pos += (bytes.get(pos) == Some(&b'0')) as usize;
+        if bytes.get(pos)?.to_ascii_lowercase() != *prefix {
+            return None;
+        }
+        pos += 1;
+        pos += (bytes.get(pos) == Some(&b'-')) as usize;
+        digits(bytes, pos, *is_digit)
+    })
+    .or_else(|| {
+        let mut pos = (bytes.first() == Some(&b'#')) as usize;
+        pos += (bytes.get(pos) == Some(&b'-')) as usize;
+        digits(bytes, pos, u8::is_ascii_digit)
+    })?;
+    if len != bytes.len() {
+        return None;
+    }
+    parse_number_literal(token).ok()
+}
+
+/// Returns the end of digits optionally separated by single underscores, starting at `pos`.
+fn digits(bytes: &[u8], mut pos: usize, is_digit: fn(&u8) -> bool) -> Option<usize> {
+    if !bytes.get(pos).is_some_and(is_digit) {
+        return None;
+    }
+    pos += 1;
+    loop {
+        match bytes.get(pos) {
+            Some(byte) if is_digit(byte) => pos += 1,
+            Some(b'_') if bytes.get(pos + 1).is_some_and(is_digit) => pos += 2,
+            _ => return Some(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    /// Assembles given program with the grammar only.
+    fn grammar(options: &Assembler, input: &str) -> Result<AssemblyOutput, Error> {
+        options.assemble_pairs(crate::parse(input)?.collect())
+    }
+
+    /// Checks that the fast path either declines given program or agrees with the grammar,
+    /// returning whether it read the program.
+    fn check(options: &Assembler, input: &str) -> bool {
+        match assemble(options, input) {
+            Some(output) => {
+                let expected = grammar(options, input)
+                    .unwrap_or_else(|err| panic!("Accepted invalid program\n{}\n{}", input, err));
+                assert_eq!(output, expected, "{}", input);
+                true
+            }
+            None => false,
+        }
+    }
+
+    const PROGRAM: &str = "\
+; Leading comment
+
+\t.ORIG x3000 ; origin
+START\tLEA R0, MSG
+        puts
+        LD R1, COUNT
+LOOP:   ADD R1, R1, #-1
+        add r2,r2 , R1
+        AND R3, R3, xF
+        NOT R4, R3
+        BRp LOOP
+        BRnz\t#1
+        BR START
+        BRnzp DONE
+        JSR SUBR
+        JSRR R5
+        LDI R0, PTR
+        LDR R0, R6, #-32
+        ST R0, COUNT
+        STI R0, PTR
+        STR R0, R6, b1_1
+        TRAP x25
+        RTI
+        NOP
+        JMP R2
+SUBR    RET
+DONE    HALT
+COUNT   .FILL #10
+MINUS   .fill -1
+PTR     .FILL COUNT
+ADDR    .FILL 0xFFFF
+BLOCK   .BLKW 3
+        .BLKW o7
+MSG     .STRINGZ \"Hi;\\n\\\"\\u0041\"
+EMPTY:  .STRINGZ \"\"
+LAST: .END trailing text
+HALT
+";
+
+    #[test]
+    fn test_fast_path() {
+        assert!(check(&Assembler::new(), PROGRAM));
+        assert!(check(&Assembler::new(), &PROGRAM.replace('\n', "\r\n")));
+        assert!(check(&Assembler::new().origin(0x4000), PROGRAM));
+        assert!(check(
+            &Assembler::new().warnings(WarningLevel::Allow),
+            PROGRAM
+        ));
+        assert!(check(
+            &Assembler::new().case_sensitive_labels(false),
+            &PROGRAM.replace("LD R1, COUNT", "LD R1, count")
+        ));
+        assert!(check(
+            &Assembler::new()
+                .define("SIZE", 4)
+                .define("VALUE", -3)
+                .define("DATA", 0x3005),
+            ".ORIG x3000\nADD R0, R0, VALUE\n.BLKW SIZE\nLD R0, DATA\n.END"
+        ));
+        let snippets = [
+            "HALT\n.END",
+            "LOOP:HALT\nLOOP2:.FILL LOOP\n.END",
+            "LOOP.FILL 1\n.end",
+            "L\tBR L ; é\n.END",
+            "BR 5\nBRz\t-1\n.END",
+            ".FILL x\nx HALT\n.END",
+            ".FILL x10\n.STRINGZ \"é\\t\"\n.END",
+            "ADD R0, R0, #5;c\nADD\tR0,R0,R0\n.END",
+            "INPUT HALT\nOUTPUT\nRETURN RET\n.END",
+            "HALT\n\t.End ; done\n",
+            "DONE .END",
+        ];
+        for snippet in snippets.iter() {
+            let input = format!(".ORIG x3000\n{}", snippet);
+            assert!(check(&Assembler::new(), &input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_declined() {
+        let declined = [
+            // Constructs read by the grammar only
+            ".ORIG x3000\nPUSH R0\n.END",
+            ".ORIG x3000\nCALL HALT\n.END",
+            ".ORIG x3000\n.FILL 'a'\n.END",
+            ".ORIG x3000\n.EXTERNAL FOO\n.END",
+            ".ORIG x3000\n.TRAPDEF PUTNUM, x26\nPUTNUM\n.END",
+            ".ORIG x3000\nLÄBEL HALT\n.END",
+            ".ORIG x3000\nBRzn #0\n.END",
+            ".ORIG x3000\nHALT\rHALT\n.END",
+            // Errors
+            ".ORIG x3000\nHALT",
+            ".ORIG x3000\nLD R0, MISSING\n.END",
+            ".ORIG x3000\nA HALT\nA HALT\n.END",
+            ".ORIG x3000\nADD R8, R0, R0\n.END",
+            ".ORIG x3000\nADD R0, R0, #16\n.END",
+            ".ORIG x3000\nLDR R0, R1, R2\n.END",
+            ".ORIG x3000\nLD R0, x10\n.END",
+            ".ORIG x3000\nx10 HALT\n.END",
+            ".ORIG x3000\nHALT HALT\n.END",
+            ".ORIG x3000\nADD R0, R0, 1_\n.END",
+            ".ORIG x3000\n.STRINGZ \"\\q\"\n.END",
+            "HALT\n.ORIG x3000\n.END",
+        ];
+        for input in declined.iter() {
+            assert!(!check(&Assembler::new(), input), "{}", input);
+            assert_eq!(
+                Assembler::new()
+                    .assemble_text(input)
+                    .map_err(|err| err.to_string()),
+                grammar(&Assembler::new(), input).map_err(|err| err.to_string())
+            );
+        }
+        for options in [
+            Assembler::new().target(Target::Lc3b),
+            Assembler::new().dialect(Dialect::Lc3as),
+            Assembler::new().strict(true),
+            Assembler::new().relax_branches(true),
+            Assembler::new().warnings(WarningLevel::Deny),
+        ]
+        .iter()
+        {
+            assert!(!check(options, ".ORIG x3000\nBR #0\n.END"));
+        }
+    }
+
+    /// Returns pseudo-random numbers of a xorshift generator.
+    struct Random(u64);
+
+    impl Random {
+        fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            choices[(self.0 % choices.len() as u64) as usize]
+        }
+    }
+
+    #[test]
+    fn test_generated() {
+        const MNEMONICS: &[&str] = &[
+            "ADD", "and", "NOT", "BR", "BRnp", "BRzp", "BRnzp", "JMP", "JSR", "JSRR", "LD", "ldi",
+            "LDR", "ST", "STI", "STR", "LEA", "TRAP", "RET", "RTI", "NOP", "HALT", "out", "PUTSP",
+            ".FILL", ".BLKW", ".stringz",
+        ];
+        const REGISTERS: &[&str] = &["R0", "r7", "R3"];
+        const VALUES: &[&str] = &[
+            "#0", "#-1", "x7", "0x0", "x-5", "b101", "o7", "1_0", "5", "L1",
+        ];
+        const SEPARATORS: &[&str] = &[", ", ",", " , ", "\t,\t"];
+        const ENDINGS: &[&str] = &["", "", " ; comment", ";", "\t"];
+        // Replacements making a statement invalid or unsupported by the fast path
+        const MUTATIONS: &[&str] = &[
+            "R8", "R10", "R1x", "#16", "x1F", "0b12", "1__0", "#5a", "x", "'a'", "B", "x10",
+            "PUSH", "LDB", "FOO", "BRzn", "BRN", "IN", "", ",", ":", "\"s\"", "\"\\q\"", ".END",
+            "L1",
+        ];
+
+        let mut random = Random(0x2545_F491_4F6C_DD1D);
+        let (mut accepted, mut valid) = (0, 0);
+        for _ in 0..1000 {
+            let mut input = String::from(".ORIG x3000\n");
+            for line in 0..8 {
+                let mut tokens = Vec::new();
+                tokens.push(format!("L{}{}", line, random.pick(&["", ":", "\t"])));
+                let mnemonic = random.pick(MNEMONICS);
+                let source = [random.pick(REGISTERS), random.pick(VALUES)];
+                let operands = match mnemonic.to_ascii_uppercase().as_str() {
+                    "ADD" | "AND" => vec![
+                        random.pick(REGISTERS),
+                        random.pick(REGISTERS),
+                        random.pick(&source),
+                    ],
+                    "NOT" => vec![random.pick(REGISTERS), random.pick(REGISTERS)],
+                    "JMP" | "JSRR" => vec![random.pick(REGISTERS)],
+                    "LD" | "LDI" | "ST" | "STI" | "LEA" => {
+                        vec![
+                            random.pick(REGISTERS),
+                            random.pick(&["L0", "L5", "#3", "#-2"]),
+                        ]
+                    }
+                    "LDR" | "STR" => vec![
+                        random.pick(REGISTERS),
+                        random.pick(REGISTERS),
+                        random.pick(VALUES),
+                    ],
+                    "TRAP" => vec![random.pick(&["x25", "x21", "#35"])],
+                    ".FILL" => vec![random.pick(VALUES)],
+                    ".BLKW" => vec![random.pick(&["1", "#2", "x0"])],
+                    ".STRINGZ" => vec![random.pick(&["\"\"", "\"a b;c\"", "\"\\n\\u00e9\\\"\""])],
+                    "RET" | "RTI" | "NOP" | "HALT" | "OUT" | "PUTSP" => vec![],
+                    _ => vec![random.pick(&["L2", "L7", "#-1", "3"])],
+                };
+                tokens.push(mnemonic.to_owned());
+                let mut statement = tokens.join(random.pick(&[" ", "\t", "  "]));
+                for (index, operand) in operands.iter().enumerate() {
+                    statement += if index == 0 {
+                        random.pick(&[" ", "\t", " \t"])
+                    } else {
+                        random.pick(SEPARATORS)
+                    };
+                    statement += operand;
+                }
+                if random.pick(&["mutate", "", "", "", "", "", "", "", "", ""]) == "mutate" {
+                    let words = statement.split(' ').collect::<Vec<_>>();
+                    let target = random.pick(&words);
+                    statement = statement.replacen(target, random.pick(MUTATIONS), 1);
+                }
+                input += &statement;
+                input += random.pick(ENDINGS);
+                input += random.pick(&["\n", "\r\n"]);
+            }
+            input += random.pick(&[".END", "L8 .END x", ".end\nJUNK", "HALT"]);
+            for options in [
+                Assembler::new(),
+                Assembler::new().case_sensitive_labels(false).define("B", 3),
+                Assembler::new()
+                    .warnings(WarningLevel::Allow)
+                    .origin(0x0100),
+            ]
+            .iter()
+            {
+                accepted += check(options, &input) as usize;
+                valid += grammar(options, &input).is_ok() as usize;
+            }
+        }
+        // Valid programs are only declined for labels named like pseudo-instructions
+        assert!(accepted * 10 >= valid * 9, "{} of {}", accepted, valid);
+        assert!(valid > 1000, "{}", valid);
+    }
+}
@@ -0,0 +1,226 @@
+//! Provides [Field] model describing operand slots of instruction words.
+use alloc::borrow::Cow;
+use core::fmt;
+
+/// Interpretation of values accepted by a [Field].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signedness {
+    /// Two's complement values, e.g. `-16..15` for 5 bits
+    Signed,
+    /// Non-negative values, e.g. `0..31` for 5 bits
+    Unsigned,
+    /// Either signed or unsigned values which wrap into the same bits, e.g. `-16..31` for 5 bits
+    Either,
+}
+
+/// Operand slot of a word with its width and accepted values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Name used in error messages, e.g. `imm5`
+    pub name: Cow<'static, str>,
+    pub bits: u32,
+    pub signedness: Signedness,
+}
+
+impl Field {
+    pub const fn signed(name: &'static str, bits: u32) -> Self {
+        Field {
+            name: Cow::Borrowed(name),
+            bits,
+            signedness: Signedness::Signed,
+        }
+    }
+
+    pub const fn unsigned(name: &'static str, bits: u32) -> Self {
+        Field {
+            name: Cow::Borrowed(name),
+            bits,
+            signedness: Signedness::Unsigned,
+        }
+    }
+
+    pub const fn either(name: &'static str, bits: u32) -> Self {
+        Field {
+            name: Cow::Borrowed(name),
+            bits,
+            signedness: Signedness::Either,
+        }
+    }
+
+    /// Returns the inclusive range of accepted values.
+    pub fn range(&self) -> (i64, i64) {
+        let half = 1i64 << (self.bits - 1);
+        match self.signedness {
+            Signedness::Signed => (-half, half - 1),
+            Signedness::Unsigned => (0, (half << 1) - 1),
+            Signedness::Either => (-half, (half << 1) - 1),
+        }
+    }
+
+    /// Encodes given value into the lower bits of a word.
+    pub fn encode(&self, value: i64) -> Result<u16, FieldError> {
+        let (min, max) = self.range();
+        if value < min || value > max {
+            return Err(FieldError {
+                field: self.clone(),
+                value,
+            });
+        }
+        Ok((value as u16) & (((1u32 << self.bits) - 1) as u16))
+    }
+
+    /// Decodes the lower bits of given word, sign-extending signed fields.
+    pub fn decode(&self, bits: u16) -> i64 {
+        let mask = ((1u32 << self.bits) - 1) as u16;
+        let value = i64::from(bits & mask);
+        match self.signedness {
+            Signedness::Signed if value >= 1 << (self.bits - 1) => value - (1 << self.bits),
+            _ => value,
+        }
+    }
+}
+
+/// Error returned when a value does not fit in a [Field].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: Field,
+    pub value: i64,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (min, max) = self.field.range();
+        write!(
+            f,
+            "Value {} is out of range: expected {}..{} for {}",
+            self.value, min, max, self.field.name
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FieldError {}
+
+pub const DR: Field = Field::unsigned("DR", 3);
+pub const SR: Field = Field::unsigned("SR", 3);
+pub const SR1: Field = Field::unsigned("SR1", 3);
+pub const SR2: Field = Field::unsigned("SR2", 3);
+pub const BASE_R: Field = Field::unsigned("BaseR", 3);
+pub const IMM5: Field = Field::signed("imm5", 5);
+pub const OFFSET6: Field = Field::signed("offset6", 6);
+pub const BOFFSET6: Field = Field::signed("boffset6", 6);
+pub const AMOUNT4: Field = Field::unsigned("amount4", 4);
+pub const PC_OFFSET9: Field = Field::signed("PCoffset9", 9);
+pub const PC_OFFSET11: Field = Field::signed("PCoffset11", 11);
+pub const CONDITION: Field = Field::unsigned("nzp", 3);
+pub const TRAP_VECT8: Field = Field::unsigned("trapvect8", 8);
+pub const FILL: Field = Field::either(".FILL value", 16);
+pub const BLKW: Field = Field::unsigned(".BLKW count", 16);
+pub const ORIG: Field = Field::unsigned(".ORIG address", 16);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Error};
+
+    #[test]
+    fn test_field_ranges() {
+        assert_eq!(IMM5.range(), (-16, 15));
+        assert_eq!(TRAP_VECT8.range(), (0, 255));
+        assert_eq!(FILL.range(), (-32768, 65535));
+        assert_eq!(IMM5.encode(-1), Ok(0b11111));
+        assert_eq!(FILL.encode(0xFFFF), Ok(0xFFFF));
+        assert_eq!(FILL.encode(-1), Ok(0xFFFF));
+        assert_eq!(IMM5.decode(0b11111), -1);
+        assert_eq!(TRAP_VECT8.decode(0xFF), 255);
+        assert_eq!(
+            IMM5.encode(16).unwrap_err().to_string(),
+            "Value 16 is out of range: expected -16..15 for imm5"
+        );
+    }
+
+    fn word(line: &str) -> Result<u16, Error> {
+        let source = format!(".ORIG x3000\n{}\nLABEL .FILL #0\n.END", line);
+        let obj = assemble(source)?.0;
+        Ok(u16::from_be_bytes([obj[2], obj[3]]))
+    }
+
+    #[test]
+    fn test_every_opcode() -> Result<(), Error> {
+        let cases: &[(&str, u16)] = &[
+            ("ADD R1, R2, R3", 0x1283),
+            ("ADD R1, R2, #-16", 0x12B0),
+            ("ADD R1, R2, #15", 0x12AF),
+            ("AND R7, R0, R1", 0x5E01),
+            ("AND R7, R0, #0", 0x5E20),
+            ("NOT R4, R5", 0x997F),
+            ("BRnzp LABEL", 0x0E00),
+            ("BRn #-256", 0x0900),
+            ("BRz #255", 0x04FF),
+            ("JMP R3", 0xC0C0),
+            ("JSR LABEL", 0x4800),
+            ("JSR #-1024", 0x4C00),
+            ("JSRR R6", 0x4180),
+            ("LD R0, LABEL", 0x2000),
+            ("LDI R1, LABEL", 0xA200),
+            ("LDR R2, R3, #-32", 0x64E0),
+            ("LDR R2, R3, #31", 0x64DF),
+            ("LEA R4, LABEL", 0xE800),
+            ("ST R5, LABEL", 0x3A00),
+            ("STI R6, LABEL", 0xBC00),
+            ("STR R7, R0, #1", 0x7E01),
+            ("RET", 0xC1C0),
+            ("RTI", 0x8000),
+            ("TRAP x25", 0xF025),
+            ("TRAP xFF", 0xF0FF),
+            ("NOP", 0x0000),
+            ("GETC", 0xF020),
+            ("OUT", 0xF021),
+            ("PUTS", 0xF022),
+            ("IN", 0xF023),
+            ("PUTSP", 0xF024),
+            ("HALT", 0xF025),
+            (".FILL xFFFF", 0xFFFF),
+            (".FILL x8000", 0x8000),
+            (".FILL #-32768", 0x8000),
+            (".FILL #65535", 0xFFFF),
+            (".FILL LABEL", 0x3001),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(word(line)?, *expected, "{}", line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_errors() {
+        let cases: &[(&str, &str)] = &[
+            ("ADD R1, R2, #16", "expected -16..15 for imm5"),
+            ("AND R1, R2, #-17", "expected -16..15 for imm5"),
+            ("ADD R8, R2, R3", "expected 0..7 for DR"),
+            ("ADD R1, R9, R3", "expected 0..7 for SR1"),
+            ("AND R1, R2, R8", "expected 0..7 for SR2"),
+            ("NOT R1, R8", "expected 0..7 for SR"),
+            ("BRz #256", "expected -256..255 for PCoffset9"),
+            ("JMP R8", "expected 0..7 for BaseR"),
+            ("JSR #1024", "expected -1024..1023 for PCoffset11"),
+            ("JSRR R9", "expected 0..7 for BaseR"),
+            ("LD R0, #-257", "expected -256..255 for PCoffset9"),
+            ("LDI R0, #256", "expected -256..255 for PCoffset9"),
+            ("LDR R0, R1, #32", "expected -32..31 for offset6"),
+            ("LEA R0, #300", "expected -256..255 for PCoffset9"),
+            ("ST R0, #256", "expected -256..255 for PCoffset9"),
+            ("STI R0, #256", "expected -256..255 for PCoffset9"),
+            ("STR R0, R1, #-33", "expected -32..31 for offset6"),
+            ("TRAP x100", "expected 0..255 for trapvect8"),
+            ("TRAP #-1", "expected 0..255 for trapvect8"),
+            (".FILL x10000", "expected -32768..65535 for .FILL value"),
+            (".FILL #-32769", "expected -32768..65535 for .FILL value"),
+            (".BLKW #-1", "expected 0..65535 for .BLKW count"),
+        ];
+        for (line, message) in cases {
+            let err = word(line).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", line, err);
+        }
+    }
+}
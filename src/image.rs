@@ -0,0 +1,250 @@
+//! Provides the word-level [Image] of an assembled program, recording the statement each word
+//! was assembled from.
+//!
+//! Object files, listings and source maps of an [AssemblyOutput](crate::AssemblyOutput) are
+//! derived from its image.
+use crate::assembler::{ListingLine, Location, Segment};
+use crate::{Instruction, Target};
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Kind of statement a [Word] was assembled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum StatementKind {
+    /// Instruction, including extensions and trap aliases
+    Instruction,
+    /// Instruction written by a pseudo-instruction or a relaxed branch, listed with its text
+    Expansion,
+    /// `.FILL`, or the address literal ending a relaxed branch
+    Fill,
+    /// `.BLKW`
+    Blkw,
+    /// `.STRINGZ`
+    Stringz,
+}
+
+/// Assembled word with its provenance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Word {
+    pub value: u16,
+    pub kind: StatementKind,
+    /// Statement the word was assembled from, `None` for [ProgramBuilder](crate::ProgramBuilder)
+    /// outputs
+    pub location: Option<Location>,
+}
+
+/// Contiguous block of words of an [Image].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImageSegment {
+    /// Address of the first word
+    pub origin: u16,
+    /// `.ORIG` statement of the segment
+    pub location: Option<Location>,
+    pub words: Vec<Word>,
+}
+
+impl ImageSegment {
+    pub(crate) fn push(&mut self, value: u16, kind: StatementKind, location: Option<Location>) {
+        self.words.push(Word {
+            value,
+            kind,
+            location,
+        });
+    }
+}
+
+/// Assembled program as segments of words with their provenance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Image {
+    pub segments: Vec<ImageSegment>,
+    pub(crate) target: Target,
+}
+
+impl Image {
+    /// Returns the words of each segment without their provenance.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.segments
+            .iter()
+            .map(|segment| Segment {
+                origin: segment.origin,
+                words: segment.words.iter().map(|word| word.value).collect(),
+            })
+            .collect()
+    }
+
+    /// Returns the origin of each segment followed by its words, as written to object files.
+    pub(crate) fn object_words(&self) -> impl Iterator<Item = u16> + '_ {
+        self.segments.iter().flat_map(|segment| {
+            core::iter::once(segment.origin).chain(segment.words.iter().map(|word| word.value))
+        })
+    }
+
+    /// Returns each word with its address.
+    pub fn words(&self) -> impl Iterator<Item = (u16, &Word)> + '_ {
+        let word_size = self.target.word_size();
+        self.segments.iter().flat_map(move |segment| {
+            segment.words.iter().enumerate().map(move |(index, word)| {
+                (
+                    (usize::from(segment.origin) + index * word_size) as u16,
+                    word,
+                )
+            })
+        })
+    }
+
+    /// Returns the location of the statement each word was assembled from, keyed by address.
+    pub fn source_map(&self) -> BTreeMap<u16, Location> {
+        self.words()
+            .filter_map(|(address, word)| Some((address, word.location?)))
+            .collect()
+    }
+
+    /// Returns the `.ORIG` statement of each segment and each statement with its words, from
+    /// the source the image was assembled from.
+    ///
+    /// Statements without words other than `.ORIG`, e.g. `.BLKW 0`, are not listed.
+    pub fn listing(&self, source: &str) -> Vec<ListingLine> {
+        let line = |location: &Location| {
+            let line_start = source[..location.start].rfind('\n').map_or(0, |i| i + 1);
+            source[line_start..]
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim_end()
+                .to_owned()
+        };
+        let mut listing = Vec::new();
+        for segment in &self.segments {
+            if let Some(location) = &segment.location {
+                listing.push(ListingLine {
+                    address: segment.origin,
+                    words: Vec::new(),
+                    line: location.line,
+                    source: line(location),
+                    expansion: Vec::new(),
+                });
+            }
+        }
+        let mut words = self.words().peekable();
+        while let Some((address, first)) = words.next() {
+            let location = match &first.location {
+                Some(location) => location,
+                None => continue,
+            };
+            let mut statement = vec![first];
+            while let Some((_, word)) = words.next_if(|(_, word)| word.location == first.location) {
+                statement.push(word);
+            }
+            let expanded = statement
+                .iter()
+                .any(|word| word.kind == StatementKind::Expansion);
+            listing.push(ListingLine {
+                address,
+                words: statement.iter().map(|word| word.value).collect(),
+                line: location.line,
+                source: line(location),
+                expansion: if expanded {
+                    statement.iter().map(|word| describe(word)).collect()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+        listing
+    }
+}
+
+/// Returns the text of a word of an expanded statement.
+fn describe(word: &Word) -> String {
+    match word.kind {
+        StatementKind::Fill => format!(".FILL x{:04X}", word.value),
+        _ => Instruction::decode(word.value).to_string(),
+    }
+}
+
+/// Computes [Location]s of spans given in increasing order, counting lines incrementally as
+/// counting from the start of the source is quadratic.
+pub(crate) struct Locator<'i> {
+    source: &'i str,
+    offset: usize,
+    line: usize,
+}
+
+impl<'i> Locator<'i> {
+    pub(crate) fn new(source: &'i str) -> Self {
+        Locator {
+            source,
+            offset: 0,
+            line: 1,
+        }
+    }
+
+    pub(crate) fn locate(&mut self, start: usize, end: usize) -> Location {
+        self.line += self.source[self.offset..start].matches('\n').count();
+        self.offset = start;
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        Location {
+            start,
+            end,
+            line: self.line,
+            column: self.source[line_start..start].chars().count() + 1,
+            file: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+
+    #[test]
+    fn test_provenance() -> Result<(), Error> {
+        let source = ".ORIG x3000\nLOOP ADD R0, R0, #1\n  PUSH R0\n.FILL LOOP\n\
+                      .BLKW 2\nMSG .STRINGZ \"a\"\n.END";
+        let output = Assembler::new().assemble(source)?;
+        let segment = &output.image.segments[0];
+        assert_eq!(segment.location.map(|location| location.line), Some(1));
+        let kinds = segment
+            .words
+            .iter()
+            .map(|word| word.kind)
+            .collect::<Vec<_>>();
+        use StatementKind::*;
+        assert_eq!(
+            kinds,
+            [
+                Instruction,
+                Expansion,
+                Expansion,
+                Fill,
+                Blkw,
+                Blkw,
+                Stringz,
+                Stringz
+            ]
+        );
+        let push = segment.words[1].location.unwrap();
+        assert_eq!(&source[push.start..push.end], "PUSH R0");
+        assert_eq!((push.line, push.column), (3, 3));
+        assert_eq!(segment.words[2].location, Some(push));
+
+        let source_map = output.image.source_map();
+        assert_eq!(source_map.len(), 8);
+        assert_eq!(source_map[&0x3002].line, 3);
+        assert_eq!(source_map[&0x3007].line, 6);
+        assert_eq!(output.segments, output.image.segments());
+        assert_eq!(output.listing, output.image.listing(source));
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+//! Provides [Instruction] which encodes and decodes a single instruction word.
+use crate::field::FieldError;
+use crate::isa::{self, Format};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Single LC-3 instruction with its operands.
+///
+/// Registers are numbered `0..=7`, and offsets are relative to the incremented PC.
+/// [Instruction::decode] accepts every word, so any memory content can be round-tripped:
+///
+/// ```
+/// use lc3asm::Instruction;
+///
+/// let instruction = Instruction::decode(0x12BF);
+/// assert_eq!(instruction, Instruction::AddImm { dr: 1, sr1: 2, imm5: -1 });
+/// assert_eq!(instruction.to_string(), "ADD R1, R2, #-1");
+/// assert_eq!(instruction.encode(), Ok(0x12BF));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Add {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AddImm {
+        dr: u8,
+        sr1: u8,
+        imm5: i16,
+    },
+    And {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AndImm {
+        dr: u8,
+        sr1: u8,
+        imm5: i16,
+    },
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        pc_offset9: i16,
+    },
+    Jmp {
+        base_r: u8,
+    },
+    Jsr {
+        pc_offset11: i16,
+    },
+    Jsrr {
+        base_r: u8,
+    },
+    Ld {
+        dr: u8,
+        pc_offset9: i16,
+    },
+    Ldi {
+        dr: u8,
+        pc_offset9: i16,
+    },
+    Ldr {
+        dr: u8,
+        base_r: u8,
+        offset6: i16,
+    },
+    Lea {
+        dr: u8,
+        pc_offset9: i16,
+    },
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    Ret,
+    Rti,
+    St {
+        sr: u8,
+        pc_offset9: i16,
+    },
+    Sti {
+        sr: u8,
+        pc_offset9: i16,
+    },
+    Str {
+        sr: u8,
+        base_r: u8,
+        offset6: i16,
+    },
+    Trap {
+        trapvect8: u8,
+    },
+    Nop,
+    /// Word which is not a valid instruction, e.g. one with the reserved opcode `1101`
+    Reserved(u16),
+}
+
+impl Instruction {
+    /// Encodes the instruction into a word.
+    pub fn encode(&self) -> Result<u16, FieldError> {
+        use Instruction::*;
+        let (format, operands): (Format, Vec<i64>) = match *self {
+            Add { dr, sr1, sr2 } => (isa::ADD, vec![dr.into(), sr1.into(), sr2.into()]),
+            AddImm { dr, sr1, imm5 } => (isa::ADD_IMM, vec![dr.into(), sr1.into(), imm5.into()]),
+            And { dr, sr1, sr2 } => (isa::AND, vec![dr.into(), sr1.into(), sr2.into()]),
+            AndImm { dr, sr1, imm5 } => (isa::AND_IMM, vec![dr.into(), sr1.into(), imm5.into()]),
+            Br {
+                n,
+                z,
+                p,
+                pc_offset9,
+            } => {
+                let condition = i64::from(n) << 2 | i64::from(z) << 1 | i64::from(p);
+                (isa::BR, vec![condition, pc_offset9.into()])
+            }
+            Jmp { base_r } => (isa::JMP, vec![base_r.into()]),
+            Jsr { pc_offset11 } => (isa::JSR, vec![pc_offset11.into()]),
+            Jsrr { base_r } => (isa::JSRR, vec![base_r.into()]),
+            Ld { dr, pc_offset9 } => (isa::LD, vec![dr.into(), pc_offset9.into()]),
+            Ldi { dr, pc_offset9 } => (isa::LDI, vec![dr.into(), pc_offset9.into()]),
+            Ldr {
+                dr,
+                base_r,
+                offset6,
+            } => (isa::LDR, vec![dr.into(), base_r.into(), offset6.into()]),
+            Lea { dr, pc_offset9 } => (isa::LEA, vec![dr.into(), pc_offset9.into()]),
+            Not { dr, sr } => (isa::NOT, vec![dr.into(), sr.into()]),
+            Ret => (isa::RET, vec![]),
+            Rti => (isa::RTI, vec![]),
+            St { sr, pc_offset9 } => (isa::ST, vec![sr.into(), pc_offset9.into()]),
+            Sti { sr, pc_offset9 } => (isa::STI, vec![sr.into(), pc_offset9.into()]),
+            Str {
+                sr,
+                base_r,
+                offset6,
+            } => (isa::STR, vec![sr.into(), base_r.into(), offset6.into()]),
+            Trap { trapvect8 } => (isa::TRAP, vec![trapvect8.into()]),
+            Nop => (isa::NOP, vec![]),
+            Reserved(word) => return Ok(word),
+        };
+        format.encode(&operands)
+    }
+
+    /// Decodes a word into the instruction it encodes.
+    pub fn decode(word: u16) -> Instruction {
+        use Instruction::*;
+        let (format, operands) = match isa::decode(word) {
+            Some(decoded) => decoded,
+            None => return Reserved(word),
+        };
+        let register = |i: usize| operands[i] as u8;
+        let offset = |i: usize| operands[i] as i16;
+        match &*format.mnemonic {
+            "ADD" if *format == isa::ADD => Add {
+                dr: register(0),
+                sr1: register(1),
+                sr2: register(2),
+            },
+            "ADD" => AddImm {
+                dr: register(0),
+                sr1: register(1),
+                imm5: offset(2),
+            },
+            "AND" if *format == isa::AND => And {
+                dr: register(0),
+                sr1: register(1),
+                sr2: register(2),
+            },
+            "AND" => AndImm {
+                dr: register(0),
+                sr1: register(1),
+                imm5: offset(2),
+            },
+            "BR" => Br {
+                n: operands[0] & 0b100 != 0,
+                z: operands[0] & 0b010 != 0,
+                p: operands[0] & 0b001 != 0,
+                pc_offset9: offset(1),
+            },
+            "JMP" => Jmp {
+                base_r: register(0),
+            },
+            "JSR" => Jsr {
+                pc_offset11: offset(0),
+            },
+            "JSRR" => Jsrr {
+                base_r: register(0),
+            },
+            "LD" => Ld {
+                dr: register(0),
+                pc_offset9: offset(1),
+            },
+            "LDI" => Ldi {
+                dr: register(0),
+                pc_offset9: offset(1),
+            },
+            "LDR" => Ldr {
+                dr: register(0),
+                base_r: register(1),
+                offset6: offset(2),
+            },
+            "LEA" => Lea {
+                dr: register(0),
+                pc_offset9: offset(1),
+            },
+            "NOT" => Not {
+                dr: register(0),
+                sr: register(1),
+            },
+            "RET" => Ret,
+            "RTI" => Rti,
+            "ST" => St {
+                sr: register(0),
+                pc_offset9: offset(1),
+            },
+            "STI" => Sti {
+                sr: register(0),
+                pc_offset9: offset(1),
+            },
+            "STR" => Str {
+                sr: register(0),
+                base_r: register(1),
+                offset6: offset(2),
+            },
+            "NOP" => Nop,
+            // TRAP and its aliases
+            _ => Trap {
+                trapvect8: word as u8,
+            },
+        }
+    }
+}
+
+impl Instruction {
+    /// Shows the instruction like [Display](fmt::Display), with trap vectors without a
+    /// predefined alias named by given aliases, e.g. [AssemblyOutput::trap_aliases].
+    ///
+    /// ```
+    /// use lc3asm::Instruction;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let aliases = BTreeMap::from([("PUTNUM".to_owned(), 0x26)]);
+    /// assert_eq!(Instruction::decode(0xF026).to_string_with(&aliases), "PUTNUM");
+    /// assert_eq!(Instruction::decode(0xF027).to_string_with(&aliases), "TRAP x27");
+    /// ```
+    ///
+    /// [AssemblyOutput::trap_aliases]: crate::AssemblyOutput::trap_aliases
+    pub fn to_string_with(&self, trap_aliases: &BTreeMap<String, u8>) -> String {
+        let alias = match *self {
+            Instruction::Trap { trapvect8 }
+                if isa::decode(0xF000 | u16::from(trapvect8))
+                    .is_some_and(|(format, _)| *format == isa::TRAP) =>
+            {
+                trap_aliases
+                    .iter()
+                    .find(|(_, vector)| **vector == trapvect8)
+            }
+            _ => None,
+        };
+        match alias {
+            Some((name, _)) => name.clone(),
+            None => self.to_string(),
+        }
+    }
+}
+
+/// Shows the instruction as assembly text which assembles back into the same word.
+///
+/// Trap vectors with an alias are shown with the alias, e.g. `HALT`. Branches without any
+/// condition code and [Instruction::Reserved] words are shown as `.FILL`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Add { dr, sr1, sr2 } => write!(f, "ADD R{}, R{}, R{}", dr, sr1, sr2),
+            AddImm { dr, sr1, imm5 } => write!(f, "ADD R{}, R{}, #{}", dr, sr1, imm5),
+            And { dr, sr1, sr2 } => write!(f, "AND R{}, R{}, R{}", dr, sr1, sr2),
+            AndImm { dr, sr1, imm5 } => write!(f, "AND R{}, R{}, #{}", dr, sr1, imm5),
+            Br {
+                n: false,
+                z: false,
+                p: false,
+                ..
+            } => match self.encode() {
+                Ok(word) => write!(f, ".FILL x{:04X}", word),
+                Err(_) => write!(f, "{:?}", self),
+            },
+            Br {
+                n,
+                z,
+                p,
+                pc_offset9,
+            } => {
+                f.write_str("BR")?;
+                for (flag, name) in [(n, "n"), (z, "z"), (p, "p")] {
+                    if flag {
+                        f.write_str(name)?;
+                    }
+                }
+                write!(f, " #{}", pc_offset9)
+            }
+            Jmp { base_r } => write!(f, "JMP R{}", base_r),
+            Jsr { pc_offset11 } => write!(f, "JSR #{}", pc_offset11),
+            Jsrr { base_r } => write!(f, "JSRR R{}", base_r),
+            Ld { dr, pc_offset9 } => write!(f, "LD R{}, #{}", dr, pc_offset9),
+            Ldi { dr, pc_offset9 } => write!(f, "LDI R{}, #{}", dr, pc_offset9),
+            Ldr {
+                dr,
+                base_r,
+                offset6,
+            } => write!(f, "LDR R{}, R{}, #{}", dr, base_r, offset6),
+            Lea { dr, pc_offset9 } => write!(f, "LEA R{}, #{}", dr, pc_offset9),
+            Not { dr, sr } => write!(f, "NOT R{}, R{}", dr, sr),
+            Ret => f.write_str("RET"),
+            Rti => f.write_str("RTI"),
+            St { sr, pc_offset9 } => write!(f, "ST R{}, #{}", sr, pc_offset9),
+            Sti { sr, pc_offset9 } => write!(f, "STI R{}, #{}", sr, pc_offset9),
+            Str {
+                sr,
+                base_r,
+                offset6,
+            } => write!(f, "STR R{}, R{}, #{}", sr, base_r, offset6),
+            Trap { trapvect8 } => match isa::decode(0xF000 | u16::from(trapvect8)) {
+                Some((format, _)) if *format != isa::TRAP => f.write_str(&format.mnemonic),
+                _ => write!(f, "TRAP x{:02X}", trapvect8),
+            },
+            Nop => f.write_str("NOP"),
+            Reserved(word) => write!(f, ".FILL x{:04X}", word),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+
+    #[test]
+    fn test_round_trip() {
+        for word in 0..=0xFFFF {
+            let instruction = Instruction::decode(word);
+            assert_eq!(instruction.encode(), Ok(word), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn test_display_reassembles() -> Result<(), Error> {
+        let mut source = String::from(".ORIG x0000\n");
+        for word in 0..=0xFFFF {
+            source.push_str(&Instruction::decode(word).to_string());
+            source.push('\n');
+        }
+        source.push_str(".END\n");
+        let output = Assembler::new().assemble(&source)?;
+        let words = &output.segments[0].words;
+        for word in 0..=0xFFFF {
+            assert_eq!(
+                words[usize::from(word)],
+                word,
+                "{}",
+                Instruction::decode(word)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_display() {
+        let cases: &[(u16, &str)] = &[
+            (0x1283, "ADD R1, R2, R3"),
+            (0x0BFE, "BRnp #-2"),
+            (0x0005, ".FILL x0005"),
+            (0x0000, "NOP"),
+            (0x4FFF, "JSR #-1"),
+            (0xC1C0, "RET"),
+            (0xF025, "HALT"),
+            (0xF030, "TRAP x30"),
+            (0xD123, ".FILL xD123"),
+            (0x1208, ".FILL x1208"),
+        ];
+        for (word, text) in cases {
+            assert_eq!(Instruction::decode(*word).to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let add = Instruction::AddImm {
+            dr: 0,
+            sr1: 8,
+            imm5: 0,
+        };
+        assert!(add.encode().unwrap_err().to_string().contains("for SR1"));
+        let ld = Instruction::Ld {
+            dr: 0,
+            pc_offset9: 256,
+        };
+        assert!(ld
+            .encode()
+            .unwrap_err()
+            .to_string()
+            .contains("for PCoffset9"));
+    }
+}
@@ -0,0 +1,482 @@
+//! Provides the declarative description of the LC-3 instruction set.
+//!
+//! Each [Format] lists the opcode and the [Slot]s following it, from the most significant bit.
+//! The assembler encodes instructions with [Format::encode], and [decode] reads them back with
+//! the same table, so both directions always agree.
+use crate::field::{self, Field, FieldError};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// How the value of an operand is written in assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// Register number, e.g. `R3`
+    Register,
+    /// Number or constant, e.g. `#-1`
+    Immediate,
+    /// Offset from the incremented PC, written as a label or number
+    PcOffset,
+    /// Condition codes written as a mnemonic suffix, e.g. `nz` of `BRnz`
+    Condition,
+}
+
+/// Part of an instruction word following the opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slot {
+    /// Bits which always hold given value
+    Fixed { bits: u32, value: u16 },
+    /// Bits holding an operand
+    Operand(OperandKind, Field),
+}
+
+impl Slot {
+    pub fn bits(&self) -> u32 {
+        match self {
+            Slot::Fixed { bits, .. } => *bits,
+            Slot::Operand(_, field) => field.bits,
+        }
+    }
+}
+
+/// Encoding of an instruction with its assembly mnemonic.
+///
+/// Formats of the instruction set borrow static names and slots, while those of extensions read
+/// from text own them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub mnemonic: Cow<'static, str>,
+    pub opcode: u16,
+    /// Slots covering the lower 12 bits of the word
+    pub slots: Cow<'static, [Slot]>,
+}
+
+impl Format {
+    /// Returns the operands of the instruction in the order they are written.
+    pub fn operands(&self) -> impl Iterator<Item = (OperandKind, &Field)> + '_ {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Operand(kind, field) => Some((*kind, field)),
+            Slot::Fixed { .. } => None,
+        })
+    }
+
+    /// Returns whether given word is encoded in this format.
+    pub fn matches(&self, word: u16) -> bool {
+        let mut shift = 12;
+        (word >> 12) == self.opcode
+            && self.slots.iter().all(|slot| {
+                shift -= slot.bits();
+                match slot {
+                    Slot::Fixed { bits, value } => (word >> shift) & mask(*bits) == *value,
+                    Slot::Operand(..) => true,
+                }
+            })
+    }
+
+    /// Encodes the instruction with given operand values.
+    ///
+    /// Panics if the number of values differs from the number of operands.
+    pub fn encode(&self, operands: &[i64]) -> Result<u16, FieldError> {
+        assert_eq!(
+            operands.len(),
+            self.operands().count(),
+            "Wrong number of operands for {}",
+            self.mnemonic
+        );
+        let mut operands = operands.iter();
+        let mut word = self.opcode;
+        for slot in self.slots.iter() {
+            let bits = match slot {
+                Slot::Fixed { value, .. } => *value,
+                Slot::Operand(_, field) => field.encode(*operands.next().unwrap())?,
+            };
+            word = (word << slot.bits()) | bits;
+        }
+        Ok(word)
+    }
+
+    /// Decodes the operand values of given word, which must match this format.
+    pub fn decode(&self, word: u16) -> Vec<i64> {
+        let mut shift = 12;
+        self.slots
+            .iter()
+            .filter_map(|slot| {
+                shift -= slot.bits();
+                match slot {
+                    Slot::Operand(_, field) => Some(field.decode(word >> shift)),
+                    Slot::Fixed { .. } => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Shows the syntax of the instruction, e.g. `ADD DR, SR1, imm5`.
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.mnemonic)?;
+        let mut separator = " ";
+        for (kind, field) in self.operands() {
+            if kind == OperandKind::Condition {
+                f.write_str("[n][z][p]")?;
+            } else {
+                write!(f, "{}{}", separator, field.name)?;
+                separator = ", ";
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mask(bits: u32) -> u16 {
+    ((1u32 << bits) - 1) as u16
+}
+
+const fn fixed(bits: u32, value: u16) -> Slot {
+    Slot::Fixed { bits, value }
+}
+
+const fn register(field: Field) -> Slot {
+    Slot::Operand(OperandKind::Register, field)
+}
+
+const fn immediate(field: Field) -> Slot {
+    Slot::Operand(OperandKind::Immediate, field)
+}
+
+const fn pc_offset(field: Field) -> Slot {
+    Slot::Operand(OperandKind::PcOffset, field)
+}
+
+/// Describes a format of the instruction set, e.g. `instruction!("NOT", 0b1001, &[...])`.
+///
+/// Unlike a `const fn`, the macro lets the slots live as long as the constant it initializes.
+macro_rules! instruction {
+    ($mnemonic:expr, $opcode:expr, &[$($slot:expr),* $(,)?] $(,)?) => {
+        Format {
+            mnemonic: Cow::Borrowed($mnemonic),
+            opcode: $opcode,
+            slots: Cow::Borrowed(&[$($slot),*]),
+        }
+    };
+}
+
+pub const NOP: Format = instruction!("NOP", 0b0000, &[fixed(12, 0)]);
+pub const BR: Format = instruction!(
+    "BR",
+    0b0000,
+    &[
+        Slot::Operand(OperandKind::Condition, field::CONDITION),
+        pc_offset(field::PC_OFFSET9),
+    ],
+);
+pub const ADD: Format = instruction!(
+    "ADD",
+    0b0001,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(3, 0b000),
+        register(field::SR2),
+    ],
+);
+pub const ADD_IMM: Format = instruction!(
+    "ADD",
+    0b0001,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(1, 0b1),
+        immediate(field::IMM5),
+    ],
+);
+pub const LD: Format = instruction!(
+    "LD",
+    0b0010,
+    &[register(field::DR), pc_offset(field::PC_OFFSET9)],
+);
+pub const ST: Format = instruction!(
+    "ST",
+    0b0011,
+    &[register(field::SR), pc_offset(field::PC_OFFSET9)],
+);
+pub const JSR: Format = instruction!(
+    "JSR",
+    0b0100,
+    &[fixed(1, 0b1), pc_offset(field::PC_OFFSET11)],
+);
+pub const JSRR: Format = instruction!(
+    "JSRR",
+    0b0100,
+    &[fixed(3, 0b000), register(field::BASE_R), fixed(6, 0)],
+);
+pub const AND: Format = instruction!(
+    "AND",
+    0b0101,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(3, 0b000),
+        register(field::SR2),
+    ],
+);
+pub const AND_IMM: Format = instruction!(
+    "AND",
+    0b0101,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(1, 0b1),
+        immediate(field::IMM5),
+    ],
+);
+pub const LDR: Format = instruction!(
+    "LDR",
+    0b0110,
+    &[
+        register(field::DR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const STR: Format = instruction!(
+    "STR",
+    0b0111,
+    &[
+        register(field::SR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const RTI: Format = instruction!("RTI", 0b1000, &[fixed(12, 0)]);
+pub const NOT: Format = instruction!(
+    "NOT",
+    0b1001,
+    &[register(field::DR), register(field::SR), fixed(6, 0b111111)],
+);
+pub const LDI: Format = instruction!(
+    "LDI",
+    0b1010,
+    &[register(field::DR), pc_offset(field::PC_OFFSET9)],
+);
+pub const STI: Format = instruction!(
+    "STI",
+    0b1011,
+    &[register(field::SR), pc_offset(field::PC_OFFSET9)],
+);
+pub const RET: Format = instruction!("RET", 0b1100, &[fixed(12, 0b000_111_000000)]);
+pub const JMP: Format = instruction!(
+    "JMP",
+    0b1100,
+    &[fixed(3, 0b000), register(field::BASE_R), fixed(6, 0)],
+);
+pub const LEA: Format = instruction!(
+    "LEA",
+    0b1110,
+    &[register(field::DR), pc_offset(field::PC_OFFSET9)],
+);
+pub const GETC: Format = instruction!("GETC", 0b1111, &[fixed(12, 0x020)]);
+pub const OUT: Format = instruction!("OUT", 0b1111, &[fixed(12, 0x021)]);
+pub const PUTS: Format = instruction!("PUTS", 0b1111, &[fixed(12, 0x022)]);
+pub const IN: Format = instruction!("IN", 0b1111, &[fixed(12, 0x023)]);
+pub const PUTSP: Format = instruction!("PUTSP", 0b1111, &[fixed(12, 0x024)]);
+pub const HALT: Format = instruction!("HALT", 0b1111, &[fixed(12, 0x025)]);
+pub const TRAP: Format = instruction!(
+    "TRAP",
+    0b1111,
+    &[fixed(4, 0b0000), immediate(field::TRAP_VECT8)],
+);
+
+pub const LDB: Format = instruction!(
+    "LDB",
+    0b0010,
+    &[
+        register(field::DR),
+        register(field::BASE_R),
+        immediate(field::BOFFSET6),
+    ],
+);
+pub const STB: Format = instruction!(
+    "STB",
+    0b0011,
+    &[
+        register(field::SR),
+        register(field::BASE_R),
+        immediate(field::BOFFSET6),
+    ],
+);
+pub const LDW: Format = instruction!(
+    "LDW",
+    0b0110,
+    &[
+        register(field::DR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const LDI_BASE: Format = instruction!(
+    "LDI",
+    0b1010,
+    &[
+        register(field::DR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const STI_BASE: Format = instruction!(
+    "STI",
+    0b1011,
+    &[
+        register(field::SR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const STW: Format = instruction!(
+    "STW",
+    0b0111,
+    &[
+        register(field::SR),
+        register(field::BASE_R),
+        immediate(field::OFFSET6),
+    ],
+);
+pub const XOR: Format = instruction!(
+    "XOR",
+    0b1001,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(3, 0b000),
+        register(field::SR2),
+    ],
+);
+pub const XOR_IMM: Format = instruction!(
+    "XOR",
+    0b1001,
+    &[
+        register(field::DR),
+        register(field::SR1),
+        fixed(1, 0b1),
+        immediate(field::IMM5),
+    ],
+);
+pub const LSHF: Format = instruction!(
+    "LSHF",
+    0b1101,
+    &[
+        register(field::DR),
+        register(field::SR),
+        fixed(2, 0b00),
+        immediate(field::AMOUNT4),
+    ],
+);
+pub const RSHFL: Format = instruction!(
+    "RSHFL",
+    0b1101,
+    &[
+        register(field::DR),
+        register(field::SR),
+        fixed(2, 0b01),
+        immediate(field::AMOUNT4),
+    ],
+);
+pub const RSHFA: Format = instruction!(
+    "RSHFA",
+    0b1101,
+    &[
+        register(field::DR),
+        register(field::SR),
+        fixed(2, 0b11),
+        immediate(field::AMOUNT4),
+    ],
+);
+
+/// Every instruction, ordered by opcode.
+///
+/// Formats sharing an opcode are listed from the most specific one, e.g. `RET` before `JMP`,
+/// so the first format matching a word is the preferred way to write it.
+pub const INSTRUCTIONS: &[Format] = &[
+    NOP, BR, ADD, ADD_IMM, LD, ST, JSR, JSRR, AND, AND_IMM, LDR, STR, RTI, NOT, LDI, STI, RET, JMP,
+    LEA, GETC, OUT, PUTS, IN, PUTSP, HALT, TRAP,
+];
+
+/// Every LC-3b instruction, ordered like [INSTRUCTIONS].
+///
+/// `LDW` and `STW` share the encodings of `LDR` and `STR`, and `NOT` is `XOR` with `#-1`.
+/// `LDI` and `STI` address memory with a base register and offset6 like `LDW` and `STW`,
+/// e.g. `LDI R0, R1, #2`, and `LD` and `ST` do not exist.
+pub const LC3B_INSTRUCTIONS: &[Format] = &[
+    NOP, BR, ADD, ADD_IMM, LDB, STB, JSR, JSRR, AND, AND_IMM, LDW, STW, RTI, NOT, XOR, XOR_IMM,
+    LDI_BASE, STI_BASE, RET, JMP, LSHF, RSHFL, RSHFA, LEA, GETC, OUT, PUTS, IN, PUTSP, HALT, TRAP,
+];
+
+/// Returns formats with given mnemonic, ignoring case.
+pub fn formats(mnemonic: &str) -> impl Iterator<Item = &'static Format> + '_ {
+    INSTRUCTIONS
+        .iter()
+        .filter(move |format| format.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+/// Decodes given word into its preferred format and operand values.
+///
+/// Returns [None] if the word is not a valid instruction, e.g. it uses the reserved opcode.
+pub fn decode(word: u16) -> Option<(&'static Format, Vec<i64>)> {
+    INSTRUCTIONS
+        .iter()
+        .find(|format| format.matches(word))
+        .map(|format| (format, format.decode(word)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_cover_words() {
+        for format in INSTRUCTIONS.iter().chain(LC3B_INSTRUCTIONS) {
+            let bits: u32 = format.slots.iter().map(Slot::bits).sum();
+            assert_eq!(bits, 12, "{}", format);
+            assert!(format.opcode <= 0b1111, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let decoded = |word| decode(word).map(|(format, operands)| (format.to_string(), operands));
+        assert_eq!(
+            decoded(0x12BF),
+            Some(("ADD DR, SR1, imm5".to_owned(), vec![1, 2, -1]))
+        );
+        assert_eq!(
+            decoded(0x0BFE),
+            Some(("BR[n][z][p] PCoffset9".to_owned(), vec![0b101, -2]))
+        );
+        assert_eq!(decoded(0xC1C0), Some(("RET".to_owned(), vec![])));
+        assert_eq!(decoded(0xC0C0), Some(("JMP BaseR".to_owned(), vec![3])));
+        assert_eq!(decoded(0xF025), Some(("HALT".to_owned(), vec![])));
+        assert_eq!(
+            decoded(0xF0FF),
+            Some(("TRAP trapvect8".to_owned(), vec![0xFF]))
+        );
+        assert_eq!(decoded(0xD000), None);
+        assert_eq!(decoded(0x1208), None);
+        assert_eq!(decoded(0x8001), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for word in 0..=0xFFFF {
+            if let Some((format, operands)) = decode(word) {
+                assert_eq!(format.encode(&operands), Ok(word), "{}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let adds = formats("add").map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(adds, vec!["ADD DR, SR1, SR2", "ADD DR, SR1, imm5"]);
+        assert_eq!(formats("FOO").count(), 0);
+    }
+}
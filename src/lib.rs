@@ -1,21 +1,73 @@
-#![allow(clippy::inconsistent_digit_grouping, clippy::unreadable_literal)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(
+    clippy::inconsistent_digit_grouping,
+    clippy::unusual_byte_groupings,
+    clippy::unreadable_literal
+)]
+extern crate alloc;
+
+pub use assembler::{
+    Assembler, AssemblyOutput, Diagnostic, ListingLine, Location, OutputFormat, Segment, Severity,
+    TrapAliasError, WarningLevel,
+};
+pub use ast::Node;
+pub use builder::{BuildError, Operand, ProgramBuilder};
+pub use dialect::{Construct, Dialect};
 pub use error::Error;
+pub use field::{Field, FieldError, Signedness};
+pub use image::{Image, ImageSegment, StatementKind, Word};
+pub use instruction::Instruction;
+pub use merge::{merge, MergeConflict, MergeError, MergeInput, MergeOutput};
+pub use object::{
+    ObjectError, ObjectFile, WordDiff, MULTI_SEGMENT_EXTENSION, MULTI_SEGMENT_MAGIC,
+};
+pub use preprocess::Preprocessed;
+pub use target::Target;
+use isa::OperandKind;
 use pest::error::Error as PestError;
 use pest::error::ErrorVariant as PestErrorVariant;
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
-use std::io::{ErrorKind as IOErrorKind, Write};
-use unescape::unescape;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
 
-use crate::util::parse_number_literal;
-use symbol_table::SymbolTable;
-use crate::symbol_table::table_to_string;
+pub use symbol_table::{parse_symbols, MAX_LABEL_LENGTH, RESERVED_WORDS};
+pub use util::{parse_number_literal, LiteralError};
+use resolve::{Symbols, Unresolved};
+use util::unescape;
 
 #[cfg(test)]
 mod asm_tests;
+mod assembler;
+mod ast;
+#[cfg(feature = "std")]
+pub mod build;
+mod builder;
+mod dialect;
+mod embed;
 pub(crate) mod error;
+pub mod extension;
+mod fast;
+pub mod field;
+mod image;
+mod instruction;
+pub mod isa;
+mod merge;
+mod object;
+#[cfg(test)]
+mod parse_tests;
+mod preprocess;
+mod pseudo;
+mod relax;
+mod resolve;
+pub mod stdlib;
+#[cfg(test)]
+mod stdlib_tests;
 mod symbol_table;
+mod target;
 mod util;
 
 /// Parser struct.
@@ -24,75 +76,394 @@ mod util;
 pub struct AsmParser;
 
 /// Reads code from input and produces [Vec] of parsed pairs.
-pub fn parse(input: &str) -> Result<Pairs<Rule>, PestError<Rule>> {
+pub fn parse(input: &str) -> Result<Pairs<'_, Rule>, PestError<Rule>> {
     AsmParser::parse(Rule::file, input)
 }
 
 /// Reads code from input and produces object code output and symbol table.
+///
+/// Warnings are printed to standard error. Use [Assembler] to collect them instead.
+#[cfg(feature = "std")]
 pub fn assemble(input: impl AsRef<str>) -> Result<(Vec<u8>, Vec<u8>), Error> {
-    let asm_parsed = parse(input.as_ref())?.collect();
-    assemble_from_pairs(asm_parsed)
+    let output = Assembler::new().assemble_text(input.as_ref())?;
+    for diagnostic in &output.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    Ok((output.object_bytes(), output.symbol_table()?.into_bytes()))
 }
 
 /// Reads code from slice of pairs and produces object code output.
+///
+/// Warnings are printed to standard error. Use [Assembler] to collect them instead.
+#[cfg(feature = "std")]
 pub fn assemble_from_pairs(asm_parsed: Vec<Pair<Rule>>) -> Result<(Vec<u8>, Vec<u8>), Error> {
-    let (symbols, size, entry) = first_pass(&asm_parsed)?;
-    let buf: Vec<u8> = Vec::with_capacity(size);
-    let mut wr = util::BitVecWriter::new(buf);
+    let output = Assembler::new().assemble_pairs(asm_parsed)?;
+    for diagnostic in &output.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    Ok((output.object_bytes(), output.symbol_table()?.into_bytes()))
+}
 
+pub(crate) fn assemble_with(
+    options: &Assembler,
+    asm_parsed: Vec<Pair<Rule>>,
+) -> Result<AssemblyOutput, Error> {
+    let (mut ctx, size) = first_pass(&asm_parsed, options)?;
+    let input = asm_parsed
+        .first()
+        .map_or("", |pair| pair.as_span().get_input());
+    let mut locator = image::Locator::new(input);
+    let mut segment = ImageSegment {
+        origin: ctx.symbols.origin as u16,
+        location: None,
+        words: Vec::with_capacity(size),
+    };
     for pair in asm_parsed {
-        second_pass(pair, &mut wr, &symbols)?;
-        assert_eq!(
-            wr.count_written().1,
-            0,
-            "Each assembly pass should write aligned bytes to buffer"
-        );
+        let span = pair.as_span();
+        let location = locator.locate(span.start(), span.end());
+        second_pass(pair, &mut segment, location, &mut ctx)?;
+    }
+    if segment.words.len() != size {
+        return Err(PestError::new_from_pos(
+            PestErrorVariant::<Rule>::CustomError {
+                message: format!(
+                    "The second pass wrote {} words where the first pass laid out {}",
+                    segment.words.len(),
+                    size
+                ),
+            },
+            pest::Position::from_start(input),
+        )
+        .into());
+    }
+
+    let image = Image {
+        segments: vec![segment],
+        target: options.target,
+    };
+    Ok(AssemblyOutput {
+        segments: image.segments(),
+        symbols: ctx.symbols.absolute(),
+        diagnostics: ctx.diagnostics,
+        listing: image.listing(input),
+        image,
+        trap_aliases: ctx.trap_aliases,
+        files: vec![preprocess::INPUT.into()],
+        format: options.output_format,
+        target: options.target,
+    })
+}
+
+/// State shared between assembly passes.
+pub(crate) struct Context<'a, 'i> {
+    options: &'a Assembler,
+    symbols: Symbols<'a, Pair<'i, Rule>>,
+    externals: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Start of each instruction rewritten by [relax]
+    relaxed: BTreeSet<usize>,
+    /// Instructions which may be relaxed, with their target and offset
+    relaxable: Vec<(Pair<'i, Rule>, Pair<'i, Rule>, usize)>,
+    /// Vector of each trap alias, keyed by its name in uppercase
+    trap_aliases: BTreeMap<String, u8>,
+}
+
+impl<'a, 'i> Context<'a, 'i> {
+    fn new(options: &'a Assembler) -> Self {
+        Context {
+            options,
+            symbols: Symbols::new(options),
+            externals: BTreeSet::new(),
+            diagnostics: Vec::new(),
+            relaxed: BTreeSet::new(),
+            relaxable: Vec::new(),
+            trap_aliases: options.trap_aliases.clone(),
+        }
+    }
+
+    /// Records a warning, or fails if warnings are denied.
+    fn warn(&mut self, pair: &Pair<Rule>, message: String) -> Result<(), Error> {
+        match self.options.warnings {
+            WarningLevel::Allow => Ok(()),
+            WarningLevel::Warn => {
+                self.diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    location: Some(pair.into()),
+                    message,
+                    file: None,
+                });
+                Ok(())
+            }
+            WarningLevel::Deny => Err(pair_error_message!(pair, "{}", message).into()),
+        }
+    }
+
+    /// Records a note, unless warnings are allowed.
+    fn note(&mut self, pair: &Pair<Rule>, message: String) {
+        if self.options.warnings != WarningLevel::Allow {
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Note,
+                location: Some(pair.into()),
+                message,
+                file: None,
+            });
+        }
+    }
+
+    /// Resolves a number literal, label or constant into its value.
+    /// Labels are resolved into their address.
+    fn value(&self, pair: &Pair<Rule>) -> Result<i64, Error> {
+        if pair.as_rule() != Rule::label {
+            return parse_number_literal(pair.as_str()).map_err(|e| {
+                pair_error_message!(pair, "Invalid number literal {}: {}", pair.as_str(), e).into()
+            });
+        }
+        self.symbols
+            .value(pair.as_str())
+            .ok_or_else(|| self.unknown_symbol(pair))
+    }
+
+    /// Resolves a value like [Context::value] and checks whether it fits in given field.
+    fn field_value(&self, field: Field, pair: &Pair<Rule>) -> Result<u16, Error> {
+        field
+            .encode(self.value(pair)?)
+            .map_err(|e| pair_error_message!(pair, "{}", e).into())
+    }
+
+    /// Resolves the number of words reserved by `.BLKW`, which must be a number literal or a
+    /// constant, as addresses of labels are only known once the program is laid out.
+    fn blkw_count(&self, pair: &Pair<Rule>) -> Result<u16, Error> {
+        if pair.as_rule() == Rule::label && self.symbols.label(pair.as_str()).is_some() {
+            return Err(pair_error_message!(
+                pair,
+                "Count of .BLKW must be a number or a constant, found label {}",
+                pair.as_str(),
+            )
+            .into());
+        }
+        self.field_value(field::BLKW, pair)
+    }
+
+    /// Resolves the target of a PC-relative operand into its offset from the PC incremented
+    /// past the instruction at given offset.
+    fn pc_offset(&self, pair: &Pair<Rule>, offset: usize) -> Result<i64, Error> {
+        match util::parse_pc_pair(pair).map_err(|e| -> Error {
+            pair_error_message!(pair, "Invalid number literal {}: {}", pair.as_str(), e).into()
+        })? {
+            util::PCOffsetTarget::Symbol(symbol) => self
+                .symbols
+                .pc_offset(&symbol, offset)
+                .map_err(|err| match err {
+                    Unresolved::Missing => self.unknown_symbol(pair),
+                    Unresolved::Misaligned(address) => {
+                        pair_error_message!(pair, "{}", resolve::misaligned(&symbol, address))
+                            .into()
+                    }
+                }),
+            util::PCOffsetTarget::ExplicitOffset(value) => Ok(value),
+        }
     }
 
-    let buf = wr.into_inner().into_writer();
-    assert_eq!(buf.len(), size * 2 + 2);
+    /// Returns the word of given trap alias or extension without operands.
+    fn operandless_word(&self, name: &str) -> Option<u16> {
+        if let Some(vector) = self.trap_aliases.get(&name.to_ascii_uppercase()) {
+            return Some(0xF000 | u16::from(*vector));
+        }
+        self.options
+            .extension_formats()
+            .find(|format| {
+                format.mnemonic.eq_ignore_ascii_case(name) && format.operands().next().is_none()
+            })
+            .map(|format| format.encode(&[]).unwrap())
+    }
 
-    let table = table_to_string(symbols, entry)?;
+    fn unknown_symbol(&self, pair: &Pair<Rule>) -> Error {
+        if self
+            .externals
+            .contains(&self.options.symbol_key(pair.as_str()))
+        {
+            return pair_error_message!(
+                pair,
+                "Cannot resolve external symbol {} without linking",
+                pair.as_str(),
+            )
+            .into();
+        }
+        pair_error_message!(
+            pair,
+            "Cannot find symbol {}, available symbols: {}",
+            pair.as_str(),
+            self.symbols
+                .labels
+                .values()
+                .map(|(_, pair)| pair.as_str())
+                .chain(self.options.constants.keys().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .into()
+    }
+}
 
-    Ok((buf, table.into_bytes()))
+/// Lays out the program and collects its symbols. If [Assembler::relax_branches] is set,
+/// out-of-range instructions are relaxed until every label offset is stable.
+fn first_pass<'a, 'i>(
+    pairs: &[Pair<'i, Rule>],
+    options: &'a Assembler,
+) -> Result<(Context<'a, 'i>, usize), Error> {
+    let mut relaxed = BTreeSet::new();
+    loop {
+        let (ctx, size) = layout(pairs, options, relaxed.clone())?;
+        // Relaxing only grows the program, so each iteration adds instructions until none is
+        // left out of range. Unresolved labels are reported by the second pass.
+        let out_of_range = ctx
+            .relaxable
+            .iter()
+            .filter(|(instruction, target, offset)| {
+                let (format, _) = instruction_format(instruction, options)
+                    .expect("Relaxable instructions are checked by the first pass");
+                let (_, field) = format
+                    .operands()
+                    .find(|(kind, _)| *kind == OperandKind::PcOffset)
+                    .unwrap();
+                ctx.pc_offset(target, *offset)
+                    .is_ok_and(|value| field.encode(value).is_err())
+            })
+            .map(|(instruction, _, _)| instruction.as_span().start())
+            .collect::<Vec<_>>();
+        if out_of_range.is_empty() {
+            return Ok((ctx, size));
+        }
+        relaxed.extend(out_of_range);
+    }
 }
 
-fn first_pass<'i>(pairs: &[Pair<'i, Rule>]) -> Result<(SymbolTable<'i>, usize, usize), Error> {
-    let mut symbols = SymbolTable::new();
+/// Computes the offset of each label, with instructions starting at given positions relaxed.
+fn layout<'a, 'i>(
+    pairs: &[Pair<'i, Rule>],
+    options: &'a Assembler,
+    relaxed: BTreeSet<usize>,
+) -> Result<(Context<'a, 'i>, usize), Error> {
+    let mut ctx = Context::new(options);
+    ctx.relaxed = relaxed;
     let mut offset = 0;
     let mut entry: Option<usize> = None;
 
     for pair in pairs {
+        check_dialect(pair, &mut ctx)?;
+        if let Some(bare) = bare_instruction(pair, &ctx) {
+            check_construct(&bare.mnemonic, bare.construct(), &mut ctx)?;
+            if let Some(label) = bare.label {
+                declare_label(&mut ctx, label, offset)?;
+            }
+            offset += 1;
+            continue;
+        }
         match pair.as_rule() {
             Rule::orig => {
                 if entry.is_some() {
                     panic!("Cannot have multiple .ORIG pseudo-operation");
                 }
-                entry = Some(
-                    parse_number_literal(pair.clone().into_inner().next().unwrap().as_str())? as usize,
-                );
-            }
-            Rule::label_decl => {
-                let name = pair.as_str();
-                if let Some((_, prev_pair)) = symbols.get(name) {
+                let address = pair.clone().into_inner().next().unwrap();
+                let address = ctx.field_value(field::ORIG, &address)?;
+                let origin = usize::from(options.origin.unwrap_or(address));
+                if !origin.is_multiple_of(options.target.word_size()) {
                     return Err(pair_error_message!(
-                        pair.clone(),
-                        "Duplicate symbol definition\n{}",
-                        pair_error_message!(
-                            prev_pair.clone(),
-                            "Note: First definition of the symbol was here",
-                        )
+                        pair,
+                        "Origin of {} program must be aligned to a word",
+                        options.target,
                     )
                     .into());
                 }
-                symbols.insert(name.into(), (offset, pair.clone()));
+                entry = Some(usize::from(address));
+            }
+            Rule::label_decl => {
+                let label = pair.clone().into_inner().next().unwrap();
+                declare_label(&mut ctx, label, offset)?;
+            }
+            Rule::external => {
+                let label = pair.clone().into_inner().next().unwrap();
+                symbol_table::check_label(&label, options)?;
+                ctx.externals.insert(options.symbol_key(label.as_str()));
+            }
+            Rule::trapdef => {
+                if let [name, vector] = collect_inner!(pair.clone()) {
+                    symbol_table::check_label(name, options)?;
+                    let vector = ctx.field_value(field::TRAP_VECT8, vector)? as u8;
+                    let key = name.as_str().to_ascii_uppercase();
+                    let defined = if ctx.trap_aliases.contains_key(&key) {
+                        Some("trap alias")
+                    } else if ctx
+                        .symbols
+                        .labels
+                        .values()
+                        .any(|(_, label)| label.as_str().eq_ignore_ascii_case(&key))
+                    {
+                        Some("label")
+                    } else if ctx
+                        .symbols
+                        .constants
+                        .contains_key(&options.symbol_key(name.as_str()))
+                    {
+                        Some("constant")
+                    } else {
+                        None
+                    };
+                    if let Some(kind) = defined {
+                        return Err(pair_error_message!(
+                            name,
+                            "Symbol {} is already defined as a {}",
+                            name.as_str(),
+                            kind,
+                        )
+                        .into());
+                    }
+                    ctx.trap_aliases.insert(key, vector);
+                } else {
+                    unreachable!("{}", pair);
+                }
+            }
+            Rule::instruction | Rule::extension => {
+                let instruction = match pair.as_rule() {
+                    Rule::instruction => pair.clone().into_inner().next().unwrap(),
+                    _ => pair.clone(),
+                };
+                let (format, operands) = instruction_format(&instruction, options)?;
+                if pair.as_rule() == Rule::extension {
+                    let mnemonic = pair.clone().into_inner().next().unwrap();
+                    check_construct(&mnemonic, Construct::Extension, &mut ctx)?;
+                }
+                if ctx.relaxed.contains(&instruction.as_span().start()) {
+                    let condition = match instruction.as_rule() {
+                        Rule::br => condition(&operands[0]),
+                        _ => 0,
+                    };
+                    offset += relax::size(format, condition);
+                } else {
+                    match operands.last() {
+                        Some(target)
+                            if options.relax_branches
+                                && options.target == Target::Lc3
+                                && relax::relaxable(format)
+                                && target.as_rule() == Rule::label =>
+                        {
+                            ctx.relaxable
+                                .push((instruction.clone(), target.clone(), offset));
+                        }
+                        _ => (),
+                    }
+                    offset += 1;
+                }
             }
-            Rule::instruction | Rule::trap_code | Rule::fill => offset += 1,
+            Rule::pseudo_instruction => {
+                offset += pseudo::size(pair.clone().into_inner().next().unwrap().as_rule());
+            }
+            Rule::trap_code | Rule::fill => offset += 1,
             Rule::blkw => {
                 if let [content] = collect_inner!(pair.clone()) {
-                    offset += util::parse_number_literal(content.as_str())? as usize;
+                    offset += usize::from(ctx.blkw_count(content)?);
                 } else {
-                    unreachable!(pair);
+                    unreachable!("{}", pair);
                 }
             }
             Rule::stringz => {
@@ -106,9 +477,7 @@ fn first_pass<'i>(pairs: &[Pair<'i, Rule>]) -> Result<(SymbolTable<'i>, usize, u
                     .unwrap()
                     .as_str();
                 match unescape(string) {
-                    Some(us) => {
-                        offset += us.bytes().len() + 1;
-                    }
+                    Some(us) => offset += resolve::string_size(us.len(), options.target),
                     None => {
                         return Err(
                             pair_error_message!(pair.clone(), "Invalid escape sequence",).into(),
@@ -120,215 +489,187 @@ fn first_pass<'i>(pairs: &[Pair<'i, Rule>]) -> Result<(SymbolTable<'i>, usize, u
         }
     }
 
-    Ok((
-        symbols,
-        offset,
-        entry.expect("Expected a .ORIG pseudo-operation for file"),
-    ))
+    let entry = entry.expect("Expected a .ORIG pseudo-operation for file");
+    ctx.symbols.origin = options.origin.map_or(entry, usize::from);
+    Ok((ctx, offset))
 }
 
-fn second_pass<W: Write>(
-    pair: Pair<Rule>,
-    wr: &mut util::BitVecWriter<W>,
-    symbols: &SymbolTable,
+/// Adds a label at given offset.
+fn declare_label<'i>(
+    ctx: &mut Context<'_, 'i>,
+    label: Pair<'i, Rule>,
+    offset: usize,
 ) -> Result<(), Error> {
-    match pair.as_rule() {
-        Rule::orig => {
-            wr.write(16, util::parse_number_literal(pair.into_inner().as_str())?)?;
-        }
-
-        Rule::instruction => {
-            for inner_pair in pair.into_inner() {
-                second_pass(inner_pair, wr, symbols)?;
-            }
-        }
+    symbol_table::check_label(&label, ctx.options)?;
+    let name = ctx.options.symbol_key(label.as_str());
+    let defined = if ctx.symbols.constants.contains_key(&name) {
+        Some("constant")
+    } else if ctx
+        .trap_aliases
+        .contains_key(&label.as_str().to_ascii_uppercase())
+    {
+        Some("trap alias")
+    } else {
+        None
+    };
+    if let Some(kind) = defined {
+        return Err(pair_error_message!(
+            label,
+            "Symbol {} is already defined as a {}",
+            label.as_str(),
+            kind,
+        )
+        .into());
+    }
+    if let Some((_, prev_pair)) = ctx.symbols.labels.get(&name) {
+        return Err(pair_error_message!(
+            label,
+            "Duplicate symbol definition\n{}",
+            pair_error_message!(
+                prev_pair.clone(),
+                "Note: First definition of the symbol was here",
+            )
+        )
+        .into());
+    }
+    ctx.symbols.labels.insert(name, (offset, label));
+    Ok(())
+}
 
-        rule @ Rule::add | rule @ Rule::and => {
-            if let [dr, sr1, sr2] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 4, if rule == Rule::add { 0b0001 } else { 0b0101 }],
-                    [register destination_register; dr],
-                    [register source_register1; sr1],
-                    [const; 3, 0b000],
-                    [register source_register2; sr2],
-                );
-            } else {
-                unreachable!();
-            }
-        }
+/// Instruction without operands which the grammar reads as a label or an extension, as trap
+/// aliases and extensions are only known when assembling.
+struct BareInstruction<'i> {
+    /// Label preceding the instruction, e.g. `LOOP` of `LOOP PUTNUM`
+    label: Option<Pair<'i, Rule>>,
+    mnemonic: Pair<'i, Rule>,
+    word: u16,
+}
 
-        rule @ Rule::add_immd | rule @ Rule::and_immd => {
-            if let [dr, sr1, immd5] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 4, if rule == Rule::add_immd { 0b0001 } else { 0b0101 }],
-                    [register destination_register; dr],
-                    [register source_register1; sr1],
-                    [bool; true],
-                    [number_signed immediate; 5, immd5],
-                );
-            } else {
-                unreachable!();
-            }
+impl BareInstruction<'_> {
+    /// Returns whether the instruction is a trap alias or an extension.
+    fn construct(&self) -> Construct {
+        if self.word >> 12 == 0xF {
+            Construct::TrapAlias
+        } else {
+            Construct::Extension
         }
+    }
+}
 
-        Rule::not => {
-            if let [dr, sr] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 4, 0b1001],
-                    [register destination_register; dr],
-                    [register source_register; sr],
-                    [const; 6, 0b111111]
-                );
-            } else {
-                unreachable!();
+/// Reads a word alone on its line, or the second of two words, as an instruction if it names a
+/// trap alias or an extension without operands, e.g. `PUTNUM` or `LOOP PUTNUM`.
+fn bare_instruction<'i>(pair: &Pair<'i, Rule>, ctx: &Context) -> Option<BareInstruction<'i>> {
+    match pair.as_rule() {
+        Rule::label_decl if !pair.as_str().ends_with(':') => {
+            let span = pair.as_span();
+            let rest = span.get_input()[span.end()..]
+                .lines()
+                .next()
+                .unwrap_or_default();
+            if !rest.split(';').next().unwrap().trim().is_empty() {
+                return None;
             }
+            Some(BareInstruction {
+                label: None,
+                mnemonic: pair.clone(),
+                word: ctx.operandless_word(pair.as_str())?,
+            })
         }
-
-        Rule::br => {
-            if let [br_option, label] = collect_inner!(pair) {
-                let br_option = br_option.as_str();
-                let (n, z, p) = (
-                    br_option.find('n').is_some(),
-                    br_option.find('z').is_some(),
-                    br_option.find('p').is_some(),
-                );
-                let implicit_unconditional_branch = (n, z, p) == (false, false, false);
-                if implicit_unconditional_branch {
-                    // TODO: Print position
-                    eprintln!("Warning: Use BRnzp instead of BR for clarity");
+        Rule::extension => {
+            let mut inner = pair.clone().into_inner();
+            let mnemonic = inner.next().unwrap();
+            match inner.collect::<Vec<_>>().as_slice() {
+                [] => Some(BareInstruction {
+                    label: None,
+                    mnemonic: mnemonic.clone(),
+                    word: 0xF000
+                        | u16::from(
+                            *ctx.trap_aliases
+                                .get(&mnemonic.as_str().to_ascii_uppercase())?,
+                        ),
+                }),
+                [operand]
+                    if operand.as_rule() == Rule::label
+                        && !ctx.options.extension_formats().any(|format| {
+                            format.mnemonic.eq_ignore_ascii_case(mnemonic.as_str())
+                        }) =>
+                {
+                    Some(BareInstruction {
+                        word: ctx.operandless_word(operand.as_str())?,
+                        mnemonic: operand.clone(),
+                        label: Some(mnemonic),
+                    })
                 }
-                write_fields!(
-                    wr,
-                    [const; 4, 0b0000],
-                    [bool; n||implicit_unconditional_branch],
-                    [bool; z||implicit_unconditional_branch],
-                    [bool; p||implicit_unconditional_branch],
-                    [pcoffset; 9, label, symbols],
-                );
-            } else {
-                unreachable!();
-            }
-        }
-
-        Rule::jmp => {
-            if let [br] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 7, 0b1100_000],
-                    [register base_register; br],
-                    [const; 6, 0b000000]
-                );
-            } else {
-                unreachable!();
+                _ => None,
             }
         }
+        _ => None,
+    }
+}
 
-        Rule::jsr => {
-            if let [pcoffset] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 5, 0b0100_1],
-                    [pcoffset; 11, pcoffset, symbols],
-                );
-            } else {
-                unreachable!();
-            }
+/// Rejects constructs not accepted by the configured dialect, and warns about
+/// non-portable constructs in strict mode.
+fn check_dialect(pair: &Pair<Rule>, ctx: &mut Context) -> Result<(), Error> {
+    for inner_pair in core::iter::once(pair.clone()).chain(pair.clone().into_inner().flatten()) {
+        if let Some(construct) = Construct::of(&inner_pair) {
+            check_construct(&inner_pair, construct, ctx)?;
         }
+    }
+    Ok(())
+}
 
-        Rule::jsrr => {
-            if let [br] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 7, 0b0100_000],
-                    [register base_register; br],
-                    [const; 6, 0b000000],
-                );
-            } else {
-                unreachable!();
-            }
-        }
+/// Rejects given construct if the configured dialect does not accept it, and warns about it in
+/// strict mode.
+fn check_construct(
+    pair: &Pair<Rule>,
+    construct: Construct,
+    ctx: &mut Context,
+) -> Result<(), Error> {
+    let dialect = ctx.options.dialect;
+    if !dialect.accepts(construct) {
+        return Err(pair_error_message!(
+            pair,
+            "{} is not accepted by {} dialect",
+            construct,
+            dialect,
+        )
+        .into());
+    }
+    if ctx.options.strict {
+        ctx.warn(pair, format!("{} is not portable", construct))?;
+    }
+    Ok(())
+}
 
-        rule @ Rule::ld
-        | rule @ Rule::ldi
-        | rule @ Rule::lea
-        | rule @ Rule::st
-        | rule @ Rule::sti => {
-            if let [dosr, label] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 4, match rule {
-                        Rule::ld => 0b0010,
-                        Rule::ldi => 0b1010,
-                        Rule::lea => 0b1110,
-                        Rule::st => 0b0011,
-                        Rule::sti => 0b1011,
-                        _ => unreachable!()
-                    }],
-                    [register destination_or_source_register; dosr],
-                    [pcoffset; 9, label, symbols],
-                );
-            } else {
-                unreachable!();
-            }
-        }
+fn second_pass(
+    pair: Pair<Rule>,
+    segment: &mut ImageSegment,
+    location: Location,
+    ctx: &mut Context,
+) -> Result<(), Error> {
+    if let Some(bare) = bare_instruction(&pair, ctx) {
+        segment.push(bare.word, StatementKind::Instruction, Some(location));
+        return Ok(());
+    }
+    match pair.as_rule() {
+        Rule::orig => segment.location = Some(location),
 
-        rule @ Rule::ldr | rule @ Rule::str => {
-            if let [dosr, br, offset_] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 4, if rule == Rule::ldr { 0b0110 } else { 0b0111 }],
-                    [register destination_or_source_register; dosr],
-                    [register base_register; br],
-                    [number_signed offset; 6, offset_],
-                );
-            } else {
-                unreachable!();
+        Rule::instruction => {
+            for inner_pair in pair.into_inner() {
+                encode_instruction(inner_pair, segment, location, ctx)?;
             }
         }
 
-        Rule::ret => {
-            write_fields!(wr, [const; 16, 0b1100_000_111_000000]);
-        }
-
-        Rule::rti => {
-            write_fields!(wr, [const; 16, 0b1000_0000_0000_0000]);
-        }
-
-        Rule::trap => {
-            if let [trap_vect] = collect_inner!(pair) {
-                write_fields!(
-                    wr,
-                    [const; 8, 0b1111_0000],
-                    [number_signed trap_vector; 8, trap_vect],
-                );
-            } else {
-                unreachable!();
+        Rule::pseudo_instruction => {
+            for inner_pair in pair.into_inner() {
+                encode_pseudo_instruction(inner_pair, segment, location, ctx)?;
             }
         }
 
-        Rule::trap_code => {
-            write_fields!(
-            wr,
-            [const; 8, 0b1111_0000],
-            [const; 8,
-                match pair.as_str().to_lowercase().trim() {
-                    "getc" => 0x20,
-                    "out" => 0x21,
-                    "puts" => 0x22,
-                    "in" => 0x23,
-                    "putsp" => 0x24,
-                    "halt" => 0x25,
-                    _ => unreachable!(),
-                }
-            ],
-            );
-        }
+        Rule::trap_code | Rule::extension => encode_instruction(pair, segment, location, ctx)?,
 
         Rule::stringz => {
-            unescape(
+            let string = unescape(
                 pair.into_inner()
                     .next()
                     .unwrap()
@@ -337,27 +678,16 @@ fn second_pass<W: Write>(
                     .unwrap()
                     .as_str(),
             )
-            .unwrap()
-            .as_bytes()
-            .iter()
-            .chain(&[0])
-            .cloned()
-            .map(u16::from)
-            .map(|x| wr.write(16, x))
-            .collect::<Result<(), _>>()?;
-        }
-
-        Rule::nop => {
-            write_fields!(
-                wr,
-                [const; 16, 0b0000_0000_0000_0000],
-            );
+            .unwrap();
+            for word in resolve::string_words(string.as_bytes(), ctx.options.target) {
+                segment.push(word, StatementKind::Stringz, Some(location));
+            }
         }
 
         Rule::blkw => {
             if let [blocks] = collect_inner!(pair) {
-                for _ in 0..util::parse_number_literal(blocks.as_str()).unwrap() {
-                    write_fields!(wr, [const; 16, 0u16]);
+                for _ in 0..ctx.blkw_count(blocks)? {
+                    segment.push(0, StatementKind::Blkw, Some(location));
                 }
             } else {
                 unreachable!();
@@ -366,7 +696,10 @@ fn second_pass<W: Write>(
 
         Rule::fill => {
             if let [content] = collect_inner!(pair) {
-                write_fields!(wr, [number_signed fill_content; 16, content]);
+                let word = field::FILL
+                    .encode(ctx.value(content)?)
+                    .map_err(|e| -> Error { pair_error_message!(content, "{}", e).into() })?;
+                segment.push(word, StatementKind::Fill, Some(location));
             } else {
                 unreachable!();
             }
@@ -375,7 +708,219 @@ fn second_pass<W: Write>(
         Rule::end => (),
         Rule::EOI => (),
         Rule::label_decl => (),
+        Rule::external => (),
+        Rule::trapdef => (),
+        Rule::text_after_end => (),
         _ => unreachable!("{:#?}", pair),
     }
     Ok(())
 }
+
+/// Encodes an instruction in its format described by [isa::INSTRUCTIONS] or an [Extension].
+///
+/// [Extension]: extension::Extension
+fn encode_instruction(
+    pair: Pair<Rule>,
+    segment: &mut ImageSegment,
+    location: Location,
+    ctx: &mut Context,
+) -> Result<(), Error> {
+    let (format, operands) = instruction_format(&pair, ctx.options)?;
+    let relaxed = ctx.relaxed.contains(&pair.as_span().start());
+    let offset = segment.words.len();
+    let mut values = Vec::with_capacity(operands.len());
+    let mut target = 0;
+    for ((kind, field), operand) in format.operands().zip(&operands) {
+        let value = match kind {
+            OperandKind::Register => util::parse_register_literal(operand.as_str())?,
+            OperandKind::Immediate => ctx.value(operand)?,
+            OperandKind::PcOffset if relaxed => {
+                target = ctx.value(operand)? as u16;
+                values.push(0);
+                continue;
+            }
+            OperandKind::PcOffset => ctx.pc_offset(operand, offset)?,
+            OperandKind::Condition => {
+                if operand.as_str().is_empty() {
+                    ctx.warn(&pair, "Use BRnzp instead of BR for clarity".into())?;
+                }
+                condition(operand)
+            }
+        };
+        field
+            .encode(value)
+            .map_err(|e| -> Error { pair_error_message!(operand, "{}", e).into() })?;
+        values.push(value);
+    }
+    if relaxed {
+        // Only branches need a scratch register, which must be chosen as it is overwritten
+        let overwritten = match (*format == isa::BR, ctx.options.scratch_register) {
+            (false, _) => String::new(),
+            (true, Some(scratch)) => format!(", overwriting R{}", scratch),
+            (true, None) => {
+                return Err(pair_error_message!(
+                    pair,
+                    "Relaxing out-of-range {} overwrites a scratch register, which must be set \
+                     with Assembler::scratch_register",
+                    pair.as_str(),
+                )
+                .into())
+            }
+        };
+        let words = relax::expand(format, &values, target, ctx.options.scratch());
+        ctx.note(
+            &pair,
+            format!(
+                "Relaxed out-of-range {} into {} words{}",
+                pair.as_str(),
+                words.len(),
+                overwritten
+            ),
+        );
+        // The expansion ends with the address literal
+        let (literal, instructions) = words.split_last().unwrap();
+        for word in instructions {
+            segment.push(*word, StatementKind::Expansion, Some(location));
+        }
+        segment.push(*literal, StatementKind::Fill, Some(location));
+    } else {
+        let word = format
+            .encode(&values)
+            .map_err(|e| -> Error { pair_error_message!(pair, "{}", e).into() })?;
+        segment.push(word, StatementKind::Instruction, Some(location));
+    }
+    Ok(())
+}
+
+/// Encodes a pseudo-instruction into the instructions it expands into.
+fn encode_pseudo_instruction(
+    pair: Pair<Rule>,
+    segment: &mut ImageSegment,
+    location: Location,
+    ctx: &mut Context,
+) -> Result<(), Error> {
+    let offset = segment.words.len();
+    let mut registers = Vec::new();
+    let mut pc_offset = 0;
+    for operand in pair.clone().into_inner() {
+        if operand.as_rule() == Rule::register {
+            match util::parse_register_literal(operand.as_str())? {
+                register @ 0..=7 => registers.push(register as u8),
+                _ => {
+                    return Err(pair_error_message!(
+                        operand,
+                        "Invalid register {}, expected R0..R7",
+                        operand.as_str()
+                    )
+                    .into())
+                }
+            }
+        } else {
+            let value = ctx.pc_offset(&operand, offset + pseudo::CALL_JSR)?;
+            field::PC_OFFSET11
+                .encode(value)
+                .map_err(|e| -> Error { pair_error_message!(operand, "{}", e).into() })?;
+            pc_offset = value as i16;
+        }
+    }
+    let instructions = pseudo::expand(
+        pair.as_rule(),
+        &registers,
+        pc_offset,
+        ctx.options.scratch_register,
+        ctx.options.target,
+    )
+    .map_err(|message| -> Error { pair_error_message!(pair, "{}", message).into() })?;
+    for instruction in instructions {
+        let word = instruction
+            .encode()
+            .unwrap_or_else(|e| unreachable!("{}: {}", instruction, e));
+        segment.push(word, StatementKind::Expansion, Some(location));
+    }
+    Ok(())
+}
+
+/// Finds the format of given instruction from its mnemonic and operands, among instructions of
+/// the target and extensions of LC-3.
+fn instruction_format<'a, 'i>(
+    pair: &Pair<'i, Rule>,
+    options: &'a Assembler,
+) -> Result<(&'a isa::Format, Vec<Pair<'i, Rule>>), Error> {
+    let target = options.target;
+    let mut operands = pair.clone().into_inner().collect::<Vec<_>>();
+    let mnemonic_pair = match pair.as_rule() {
+        Rule::extension => Some(operands.remove(0)),
+        _ => None,
+    };
+    let mnemonic = match pair.as_rule() {
+        Rule::br => "BR",
+        _ => pair.as_str().split_whitespace().next().unwrap(),
+    };
+    let extensions = options
+        .extension_formats()
+        .filter(|format| format.mnemonic.eq_ignore_ascii_case(mnemonic));
+    let format = target
+        .formats(mnemonic)
+        .map(|format| -> &isa::Format { format })
+        .chain(extensions)
+        .find(|format| {
+            format.operands().count() == operands.len()
+                && format
+                    .operands()
+                    .zip(&operands)
+                    .all(|((kind, _), operand)| {
+                        (kind == OperandKind::Register) == (operand.as_rule() == Rule::register)
+                    })
+        })
+        .ok_or_else(|| -> Error {
+            let message = format!(
+                "{} is not an instruction of {}",
+                mnemonic.to_uppercase(),
+                target
+            );
+            let known = target.formats(mnemonic).next().is_some()
+                || options
+                    .extension_formats()
+                    .any(|format| format.mnemonic.eq_ignore_ascii_case(mnemonic));
+            match mnemonic_pair {
+                // The grammar reads any unknown word followed by operands as an extension
+                Some(mnemonic_pair) if !known => {
+                    let mnemonics = target
+                        .instructions()
+                        .iter()
+                        .chain(options.extension_formats())
+                        .map(|format| &*format.mnemonic)
+                        .chain(options.trap_aliases.keys().map(String::as_str))
+                        .chain(extension::PSEUDO_INSTRUCTIONS.iter().copied());
+                    match util::closest(mnemonic, mnemonics) {
+                        Some(closest) => pair_error_message!(
+                            mnemonic_pair,
+                            "{}, did you mean {}?",
+                            message,
+                            closest
+                        ),
+                        None => pair_error_message!(mnemonic_pair, "{}", message),
+                    }
+                    .into()
+                }
+                _ => pair_error_message!(pair, "{}", message).into(),
+            }
+        })?;
+    Ok((format, operands))
+}
+
+/// Returns the condition codes given by the `br_option` of a branch, all of them if there are
+/// none.
+fn condition(operand: &Pair<Rule>) -> i64 {
+    match operand.as_str() {
+        "" => 0b111,
+        flags => flags
+            .chars()
+            .map(|flag| match flag {
+                'n' => 0b100,
+                'z' => 0b010,
+                _ => 0b001,
+            })
+            .sum(),
+    }
+}
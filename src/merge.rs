@@ -0,0 +1,389 @@
+//! Provides [merge], which combines object files and their symbol tables into one memory image,
+//! e.g. an operating system, its trap vector table and the user programs it runs.
+use crate::assembler::Segment;
+use crate::error::Error;
+use crate::object::ObjectFile;
+use crate::symbol_table::format_symbols;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Object file to [merge] with its symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeInput {
+    /// Name of the input in conflicts, e.g. its file name
+    pub name: String,
+    pub object: ObjectFile,
+    /// Absolute address of each symbol, e.g. read with [parse_symbols](crate::parse_symbols)
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl MergeInput {
+    /// Reads the object file at given path and the symbol table next to it, if any, e.g.
+    /// `os.sym` for `os.obj`.
+    #[cfg(feature = "std")]
+    pub fn read(path: impl AsRef<Path>) -> Result<MergeInput, Error> {
+        let path = path.as_ref();
+        let sym_path = path.with_extension("sym");
+        let symbols = if sym_path.is_file() {
+            crate::parse_symbols(&fs::read_to_string(sym_path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(MergeInput {
+            name: path.display().to_string(),
+            object: ObjectFile::read(path)?,
+            symbols,
+        })
+    }
+}
+
+/// Reason inputs of [merge] cannot be combined, or a warning about them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Two inputs define a symbol at different addresses
+    Symbol {
+        name: String,
+        first: (String, u16),
+        second: (String, u16),
+    },
+    /// Two inputs load the same addresses
+    Overlap {
+        first: String,
+        second: String,
+        /// Inclusive range of the shared addresses
+        range: (u16, u16),
+        /// Symbols of either input within the range
+        symbols: Vec<String>,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeConflict::Symbol {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} is defined at x{:04X} by {} and at x{:04X} by {}",
+                name, first.1, first.0, second.1, second.0
+            ),
+            MergeConflict::Overlap {
+                first,
+                second,
+                range,
+                symbols,
+            } => {
+                write!(
+                    f,
+                    "{} and {} both load x{:04X}-x{:04X}",
+                    first, second, range.0, range.1
+                )?;
+                if !symbols.is_empty() {
+                    write!(f, " ({})", symbols.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Error returned when inputs of [merge] conflict, listing every conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeError {
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, conflict) in self.conflicts.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", conflict)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MergeError {}
+
+/// Result of [merge].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutput {
+    /// Segments of every input in order, starting at the origin of the first input
+    pub object: ObjectFile,
+    /// Symbols of every input, qualified with the input name, e.g. `user.obj::LOOP`, where
+    /// inputs define them at different addresses
+    pub symbols: BTreeMap<String, u16>,
+    /// Symbols defined at different addresses by several inputs
+    pub warnings: Vec<MergeConflict>,
+}
+
+impl MergeOutput {
+    /// Returns the whole 64K memory as one segment at `x0000`, with addresses no input loads
+    /// set to zero.
+    ///
+    /// Unlike [MergeOutput::object], the image is an object file of one segment, which any
+    /// simulator loads.
+    pub fn full_image(&self) -> ObjectFile {
+        let mut memory = vec![0; 0x10000];
+        for (address, word) in self.object.words() {
+            memory[usize::from(address)] = word;
+        }
+        ObjectFile::new(vec![Segment {
+            origin: 0,
+            words: memory,
+        }])
+        .expect("A segment at x0000 fits the address space")
+    }
+
+    /// Returns the symbol table in `.sym` file format.
+    pub fn symbol_table(&self) -> Result<String, Error> {
+        Ok(format_symbols(&self.symbols)?)
+    }
+}
+
+/// Combines the segments and symbols of given inputs.
+///
+/// Inputs must not load the same address, otherwise [Error::Merge] lists every overlapping range.
+/// Symbols defined by several inputs at the same address, as trap aliases listed in each symbol
+/// table are, are merged. Those defined at different addresses, e.g. `LOOP` in two programs, are
+/// kept for each input as `<input name>::<symbol>` and listed in [MergeOutput::warnings].
+/// Whitespace in input names is replaced with `_`, so that the symbol table lists each symbol as
+/// one word.
+///
+/// ```
+/// use lc3asm::{merge, Assembler, MergeInput, ObjectFile};
+///
+/// let input = |name: &str, source: &str| {
+///     let output = Assembler::new().assemble(source).unwrap();
+///     MergeInput {
+///         name: name.to_owned(),
+///         object: ObjectFile::parse(&output.object_bytes()).unwrap(),
+///         symbols: output.symbols,
+///     }
+/// };
+/// let os = input("os", ".ORIG x0200\nOS_START HALT\n.END");
+/// let user = input("user", ".ORIG x3000\nMAIN HALT\n.END");
+/// let output = merge(&[os, user]).unwrap();
+/// assert_eq!(output.object.segments().len(), 2);
+/// assert_eq!(output.symbols["MAIN"], 0x3000);
+/// assert_eq!(output.full_image().words().nth(0x3000), Some((0x3000, 0xF025)));
+/// ```
+pub fn merge(inputs: &[MergeInput]) -> Result<MergeOutput, Error> {
+    let mut definitions = BTreeMap::<&str, Vec<(u16, &str)>>::new();
+    for input in inputs {
+        for (name, &address) in &input.symbols {
+            definitions
+                .entry(name)
+                .or_default()
+                .push((address, &input.name));
+        }
+    }
+    let mut symbols = BTreeMap::new();
+    let mut warnings = Vec::new();
+    for (name, defined) in definitions {
+        let (address, first) = defined[0];
+        if defined.iter().all(|&(other, _)| other == address) {
+            symbols.insert(name.into(), address);
+            continue;
+        }
+        for &(other, input) in &defined {
+            let input_name = input
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c })
+                .collect::<String>();
+            symbols.insert(format!("{}::{}", input_name, name), other);
+            if other != address {
+                warnings.push(MergeConflict::Symbol {
+                    name: name.into(),
+                    first: (first.into(), address),
+                    second: (input.into(), other),
+                });
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for (index, first) in inputs.iter().enumerate() {
+        for second in &inputs[index + 1..] {
+            for (start, end) in overlaps(&first.object, &second.object) {
+                let mut names = first
+                    .symbols
+                    .iter()
+                    .chain(&second.symbols)
+                    .filter(|(_, &address)| (start..=end).contains(&address))
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                conflicts.push(MergeConflict::Overlap {
+                    first: first.name.clone(),
+                    second: second.name.clone(),
+                    range: (start, end),
+                    symbols: names,
+                });
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(MergeError { conflicts }.into());
+    }
+
+    let segments = inputs
+        .iter()
+        .flat_map(|input| input.object.segments().iter().cloned())
+        .collect();
+    Ok(MergeOutput {
+        object: ObjectFile::new(segments)?,
+        symbols,
+        warnings,
+    })
+}
+
+/// Returns the inclusive ranges of addresses loaded by both object files.
+fn overlaps(first: &ObjectFile, second: &ObjectFile) -> Vec<(u16, u16)> {
+    let ranges = |object: &ObjectFile| {
+        object
+            .segments()
+            .iter()
+            .filter(|segment| !segment.words.is_empty())
+            .map(|segment| {
+                (
+                    segment.origin,
+                    segment.origin + (segment.words.len() - 1) as u16,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut overlaps = Vec::new();
+    for (first_start, first_end) in ranges(first) {
+        for &(second_start, second_end) in &ranges(second) {
+            let (start, end) = (first_start.max(second_start), first_end.min(second_end));
+            if start <= end {
+                overlaps.push((start, end));
+            }
+        }
+    }
+    overlaps.sort_unstable();
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+    use alloc::borrow::ToOwned;
+    use alloc::string::ToString;
+
+    fn input(name: &str, source: &str) -> Result<MergeInput, Error> {
+        let output = Assembler::new().assemble(source)?;
+        Ok(MergeInput {
+            name: name.to_owned(),
+            object: ObjectFile::parse(&output.object_bytes())?,
+            symbols: crate::parse_symbols(&output.symbol_table()?)?,
+        })
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), Error> {
+        let vectors = input("vectors", ".ORIG x0020\n.FILL x0400\n.END")?;
+        let os = input(
+            "os",
+            ".ORIG x0400\n.TRAPDEF PUTNUM, x26\nGETC_SR .FILL x1234\nRTI\n.END",
+        )?;
+        let user = input(
+            "user",
+            ".ORIG x3000\n.TRAPDEF PUTNUM, x26\nMAIN PUTNUM\nHALT\n.END",
+        )?;
+        let output = merge(&[vectors, os, user])?;
+        assert_eq!(output.object.origin(), 0x0020);
+        assert_eq!(output.object.segments().len(), 3);
+        assert_eq!(output.symbols["PUTNUM"], 0x0026);
+        assert_eq!(output.symbols["MAIN"], 0x3000);
+        assert!(output.warnings.is_empty());
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&output.object.to_multi_segment_bytes())?,
+            output.object
+        );
+
+        let image = output.full_image();
+        assert_eq!(image.origin(), 0);
+        assert_eq!(image.to_bytes()?.len(), 2 + 0x20000);
+        let words = image.words().map(|(_, word)| word).collect::<Vec<_>>();
+        assert_eq!(words[0x0020], 0x0400);
+        assert_eq!(words[0x0400..0x0402], [0x1234, 0x8000]);
+        assert_eq!(words[0x3000..0x3002], [0xF026, 0xF025]);
+        assert_eq!(words[0x3002], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicts() -> Result<(), Error> {
+        let os = input(
+            "os.obj",
+            ".ORIG x3000\nOS_START ADD R0, R0, #1\nMAIN RTI\n.END",
+        )?;
+        let user = input("user.obj", ".ORIG x3001\nMAIN .BLKW 3\n.END")?;
+        match merge(&[os, user]) {
+            Err(Error::Merge(err)) => assert_eq!(
+                err.conflicts,
+                [MergeConflict::Overlap {
+                    first: "os.obj".to_owned(),
+                    second: "user.obj".to_owned(),
+                    range: (0x3001, 0x3001),
+                    symbols: vec!["MAIN".to_owned()],
+                }]
+            ),
+            result => panic!("{:?}", result),
+        }
+
+        let os = input("os.obj", ".ORIG x3000\nMAIN HALT\n.END")?;
+        let user = input("user.obj", ".ORIG x2FFF\nMAIN .BLKW 2\n.END")?;
+        assert_eq!(
+            merge(&[os, user]).unwrap_err().to_string(),
+            "os.obj and user.obj both load x3000-x3000 (MAIN)"
+        );
+
+        let a = input("a", ".ORIG x3000\nLOOP BR LOOP\nSHARED HALT\n.END")?;
+        let b = input("b", ".ORIG x4000\nLOOP BR LOOP\n.END")?;
+        let c = input("my c", ".ORIG x5000\nLOOP BR LOOP\n.END")?;
+        let output = merge(&[a, b, c])?;
+        assert_eq!(output.symbols["a::LOOP"], 0x3000);
+        assert_eq!(output.symbols["b::LOOP"], 0x4000);
+        assert_eq!(output.symbols["my_c::LOOP"], 0x5000);
+        assert_eq!(output.symbols["SHARED"], 0x3001);
+        assert!(!output.symbols.contains_key("LOOP"));
+        assert_eq!(
+            output
+                .warnings
+                .iter()
+                .map(|warning| warning.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "LOOP is defined at x3000 by a and at x4000 by b",
+                "LOOP is defined at x3000 by a and at x5000 by my c"
+            ]
+        );
+        assert_eq!(
+            crate::parse_symbols(&output.symbol_table()?)?,
+            output.symbols
+        );
+        assert!(matches!(
+            merge(&[]),
+            Err(Error::Object(crate::ObjectError::Empty))
+        ));
+        Ok(())
+    }
+}
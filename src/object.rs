@@ -0,0 +1,504 @@
+//! Provides [ObjectFile], which reads, validates and writes LC-3 object files.
+//!
+//! An object file holds big-endian words: the origin followed by the words loaded from it, as
+//! [AssemblyOutput::object_bytes](crate::AssemblyOutput::object_bytes) writes them. As the words
+//! do not tell where another segment would start, several segments are stored in a separate
+//! format, named with [MULTI_SEGMENT_EXTENSION], which starts with [MULTI_SEGMENT_MAGIC] and
+//! holds the origin, length and words of each segment. Any words may start a plain object file,
+//! so the format is chosen by the extension and never guessed from the contents.
+//!
+//! LC-3b object files are text, and are not read.
+use crate::assembler::Segment;
+#[cfg(feature = "std")]
+use crate::error::Error;
+use crate::instruction::Instruction;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// First words of object files of several segments, `LC3M` in ASCII.
+pub const MULTI_SEGMENT_MAGIC: [u16; 2] = [0x4C43, 0x334D];
+
+/// Extension of object files of several segments, e.g. `all.lc3m`.
+pub const MULTI_SEGMENT_EXTENSION: &str = "lc3m";
+
+/// Error returned when an object file is malformed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectError {
+    /// The object file has no segment, or no origin
+    Empty,
+    /// The object file does not hold whole words
+    OddLength(usize),
+    /// A segment declares more words than remain in the object file
+    Truncated { origin: u16 },
+    /// A segment runs past the end of the address space
+    Overflow { origin: u16, len: usize },
+    /// Two segments share addresses, given as inclusive ranges
+    Overlap {
+        first: (u16, u16),
+        second: (u16, u16),
+    },
+    /// A line of a symbol table is not a symbol name followed by a hexadecimal address, see
+    /// [parse_symbols](crate::parse_symbols)
+    InvalidSymbol { line: usize },
+    /// An object file of several segments does not start with [MULTI_SEGMENT_MAGIC]
+    MissingMagic,
+    /// An object file of given number of segments is written as a plain object file
+    MultipleSegments(usize),
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::Empty => f.write_str("object file is empty"),
+            ObjectError::OddLength(len) => {
+                write!(
+                    f,
+                    "object file length of {} bytes is not a whole number of words",
+                    len
+                )
+            }
+            ObjectError::Truncated { origin } => {
+                write!(f, "segment at x{:04X} is truncated", origin)
+            }
+            ObjectError::Overflow { origin, len } => write!(
+                f,
+                "segment at x{:04X} of {} words runs past xFFFF",
+                origin, len
+            ),
+            ObjectError::Overlap { first, second } => write!(
+                f,
+                "segments x{:04X}-x{:04X} and x{:04X}-x{:04X} overlap",
+                first.0, first.1, second.0, second.1
+            ),
+            ObjectError::InvalidSymbol { line } => {
+                write!(f, "line {} of the symbol table is not a symbol entry", line)
+            }
+            ObjectError::MissingMagic => {
+                f.write_str("object file of several segments does not start with LC3M")
+            }
+            ObjectError::MultipleSegments(count) => write!(
+                f,
+                "object file of {} segments must be written as a .{} file",
+                count, MULTI_SEGMENT_EXTENSION
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ObjectError {}
+
+/// Validated LC-3 object file of one or more segments.
+///
+/// ```
+/// use lc3asm::ObjectFile;
+///
+/// let object = ObjectFile::parse(&[0x30, 0x00, 0x10, 0x21, 0xF0, 0x25]).unwrap();
+/// assert_eq!(object.origin(), 0x3000);
+/// assert_eq!(object.words().collect::<Vec<_>>(), [(0x3000, 0x1021), (0x3001, 0xF025)]);
+/// assert_eq!(object.format_disassembly(), "x3000  x1021  ADD R0, R0, #1\nx3001  xF025  HALT\n");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectFile {
+    segments: Vec<Segment>,
+}
+
+impl ObjectFile {
+    /// Creates an object file of given segments, which must fit in the address space without
+    /// overlapping.
+    pub fn new(segments: Vec<Segment>) -> Result<ObjectFile, ObjectError> {
+        if segments.is_empty() {
+            return Err(ObjectError::Empty);
+        }
+        let mut ranges = Vec::new();
+        for segment in &segments {
+            let len = segment.words.len();
+            if usize::from(segment.origin) + len > 0x10000 {
+                return Err(ObjectError::Overflow {
+                    origin: segment.origin,
+                    len,
+                });
+            }
+            if len > 0 {
+                ranges.push((segment.origin, segment.origin + (len - 1) as u16));
+            }
+        }
+        ranges.sort_unstable();
+        if let Some(pair) = ranges.windows(2).find(|pair| pair[1].0 <= pair[0].1) {
+            return Err(ObjectError::Overlap {
+                first: pair[0],
+                second: pair[1],
+            });
+        }
+        Ok(ObjectFile { segments })
+    }
+
+    /// Reads a plain object file of one segment.
+    pub fn parse(bytes: &[u8]) -> Result<ObjectFile, ObjectError> {
+        let words = read_words(bytes)?;
+        let segments = match words.split_first() {
+            Some((origin, words)) => alloc::vec![Segment {
+                origin: *origin,
+                words: words.to_vec(),
+            }],
+            None => Vec::new(),
+        };
+        ObjectFile::new(segments)
+    }
+
+    /// Reads an object file of several segments, which starts with [MULTI_SEGMENT_MAGIC].
+    pub fn parse_multi_segment(bytes: &[u8]) -> Result<ObjectFile, ObjectError> {
+        let words = read_words(bytes)?;
+        let mut rest = words
+            .strip_prefix(&MULTI_SEGMENT_MAGIC[..])
+            .ok_or(ObjectError::MissingMagic)?;
+        let mut segments = Vec::new();
+        while let [origin, len, tail @ ..] = rest {
+            let len = usize::from(*len);
+            if tail.len() < len {
+                return Err(ObjectError::Truncated { origin: *origin });
+            }
+            segments.push(Segment {
+                origin: *origin,
+                words: tail[..len].to_vec(),
+            });
+            rest = &tail[len..];
+        }
+        if let [origin] = rest {
+            return Err(ObjectError::Truncated { origin: *origin });
+        }
+        ObjectFile::new(segments)
+    }
+
+    /// Reads and parses the object file at given path, of several segments if its extension is
+    /// [MULTI_SEGMENT_EXTENSION].
+    #[cfg(feature = "std")]
+    pub fn read(path: impl AsRef<Path>) -> Result<ObjectFile, Error> {
+        let bytes = fs::read(&path)?;
+        if is_multi_segment(path.as_ref()) {
+            Ok(ObjectFile::parse_multi_segment(&bytes)?)
+        } else {
+            Ok(ObjectFile::parse(&bytes)?)
+        }
+    }
+
+    /// Origin of the first segment.
+    pub fn origin(&self) -> u16 {
+        self.segments[0].origin
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns each word with its address, segment by segment.
+    pub fn words(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.segments.iter().flat_map(|segment| {
+            segment
+                .words
+                .iter()
+                .enumerate()
+                .map(move |(index, word)| (segment.origin + index as u16, *word))
+        })
+    }
+
+    /// Returns the contents of a plain object file, which holds one segment.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectError> {
+        match &self.segments[..] {
+            [segment] => Ok(write_words(
+                core::iter::once(segment.origin).chain(segment.words.iter().copied()),
+            )),
+            segments => Err(ObjectError::MultipleSegments(segments.len())),
+        }
+    }
+
+    /// Returns the contents of an object file of several segments, read by
+    /// [ObjectFile::parse_multi_segment].
+    pub fn to_multi_segment_bytes(&self) -> Vec<u8> {
+        write_words(
+            MULTI_SEGMENT_MAGIC
+                .iter()
+                .copied()
+                .chain(self.segments.iter().flat_map(|segment| {
+                    IntoIterator::into_iter([segment.origin, segment.words.len() as u16])
+                        .chain(segment.words.iter().copied())
+                })),
+        )
+    }
+
+    /// Writes the object file to given path, of several segments if its extension is
+    /// [MULTI_SEGMENT_EXTENSION].
+    #[cfg(feature = "std")]
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let bytes = if is_multi_segment(path.as_ref()) {
+            self.to_multi_segment_bytes()
+        } else {
+            self.to_bytes()?
+        };
+        Ok(fs::write(path, bytes)?)
+    }
+
+    /// Returns the address and value of each word, one per line, e.g. `x3000  x1021`.
+    pub fn format_hex(&self) -> String {
+        self.words()
+            .map(|(address, word)| format!("x{:04X}  x{:04X}\n", address, word))
+            .collect()
+    }
+
+    /// Returns the address, value and instruction of each word, one per line, e.g.
+    /// `x3000  x1021  ADD R0, R0, #1`. Data words are shown as the instruction they encode.
+    pub fn format_disassembly(&self) -> String {
+        self.words()
+            .map(|(address, word)| {
+                format!(
+                    "x{:04X}  x{:04X}  {}\n",
+                    address,
+                    word,
+                    Instruction::decode(word)
+                )
+            })
+            .collect()
+    }
+
+    /// Returns each address whose word differs between the object files, or which only one of
+    /// them loads, in increasing order.
+    pub fn diff(&self, other: &ObjectFile) -> Vec<WordDiff> {
+        let mut words = BTreeMap::new();
+        for (address, word) in self.words() {
+            words.insert(address, (Some(word), None));
+        }
+        for (address, word) in other.words() {
+            words.entry(address).or_insert((None, None)).1 = Some(word);
+        }
+        words
+            .into_iter()
+            .filter(|(_, (left, right))| left != right)
+            .map(|(address, (left, right))| WordDiff {
+                address,
+                left,
+                right,
+            })
+            .collect()
+    }
+}
+
+fn read_words(bytes: &[u8]) -> Result<Vec<u16>, ObjectError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(ObjectError::OddLength(bytes.len()));
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+fn write_words(words: impl Iterator<Item = u16>) -> Vec<u8> {
+    words.flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[cfg(feature = "std")]
+fn is_multi_segment(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == MULTI_SEGMENT_EXTENSION)
+}
+
+/// Address at which two object files differ, see [ObjectFile::diff].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WordDiff {
+    pub address: u16,
+    /// Word of the first object file, `None` if it does not load the address
+    pub left: Option<u16>,
+    /// Word of the second object file, `None` if it does not load the address
+    pub right: Option<u16>,
+}
+
+/// Shows the word of each object file on its own line, prefixed with `-` and `+`:
+///
+/// ```text
+/// x3001  - x1021  ADD R0, R0, #1
+/// x3001  + x1022  ADD R0, R0, #2
+/// ```
+impl fmt::Display for WordDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = [('-', self.left), ('+', self.right)];
+        let mut lines = lines
+            .iter()
+            .filter_map(|(sign, word)| Some((sign, (*word)?)))
+            .peekable();
+        while let Some((sign, word)) = lines.next() {
+            write!(
+                f,
+                "x{:04X}  {} x{:04X}  {}",
+                self.address,
+                sign,
+                word,
+                Instruction::decode(word)
+            )?;
+            if lines.peek().is_some() {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_parse() -> Result<(), Error> {
+        let output = Assembler::new().assemble(".ORIG x3000\nADD R0, R0, #1\nHALT\n.END")?;
+        let object = ObjectFile::parse(&output.object_bytes())?;
+        assert_eq!(object.segments(), &output.segments[..]);
+        assert_eq!(object.to_bytes()?, output.object_bytes());
+        assert_eq!(object.format_hex(), "x3000  x1021\nx3001  xF025\n");
+
+        let empty = ObjectFile::parse(&[0x30, 0x00])?;
+        assert_eq!(empty.origin(), 0x3000);
+        assert_eq!(empty.words().count(), 0);
+
+        assert_eq!(ObjectFile::parse(&[]), Err(ObjectError::Empty));
+        assert_eq!(
+            ObjectFile::parse(&[0x30, 0x00, 0x10]),
+            Err(ObjectError::OddLength(3))
+        );
+        assert_eq!(
+            ObjectFile::parse(&[0xFF, 0xFF, 0x10, 0x21, 0x10, 0x21]),
+            Err(ObjectError::Overflow {
+                origin: 0xFFFF,
+                len: 2
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_magic_words() -> Result<(), Error> {
+        // ST R1, #-179 encodes as the second word of the magic
+        let output = Assembler::new().assemble(".ORIG x4C43\nST R1, #-179\nHALT\n.END")?;
+        let bytes = output.object_bytes();
+        assert_eq!(bytes[..4], [0x4C, 0x43, 0x33, 0x4D]);
+        let object = ObjectFile::parse(&bytes)?;
+        assert_eq!(object.segments(), &output.segments[..]);
+        assert_eq!(
+            object.format_disassembly(),
+            "x4C43  x334D  ST R1, #-179\nx4C44  xF025  HALT\n"
+        );
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&bytes),
+            Err(ObjectError::Truncated { origin: 0xF025 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_segment() -> Result<(), Error> {
+        let object = ObjectFile::new(vec![
+            Segment {
+                origin: 0x3000,
+                words: vec![0xF025],
+            },
+            Segment {
+                origin: 0x0025,
+                words: vec![0x0400, 0x0450],
+            },
+        ])?;
+        assert_eq!(object.to_bytes(), Err(ObjectError::MultipleSegments(2)));
+        let bytes = object.to_multi_segment_bytes();
+        assert_eq!(
+            bytes,
+            [
+                0x4C, 0x43, 0x33, 0x4D, 0x30, 0x00, 0x00, 0x01, 0xF0, 0x25, 0x00, 0x25, 0x00, 0x02,
+                0x04, 0x00, 0x04, 0x50
+            ]
+        );
+        assert_eq!(ObjectFile::parse_multi_segment(&bytes)?, object);
+        assert_eq!(object.origin(), 0x3000);
+        assert_eq!(
+            object.words().collect::<Vec<_>>(),
+            [(0x3000, 0xF025), (0x0025, 0x0400), (0x0026, 0x0450)]
+        );
+
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&bytes[..bytes.len() - 2]),
+            Err(ObjectError::Truncated { origin: 0x0025 })
+        );
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&bytes[..12]),
+            Err(ObjectError::Truncated { origin: 0x0025 })
+        );
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&bytes[..4]),
+            Err(ObjectError::Empty)
+        );
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&bytes[4..]),
+            Err(ObjectError::MissingMagic)
+        );
+        let single = ObjectFile::parse(&[0x30, 0x00, 0xF0, 0x25])?;
+        assert_eq!(
+            ObjectFile::parse_multi_segment(&single.to_multi_segment_bytes())?,
+            single
+        );
+        let overlap = ObjectFile::new(vec![
+            Segment {
+                origin: 0x3000,
+                words: vec![0; 0x10],
+            },
+            Segment {
+                origin: 0x300F,
+                words: vec![0; 2],
+            },
+        ]);
+        assert_eq!(
+            overlap.unwrap_err().to_string(),
+            "segments x3000-x300F and x300F-x3010 overlap"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff() -> Result<(), Error> {
+        let assemble = |source: &str| -> Result<ObjectFile, Error> {
+            Ok(ObjectFile::parse(
+                &Assembler::new().assemble(source)?.object_bytes(),
+            )?)
+        };
+        let old = assemble(".ORIG x3000\nADD R0, R0, #1\nHALT\n.END")?;
+        let new = assemble(".ORIG x3000\nADD R0, R0, #2\nHALT\n.FILL 0\n.END")?;
+        assert!(old.diff(&old).is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            [
+                WordDiff {
+                    address: 0x3000,
+                    left: Some(0x1021),
+                    right: Some(0x1022)
+                },
+                WordDiff {
+                    address: 0x3002,
+                    left: None,
+                    right: Some(0x0000)
+                }
+            ]
+        );
+        assert_eq!(
+            diff[0].to_string(),
+            "x3000  - x1021  ADD R0, R0, #1\nx3000  + x1022  ADD R0, R0, #2"
+        );
+        assert_eq!(diff[1].to_string(), "x3002  + x0000  NOP");
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use super::*;
+use pest::error::LineColLocation;
+
+fn error_line(source: &str) -> usize {
+    match assemble(source).unwrap_err() {
+        Error::Pest(err) => match err.line_col {
+            LineColLocation::Pos((line, _)) | LineColLocation::Span((line, _), _) => line,
+        },
+        err => panic!("Unexpected error {}", err),
+    }
+}
+
+#[test]
+fn test_missing_operand_reports_its_line() {
+    assert_eq!(error_line(".ORIG x3000\nADD R1, R2,\nLOOP HALT\n.END"), 2);
+    assert_eq!(error_line(".ORIG x3000\nLD R1,\nLOOP HALT\n.END"), 2);
+}
+
+#[test]
+fn test_statement_does_not_continue_on_next_line() {
+    assert_eq!(error_line(".ORIG x3000\nADD R1,\n  R2, R3\n.END"), 2);
+    assert_eq!(error_line(".ORIG x3000\nBRnzp\nLOOP\n.END"), 2);
+}
+
+#[test]
+fn test_one_statement_per_line() {
+    assert_eq!(
+        error_line(".ORIG x3000\nADD R0, R0, #1 ADD R0, R0, #1\n.END"),
+        2
+    );
+    assert_eq!(error_line(".ORIG x3000\nA B HALT\n.END"), 2);
+}
+
+#[test]
+fn test_label_resolved_by_position() -> Result<(), Error> {
+    let output = Assembler::new().assemble(
+        ".ORIG x3000\n\
+         INPUT   HALT\n\
+         HALT\n\
+         OUTPUT\n\
+         RETURN  RET ; comment\n\
+         \n\
+         ; comment line\n\
+         DONE .END\n",
+    )?;
+    assert_eq!(output.segments[0].words, vec![0xF025, 0xF025, 0xC1C0]);
+    assert_eq!(
+        output.symbols.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("DONE".to_owned(), 0x3003),
+            ("INPUT".to_owned(), 0x3000),
+            ("OUTPUT".to_owned(), 0x3002),
+            ("RETURN".to_owned(), 0x3002),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_line_endings() -> Result<(), Error> {
+    let output = Assembler::new().assemble("\r\n.ORIG x3000\r\nHALT\r\n.END\r\n")?;
+    assert_eq!(output.segments[0].words, vec![0xF025]);
+    Ok(())
+}
+
+#[test]
+fn test_number_literals() -> Result<(), Error> {
+    let output = Assembler::new().assemble(
+        ".ORIG 0x30_00\n\
+         ADD R0, R0, b-1\n\
+         AND R1, R1, o7\n\
+         .FILL 'A'\n\
+         .FILL '\\n'\n\
+         .FILL b1111_0000\n\
+         TRAP 0x25\n\
+         .END",
+    )?;
+    assert_eq!(output.segments[0].origin, 0x3000);
+    assert_eq!(
+        output.segments[0].words,
+        vec![0x103F, 0x5267, 0x0041, 0x000A, 0x00F0, 0xF025]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_invalid_literal_is_an_error() {
+    assert_eq!(error_line(".ORIG x3000\n.FILL ''\n.END"), 2);
+}
@@ -0,0 +1,406 @@
+//! Provides [Preprocessed] source code, whose `.INCLUDE` directives are expanded while the file
+//! and line each line was read from is recorded.
+//!
+//! Positions in the expanded text are mapped back through these origins, so diagnostics,
+//! listings and source maps report positions in the file a statement was written in.
+use crate::assembler::{Assembler, AssemblyOutput, Diagnostic, Location, Severity, WarningLevel};
+use crate::dialect::Construct;
+use crate::error::Error;
+use crate::Rule;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use pest::error::{Error as PestError, ErrorVariant as PestErrorVariant, InputLocation};
+use pest::{Position, Span};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// Name of source code given as text rather than read from a file.
+pub(crate) const INPUT: &str = "<input>";
+
+/// Directory of an included file, which does not exist without a filesystem.
+#[cfg(not(feature = "std"))]
+pub(crate) enum Path {}
+
+/// File read while preprocessing.
+#[derive(Debug, Clone)]
+struct SourceFile {
+    /// Name of the file, as written in the `.INCLUDE` directive for included files
+    name: String,
+    text: String,
+}
+
+/// Origin of a line of the expanded text.
+#[derive(Debug, Clone, Copy)]
+struct LineOrigin {
+    /// Byte offset of the line in the expanded text
+    start: usize,
+    /// Index of the file the line was read from
+    file: usize,
+    /// Byte offset of the line in its file
+    offset: usize,
+    /// 1-based line number in its file
+    line: usize,
+}
+
+/// Source code with its `.INCLUDE` directives expanded, returned by [Assembler::preprocess].
+#[derive(Debug, Clone)]
+pub struct Preprocessed {
+    text: String,
+    files: Vec<SourceFile>,
+    /// Origin of each line of the text
+    lines: Vec<LineOrigin>,
+    /// Warnings about `.INCLUDE` directives, located in their files
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Preprocessed {
+    /// Returns the source code with included files expanded in place of their `.INCLUDE`.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the name of each file read, the preprocessed source first, e.g. `lib.asm` or
+    /// `<std/io>` for included files.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|file| file.name.as_str())
+    }
+
+    fn add_file(&mut self, name: String, text: String) -> usize {
+        self.files.push(SourceFile { name, text });
+        self.files.len() - 1
+    }
+
+    /// Whether anything was included, so that positions in the text differ from positions in
+    /// the files.
+    fn is_expanded(&self) -> bool {
+        self.files.len() > 1
+    }
+
+    /// Returns the origin of the line holding given byte offset of the text.
+    fn origin(&self, offset: usize) -> Option<LineOrigin> {
+        let index = self.lines.partition_point(|line| line.start <= offset);
+        self.lines.get(index.saturating_sub(1)).copied()
+    }
+
+    /// Maps a span of the text into the file it was read from, returning the origin of its line
+    /// with the span in the file.
+    fn map_span(&self, start: usize, end: usize) -> Option<(LineOrigin, usize, usize)> {
+        let origin = self.origin(start)?;
+        let len = self.files[origin.file].text.len();
+        // Statements never span lines, and the line ending added after an included file without
+        // one is mapped to its end
+        let mapped = (origin.offset + start - origin.start).min(len);
+        Some((origin, mapped, (mapped + end - start).min(len)))
+    }
+
+    /// Maps a location in the text into the file it was read from.
+    pub(crate) fn locate(&self, location: Location) -> Location {
+        if !self.is_expanded() {
+            return location;
+        }
+        match self.map_span(location.start, location.end) {
+            Some((origin, start, end)) => Location {
+                start,
+                end,
+                line: origin.line,
+                column: location.column,
+                file: origin.file,
+            },
+            None => location,
+        }
+    }
+
+    /// Maps the positions of a syntax or assembly error into the file it was read from, naming
+    /// the file if it was included.
+    pub(crate) fn map_error(&self, err: Error) -> Error {
+        let err = match err {
+            Error::Pest(err) if self.is_expanded() => err,
+            err => return err,
+        };
+        let (start, end) = match err.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+        let (origin, start, end) = match self.map_span(start, end) {
+            Some(span) => span,
+            None => return Error::Pest(err),
+        };
+        let path = err.path().map(ToOwned::to_owned);
+        let text = &self.files[origin.file].text;
+        let mapped = match err.location {
+            InputLocation::Pos(_) => {
+                PestError::new_from_pos(err.variant, Position::new(text, start).unwrap())
+            }
+            InputLocation::Span(_) => {
+                PestError::new_from_span(err.variant, Span::new(text, start, end).unwrap())
+            }
+        };
+        Error::Pest(match (origin.file, path) {
+            (0, Some(path)) => mapped.with_path(&path),
+            (0, None) => mapped,
+            (file, _) => mapped.with_path(&self.files[file].name),
+        })
+    }
+
+    /// Maps the locations of an output assembled from the text into the files they were read
+    /// from.
+    pub(crate) fn map_output(&self, mut output: AssemblyOutput) -> AssemblyOutput {
+        output.files = self.files().map(ToOwned::to_owned).collect();
+        if !self.is_expanded() {
+            return output;
+        }
+        for segment in &mut output.image.segments {
+            segment.location = segment.location.map(|location| self.locate(location));
+            for word in &mut segment.words {
+                word.location = word.location.map(|location| self.locate(location));
+            }
+        }
+        for line in &mut output.listing {
+            line.line = self
+                .lines
+                .get(line.line - 1)
+                .map_or(line.line, |origin| origin.line);
+        }
+        for diagnostic in &mut output.diagnostics {
+            if let Some(location) = diagnostic.location {
+                let location = self.locate(location);
+                diagnostic.location = Some(location);
+                if location.file != 0 {
+                    diagnostic.file = Some(self.files[location.file].name.clone());
+                }
+            }
+        }
+        // Warnings about `.INCLUDE` directives are already located in their files
+        output
+            .diagnostics
+            .splice(0..0, self.diagnostics.iter().cloned());
+        output
+    }
+}
+
+/// Expands `.INCLUDE` directives of a source file and the files it includes.
+pub(crate) struct Expander<'a> {
+    /// Options giving the include paths
+    options: &'a Assembler,
+    source: Preprocessed,
+    /// Files being expanded, to detect recursive inclusion
+    #[cfg(feature = "std")]
+    stack: Vec<PathBuf>,
+    /// Path of every included file
+    #[cfg(feature = "std")]
+    included: Vec<PathBuf>,
+    /// Modules of the [stdlib](crate::stdlib) already included
+    libraries: Vec<String>,
+}
+
+impl<'a> Expander<'a> {
+    /// Creates an expander of given source file.
+    pub(crate) fn new(options: &'a Assembler, name: String, input: String) -> Self {
+        Expander {
+            options,
+            source: Preprocessed {
+                text: String::with_capacity(input.len()),
+                files: vec![SourceFile { name, text: input }],
+                lines: Vec::new(),
+                diagnostics: Vec::new(),
+            },
+            #[cfg(feature = "std")]
+            stack: Vec::new(),
+            #[cfg(feature = "std")]
+            included: Vec::new(),
+            libraries: Vec::new(),
+        }
+    }
+
+    /// Expands the source file, resolving relative `.INCLUDE` paths against `base_dir` first.
+    pub(crate) fn run(mut self, base_dir: Option<&Path>) -> Result<Preprocessed, Error> {
+        self.expand(0, base_dir)?;
+        Ok(self.source)
+    }
+
+    /// Expands the source file, returning the path of every included file.
+    #[cfg(feature = "std")]
+    pub(crate) fn run_with_included(
+        mut self,
+        base_dir: Option<&Path>,
+    ) -> Result<(Preprocessed, Vec<PathBuf>), Error> {
+        self.expand(0, base_dir)?;
+        Ok((self.source, self.included))
+    }
+
+    fn expand(&mut self, file: usize, base_dir: Option<&Path>) -> Result<(), Error> {
+        let input = self.source.files[file].text.clone();
+        let mut line_start = 0;
+        for (index, line) in input.split_inclusive('\n').enumerate() {
+            let span =
+                || Span::new(&input, line_start, line_start + line.trim_end().len()).unwrap();
+            let include = include_target(line);
+            if include.is_some() {
+                self.check_include(file, span())?;
+            }
+            match include {
+                Some(Include::Library(name)) => {
+                    if crate::stdlib::module(name).is_none() {
+                        return Err(self.error(
+                            file,
+                            span(),
+                            format!("Unknown library module {}", name),
+                        ));
+                    }
+                    if !self.libraries.iter().any(|module| module == name) {
+                        self.libraries.push(name.to_owned());
+                        let content = crate::stdlib::module(name).unwrap().to_owned();
+                        let module = self.source.add_file(format!("<{}>", name), content);
+                        self.expand(module, None)?;
+                        self.end_line();
+                    }
+                }
+                Some(Include::File(target)) => self.include(file, span(), target, base_dir)?,
+                None => {
+                    self.source.lines.push(LineOrigin {
+                        start: self.source.text.len(),
+                        file,
+                        offset: line_start,
+                        line: index + 1,
+                    });
+                    self.source.text += line;
+                }
+            }
+            line_start += line.len();
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn include(
+        &mut self,
+        file: usize,
+        span: Span,
+        target: &str,
+        base_dir: Option<&Path>,
+    ) -> Result<(), Error> {
+        let path = self.options.find_include(target, base_dir).ok_or_else(|| {
+            self.error(file, span, format!("Cannot find include file {}", target))
+        })?;
+        if self.stack.contains(&path) {
+            return Err(self.error(
+                file,
+                span,
+                format!("Recursive inclusion of {}", path.display()),
+            ));
+        }
+        let content = fs::read_to_string(&path)?;
+        if !self.included.contains(&path) {
+            self.included.push(path.clone());
+        }
+        let included = self.source.add_file(target.to_owned(), content);
+        self.stack.push(path.clone());
+        self.expand(included, path.parent())?;
+        self.stack.pop();
+        self.end_line();
+        Ok(())
+    }
+
+    /// Without the `std` feature there is no filesystem to read included files from, and only
+    /// modules of the [stdlib](crate::stdlib) may be included.
+    #[cfg(not(feature = "std"))]
+    fn include(
+        &mut self,
+        file: usize,
+        span: Span,
+        target: &str,
+        _base_dir: Option<&Path>,
+    ) -> Result<(), Error> {
+        Err(self.error(
+            file,
+            span,
+            format!("Cannot include {} without the std feature", target),
+        ))
+    }
+
+    /// Rejects an `.INCLUDE` directive if the dialect does not accept it, and warns about it in
+    /// strict mode.
+    fn check_include(&mut self, file: usize, span: Span) -> Result<(), Error> {
+        let dialect = self.options.dialect;
+        if !dialect.accepts(Construct::Include) {
+            let message = format!(
+                "{} is not accepted by {} dialect",
+                Construct::Include,
+                dialect
+            );
+            return Err(self.error(file, span, message));
+        }
+        if !self.options.strict {
+            return Ok(());
+        }
+        let message = format!("{} is not portable", Construct::Include);
+        match self.options.warnings {
+            WarningLevel::Allow => Ok(()),
+            WarningLevel::Warn => {
+                let (line, column) = span.start_pos().line_col();
+                self.source.diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    message,
+                    location: Some(Location {
+                        start: span.start(),
+                        end: span.end(),
+                        line,
+                        column,
+                        file,
+                    }),
+                    file: match file {
+                        0 => None,
+                        _ => Some(self.source.files[file].name.clone()),
+                    },
+                });
+                Ok(())
+            }
+            WarningLevel::Deny => Err(self.error(file, span, message)),
+        }
+    }
+
+    /// Ends the last line of an included file, so that it does not join the next line.
+    fn end_line(&mut self) {
+        if !self.source.text.is_empty() && !self.source.text.ends_with('\n') {
+            self.source.text.push('\n');
+        }
+    }
+
+    /// Returns an error at given span of a file, naming the file if it was included.
+    fn error(&self, file: usize, span: Span, message: String) -> Error {
+        let err = PestError::new_from_span(PestErrorVariant::<Rule>::CustomError { message }, span);
+        match file {
+            0 => err.into(),
+            _ => err.with_path(&self.source.files[file].name).into(),
+        }
+    }
+}
+
+/// Target of an `.INCLUDE` directive.
+enum Include<'a> {
+    /// Quoted path, e.g. `.INCLUDE "lib/print.asm"`
+    File(&'a str),
+    /// Module of the [stdlib](crate::stdlib), e.g. `.INCLUDE <std/io>`
+    Library(&'a str),
+}
+
+/// Returns the target if given line is an `.INCLUDE "path"` or `.INCLUDE <module>` directive.
+fn include_target(line: &str) -> Option<Include<'_>> {
+    let line = line.trim();
+    let directive = line.get(..8)?;
+    if !directive.eq_ignore_ascii_case(".include") {
+        return None;
+    }
+    let rest = line[8..].trim_start();
+    if let Some(rest) = rest.strip_prefix('<') {
+        let end = rest.find('>')?;
+        return Some(Include::Library(&rest[..end]));
+    }
+    let rest = rest.strip_prefix('"')?;
+    let end = rest.find('"')?;
+    Some(Include::File(&rest[..end]))
+}
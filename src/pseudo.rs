@@ -0,0 +1,199 @@
+//! Provides pseudo-instructions, which expand into sequences of LC-3 instructions.
+//!
+//! | Pseudo-instruction | Expansion                                                         |
+//! |--------------------|-------------------------------------------------------------------|
+//! | `PUSH SR`          | `ADD R6, R6, #-1`, `STR SR, R6, #0`                               |
+//! | `POP DR`           | `LDR DR, R6, #0`, `ADD R6, R6, #1`                                |
+//! | `MOV DR, SR`       | `ADD DR, SR, #0`                                                  |
+//! | `CLR DR`           | `AND DR, DR, #0`                                                  |
+//! | `INC DR`           | `ADD DR, DR, #1`                                                  |
+//! | `DEC DR`           | `ADD DR, DR, #-1`                                                 |
+//! | `SUB DR, SR1, SR2` | `NOT Rs, SR2`, `ADD Rs, Rs, #1`, `ADD DR, SR1, Rs`                |
+//! | `NEG DR[, SR]`     | `NOT DR, SR`, `ADD DR, DR, #1`                                    |
+//! | `CALL label`       | `PUSH R7`, `JSR label`, `POP R7`                                  |
+//!
+//! `R6` is the stack pointer, and the stack grows downwards. On [Target::Lc3b] it moves by 2,
+//! the size of a word in bytes, and `STR` and `LDR` are `STW` and `LDW`.
+//!
+//! `Rs` is the scratch register set with [Assembler::scratch_register], whose previous value `SUB`
+//! overwrites. It has no default, as `R7` often holds a return address, and `SR1` of `SUB` cannot
+//! be the scratch register.
+//! `NEG DR` negates `DR` itself.
+//!
+//! Pseudo-instructions are reserved words and cannot be used as labels.
+//!
+//! [Assembler::scratch_register]: crate::Assembler::scratch_register
+use crate::Instruction;
+use crate::Rule;
+use crate::Target;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
+const SP: u8 = 6;
+
+/// Offset of the `JSR` of `CALL` from the start of its expansion.
+pub(crate) const CALL_JSR: usize = 2;
+
+/// Returns the number of instructions given pseudo-instruction expands into.
+pub(crate) fn size(rule: Rule) -> usize {
+    match rule {
+        Rule::mov | Rule::clr | Rule::inc | Rule::dec => 1,
+        Rule::push | Rule::pop | Rule::neg => 2,
+        Rule::sub => 3,
+        Rule::call => 5,
+        _ => unreachable!("{:?} is not a pseudo-instruction", rule),
+    }
+}
+
+/// Expands a pseudo-instruction with given register operands.
+///
+/// `pc_offset` is the offset of the `CALL` target from the PC incremented past its `JSR`, and is
+/// ignored by other pseudo-instructions.
+pub(crate) fn expand(
+    rule: Rule,
+    registers: &[u8],
+    pc_offset: i16,
+    scratch: Option<u8>,
+    target: Target,
+) -> Result<Vec<Instruction>, String> {
+    use Instruction::*;
+    let step = target.word_size() as i16;
+    let push = |sr| {
+        [
+            AddImm {
+                dr: SP,
+                sr1: SP,
+                imm5: -step,
+            },
+            Str {
+                sr,
+                base_r: SP,
+                offset6: 0,
+            },
+        ]
+    };
+    let pop = |dr| {
+        [
+            Ldr {
+                dr,
+                base_r: SP,
+                offset6: 0,
+            },
+            AddImm {
+                dr: SP,
+                sr1: SP,
+                imm5: step,
+            },
+        ]
+    };
+    let add_imm = |dr, sr1, imm5| AddImm { dr, sr1, imm5 };
+    let negate = |dr, sr| [Not { dr, sr }, add_imm(dr, dr, 1)];
+    Ok(match (rule, registers) {
+        (Rule::push, &[sr]) => push(sr).to_vec(),
+        (Rule::pop, &[dr]) => pop(dr).to_vec(),
+        (Rule::mov, &[dr, sr]) => vec![add_imm(dr, sr, 0)],
+        (Rule::clr, &[dr]) => vec![AndImm {
+            dr,
+            sr1: dr,
+            imm5: 0,
+        }],
+        (Rule::inc, &[dr]) => vec![add_imm(dr, dr, 1)],
+        (Rule::dec, &[dr]) => vec![add_imm(dr, dr, -1)],
+        (Rule::sub, &[dr, sr1, sr2]) => {
+            let scratch = scratch.ok_or(
+                "SUB overwrites a scratch register, which must be set with \
+                 Assembler::scratch_register",
+            )?;
+            if sr1 == scratch {
+                return Err(format!(
+                    "SR1 of SUB cannot be R{}, which is overwritten as the scratch register",
+                    scratch
+                ));
+            }
+            let mut instructions = negate(scratch, sr2).to_vec();
+            instructions.push(Add {
+                dr,
+                sr1,
+                sr2: scratch,
+            });
+            instructions
+        }
+        (Rule::neg, &[dr]) => negate(dr, dr).to_vec(),
+        (Rule::neg, &[dr, sr]) => negate(dr, sr).to_vec(),
+        (Rule::call, &[]) => {
+            let mut instructions = push(7).to_vec();
+            instructions.push(Jsr {
+                pc_offset11: pc_offset,
+            });
+            instructions.extend(&pop(7));
+            instructions
+        }
+        _ => unreachable!("{:?} {:?}", rule, registers),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(rule: Rule, registers: &[u8]) -> Vec<String> {
+        let instructions = expand(rule, registers, -3, Some(7), Target::Lc3).unwrap();
+        assert_eq!(instructions.len(), size(rule), "{:?}", rule);
+        instructions.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expanded(Rule::push, &[0]),
+            ["ADD R6, R6, #-1", "STR R0, R6, #0"]
+        );
+        assert_eq!(
+            expanded(Rule::pop, &[1]),
+            ["LDR R1, R6, #0", "ADD R6, R6, #1"]
+        );
+        assert_eq!(expanded(Rule::mov, &[1, 2]), ["ADD R1, R2, #0"]);
+        assert_eq!(expanded(Rule::clr, &[3]), ["AND R3, R3, #0"]);
+        assert_eq!(expanded(Rule::dec, &[3]), ["ADD R3, R3, #-1"]);
+        assert_eq!(
+            expanded(Rule::sub, &[1, 2, 3]),
+            ["NOT R7, R3", "ADD R7, R7, #1", "ADD R1, R2, R7"]
+        );
+        assert_eq!(expanded(Rule::neg, &[4]), ["NOT R4, R4", "ADD R4, R4, #1"]);
+        assert_eq!(
+            expanded(Rule::call, &[]),
+            [
+                "ADD R6, R6, #-1",
+                "STR R7, R6, #0",
+                "JSR #-3",
+                "LDR R7, R6, #0",
+                "ADD R6, R6, #1"
+            ]
+        );
+        assert!(expand(Rule::sub, &[1, 7, 2], 0, Some(7), Target::Lc3).is_err());
+        assert!(expand(Rule::sub, &[1, 2, 3], 0, None, Target::Lc3).is_err());
+    }
+
+    #[test]
+    fn test_scratch() -> Result<(), crate::Error> {
+        let source = ".ORIG x3000\nSUB R0, R1, R2\n.END";
+        let err = crate::Assembler::new().assemble(source).unwrap_err();
+        assert!(err.to_string().contains("must be set"), "{}", err);
+        let output = crate::Assembler::new()
+            .scratch_register(7)
+            .assemble(source)?;
+        assert_eq!(output.segments[0].words, [0x9EBF, 0x1FE1, 0x1047]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lc3b() -> Result<(), crate::Error> {
+        let output = crate::Assembler::new()
+            .target(Target::Lc3b)
+            .assemble(".ORIG x3000\nPUSH R1\nPOP R2\n.END")?;
+        // ADD R6, R6, #-2; STW R1, R6, #0; LDW R2, R6, #0; ADD R6, R6, #2
+        assert_eq!(output.segments[0].words, [0x1DBE, 0x7380, 0x6580, 0x1DA2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Provides long-branch relaxation enabled with [Assembler::relax_branches].
+//!
+//! A PC-relative `BR`, `JSR`, `LD`, `LDI` or `LEA` whose label is out of range is rewritten into
+//! a sequence which loads the absolute address of the label from a literal pool word ending it,
+//! so the literal is always within reach:
+//!
+//! | Instruction  | Expansion                                                    |
+//! |--------------|--------------------------------------------------------------|
+//! | `BRnzp L`    | `LD Rs, #1`, `JMP Rs`, `.FILL L`                             |
+//! | `BRcc L`     | `BR!cc #3`, `LD Rs, #1`, `JMP Rs`, `.FILL L`                 |
+//! | `JSR L`      | `LD R7, #2`, `JSRR R7`, `BRnzp #1`, `.FILL L`                |
+//! | `LD Rd, L`   | `LD Rd, #2`, `LDR Rd, Rd, #0`, `BRnzp #1`, `.FILL L`         |
+//! | `LDI Rd, L`  | `LD Rd, #3`, `LDR Rd, Rd, #0` (twice), `BRnzp #1`, `.FILL L` |
+//! | `LEA Rd, L`  | `LD Rd, #1`, `BRnzp #1`, `.FILL L`                           |
+//!
+//! `Rs` is the scratch register set with [Assembler::scratch_register], which a relaxed branch
+//! overwrites, so a branch is only relaxed if it is set. Relaxed `LEA` sets the condition codes,
+//! as `LD` does.
+//!
+//! [Assembler::relax_branches]: crate::Assembler::relax_branches
+//! [Assembler::scratch_register]: crate::Assembler::scratch_register
+use crate::isa::{self, Format};
+use alloc::vec;
+use alloc::vec::Vec;
+
+const ALWAYS: i64 = 0b111;
+
+/// Returns whether given format may be relaxed.
+pub(crate) fn relaxable(format: &Format) -> bool {
+    [isa::BR, isa::JSR, isa::LD, isa::LDI, isa::LEA].contains(format)
+}
+
+/// Returns the number of words [expand] writes for given format and branch condition.
+pub(crate) fn size(format: &Format, condition: i64) -> usize {
+    match &*format.mnemonic {
+        "BR" if condition == ALWAYS => 3,
+        "BR" | "JSR" | "LD" => 4,
+        "LDI" => 5,
+        "LEA" => 3,
+        _ => unreachable!("{} cannot be relaxed", format),
+    }
+}
+
+/// Expands the instruction with given operands into words reaching `target`.
+///
+/// The PC-relative operand is ignored and registers must be valid.
+pub(crate) fn expand(format: &Format, operands: &[i64], target: u16, scratch: u8) -> Vec<u16> {
+    let encode = |format: Format, operands: &[i64]| format.encode(operands).unwrap();
+    let scratch = i64::from(scratch);
+    match &*format.mnemonic {
+        "BR" => {
+            let mut words = Vec::with_capacity(4);
+            if operands[0] != ALWAYS {
+                words.push(encode(isa::BR, &[!operands[0] & ALWAYS, 3]));
+            }
+            words.push(encode(isa::LD, &[scratch, 1]));
+            words.push(encode(isa::JMP, &[scratch]));
+            words.push(target);
+            words
+        }
+        "JSR" => vec![
+            encode(isa::LD, &[7, 2]),
+            encode(isa::JSRR, &[7]),
+            encode(isa::BR, &[ALWAYS, 1]),
+            target,
+        ],
+        mnemonic => {
+            let dr = operands[0];
+            let indirections = match mnemonic {
+                "LD" => 1,
+                "LDI" => 2,
+                _ => 0,
+            };
+            let mut words = vec![encode(isa::LD, &[dr, indirections + 1])];
+            for _ in 0..indirections {
+                words.push(encode(isa::LDR, &[dr, dr, 0]));
+            }
+            words.push(encode(isa::BR, &[ALWAYS, 1]));
+            words.push(target);
+            words
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instruction;
+
+    /// Returns the text of each word of an expansion, whose last word is the literal.
+    fn describe(words: &[u16]) -> Vec<String> {
+        let (literal, instructions) = words.split_last().unwrap();
+        instructions
+            .iter()
+            .map(|word| Instruction::decode(*word).to_string())
+            .chain(std::iter::once(format!(".FILL x{:04X}", literal)))
+            .collect()
+    }
+
+    fn expanded(format: Format, operands: &[i64]) -> Vec<String> {
+        let words = expand(&format, operands, 0x1000, 6);
+        let condition = operands.first().cloned().unwrap_or_default();
+        assert_eq!(words.len(), size(&format, condition), "{}", format);
+        describe(&words)
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expanded(isa::BR, &[0b010, 0]),
+            ["BRnp #3", "LD R6, #1", "JMP R6", ".FILL x1000"]
+        );
+        assert_eq!(
+            expanded(isa::BR, &[0b111, 0]),
+            ["LD R6, #1", "JMP R6", ".FILL x1000"]
+        );
+        assert_eq!(
+            expanded(isa::JSR, &[0]),
+            ["LD R7, #2", "JSRR R7", "BRnzp #1", ".FILL x1000"]
+        );
+        assert_eq!(
+            expanded(isa::LDI, &[2, 0]),
+            [
+                "LD R2, #3",
+                "LDR R2, R2, #0",
+                "LDR R2, R2, #0",
+                "BRnzp #1",
+                ".FILL x1000"
+            ]
+        );
+        assert_eq!(
+            expanded(isa::LEA, &[1, 0]),
+            ["LD R1, #1", "BRnzp #1", ".FILL x1000"]
+        );
+    }
+}
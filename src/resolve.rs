@@ -0,0 +1,189 @@
+//! Provides [Symbols], which resolves labels and constants into values and PC offsets for the
+//! assembler passes, the fast path and [ProgramBuilder](crate::ProgramBuilder).
+use crate::assembler::Assembler;
+use crate::target::Target;
+use crate::Rule;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pest::iterators::Pair;
+
+/// Declaration of a label, giving its name as written.
+pub(crate) trait Declaration {
+    fn name(&self) -> &str;
+}
+
+impl Declaration for &str {
+    fn name(&self) -> &str {
+        self
+    }
+}
+
+impl Declaration for Pair<'_, Rule> {
+    fn name(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Reason a label or constant cannot be resolved into an offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unresolved {
+    /// No label or constant has the name
+    Missing,
+    /// The constant holds an address which is not aligned to a word
+    Misaligned(i64),
+}
+
+/// Labels and constants of a program.
+pub(crate) struct Symbols<'a, D> {
+    options: &'a Assembler,
+    /// Offset from the origin in words and declaration of each label, keyed like
+    /// [Assembler::symbol_key]
+    pub(crate) labels: BTreeMap<String, (usize, D)>,
+    /// Value of each constant given by [Assembler::define], keyed like [Assembler::symbol_key]
+    pub(crate) constants: BTreeMap<String, i64>,
+    /// Address of the first word
+    pub(crate) origin: usize,
+}
+
+impl<'a, D: Declaration> Symbols<'a, D> {
+    pub(crate) fn new(options: &'a Assembler) -> Self {
+        Symbols {
+            options,
+            labels: BTreeMap::new(),
+            constants: options
+                .constants
+                .iter()
+                .map(|(name, value)| (options.symbol_key(name), *value))
+                .collect(),
+            origin: 0,
+        }
+    }
+
+    /// Returns the address of the word at given offset from the origin.
+    pub(crate) fn address(&self, offset: usize) -> usize {
+        self.origin + offset * self.options.target.word_size()
+    }
+
+    /// Returns the offset and declaration of given label.
+    pub(crate) fn label(&self, name: &str) -> Option<&(usize, D)> {
+        if self.options.case_insensitive_labels {
+            self.labels.get(&name.to_uppercase())
+        } else {
+            self.labels.get(name)
+        }
+    }
+
+    /// Returns the value of given constant.
+    pub(crate) fn constant(&self, name: &str) -> Option<i64> {
+        if self.options.case_insensitive_labels {
+            self.constants.get(&name.to_uppercase()).copied()
+        } else {
+            self.constants.get(name).copied()
+        }
+    }
+
+    /// Resolves a label or constant into its value. Labels are resolved into their address.
+    pub(crate) fn value(&self, name: &str) -> Option<i64> {
+        match self.label(name) {
+            Some((offset, _)) => Some(i64::from(self.address(*offset) as u16 as i16)),
+            None => self.constant(name),
+        }
+    }
+
+    /// Returns the offset of given label or constant from the origin, in words.
+    pub(crate) fn offset(&self, name: &str) -> Result<i64, Unresolved> {
+        if let Some((offset, _)) = self.label(name) {
+            return Ok(*offset as i64);
+        }
+        let value = self.constant(name).ok_or(Unresolved::Missing)?;
+        let word_size = self.options.target.word_size() as i64;
+        if value.rem_euclid(word_size) != 0 {
+            return Err(Unresolved::Misaligned(value));
+        }
+        Ok((value - self.origin as i64).div_euclid(word_size))
+    }
+
+    /// Resolves given label or constant into its offset from the PC incremented past the word
+    /// at given offset.
+    pub(crate) fn pc_offset(&self, name: &str, offset: usize) -> Result<i64, Unresolved> {
+        self.offset(name).map(|target| target - offset as i64 - 1)
+    }
+
+    /// Returns the absolute address of each label, keyed by its name as declared.
+    pub(crate) fn absolute(&self) -> BTreeMap<String, u16> {
+        self.labels
+            .values()
+            .map(|(offset, declaration)| {
+                (declaration.name().to_owned(), self.address(*offset) as u16)
+            })
+            .collect()
+    }
+}
+
+/// Returns the message for a PC-relative operand naming a misaligned constant.
+pub(crate) fn misaligned(name: &str, address: i64) -> String {
+    format!(
+        "Constant {} holds address x{:X}, which is not aligned to a word",
+        name, address
+    )
+}
+
+/// Returns the number of words a `.STRINGZ` of given length occupies with its terminating zero.
+pub(crate) fn string_size(len: usize, target: Target) -> usize {
+    (len + 1).div_ceil(target.word_size())
+}
+
+/// Encodes the bytes of a `.STRINGZ` and its terminating zero into words.
+pub(crate) fn string_words(bytes: &[u8], target: Target) -> Vec<u16> {
+    let bytes = bytes.iter().copied().chain(core::iter::once(0));
+    match target {
+        Target::Lc3 => bytes.map(u16::from).collect(),
+        // Bytes are packed little-endian, padding the last word
+        Target::Lc3b => {
+            let bytes = bytes.collect::<Vec<_>>();
+            bytes
+                .chunks(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        let options = Assembler::new()
+            .target(Target::Lc3b)
+            .case_sensitive_labels(false)
+            .define("STACK", 0x3010)
+            .define("ODD", 0x3011);
+        let mut symbols = Symbols::<&str>::new(&options);
+        symbols.origin = 0x3000;
+        symbols.labels.insert("LOOP".into(), (2, "Loop"));
+        assert_eq!(symbols.value("loop"), Some(0x3004));
+        assert_eq!(symbols.pc_offset("loop", 4), Ok(-3));
+        assert_eq!(symbols.value("stack"), Some(0x3010));
+        assert_eq!(symbols.pc_offset("STACK", 4), Ok(3));
+        assert_eq!(symbols.value("MISSING"), None);
+        assert_eq!(symbols.pc_offset("MISSING", 4), Err(Unresolved::Missing));
+        assert_eq!(
+            symbols.pc_offset("ODD", 4),
+            Err(Unresolved::Misaligned(0x3011))
+        );
+        assert_eq!(symbols.absolute()["Loop"], 0x3004);
+    }
+
+    #[test]
+    fn test_string_words() {
+        assert_eq!(string_words(b"Hi", Target::Lc3), [0x48, 0x69, 0]);
+        assert_eq!(string_words(b"Hi", Target::Lc3b), [0x6948, 0]);
+        assert_eq!(string_words(b"Hi!", Target::Lc3b), [0x6948, 0x0021]);
+        assert_eq!(string_size(3, Target::Lc3b), 2);
+    }
+}
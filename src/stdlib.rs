@@ -0,0 +1,51 @@
+//! Provides the standard library bundled with the assembler, whose modules are included with
+//! `.INCLUDE <std/io>`.
+//!
+//! | Module       | Routines                            |
+//! |--------------|-------------------------------------|
+//! | `std/io`     | `STD_PRINT_INT`, `STD_READ_INT`     |
+//! | `std/math`   | `STD_MUL`, `STD_DIV`                |
+//! | `std/string` | `STD_STRLEN`, `STD_STRCMP`          |
+//!
+//! Routines are called with `JSR` and follow these register conventions:
+//!
+//!  - Arguments are passed in `R0` and `R1`, and results are returned in `R0`, and in `R1` for
+//!    the remainder of `STD_DIV`.
+//!  - Every other register is preserved, except `R7` which holds the return address.
+//!  - Registers are saved in memory next to each routine rather than on a stack, so routines are
+//!    not reentrant and `R6` need not point to a stack.
+//!
+//! Each module is included at most once per program, wherever its first `.INCLUDE` is, so it
+//! should follow the code of the program, e.g. after `HALT`. Modules including other modules,
+//! as `std/io` includes `std/math`, may be included along with them. Labels of the library start
+//! with `STD_`, and the source of each module documents its routines.
+//!
+//! ```
+//! use lc3asm::Assembler;
+//!
+//! let output = Assembler::new()
+//!     .assemble(".ORIG x3000\nAND R0, R0, #0\nJSR STD_PRINT_INT\nHALT\n.INCLUDE <std/io>\n.END")
+//!     .unwrap();
+//! assert!(output.symbols.contains_key("STD_PRINT_INT"));
+//! // std/io includes std/math
+//! assert!(output.symbols.contains_key("STD_DIV"));
+//! ```
+
+/// Version of the bundled library, changed whenever a routine changes its behavior or
+/// registers.
+pub const VERSION: &str = "1.0";
+
+/// Name and source code of each module.
+pub const MODULES: &[(&str, &str)] = &[
+    ("std/io", include_str!("stdlib/io.asm")),
+    ("std/math", include_str!("stdlib/math.asm")),
+    ("std/string", include_str!("stdlib/string.asm")),
+];
+
+/// Returns the source code of the module with given name, e.g. `std/io`.
+pub fn module(name: &str) -> Option<&'static str> {
+    MODULES
+        .iter()
+        .find(|(module, _)| *module == name)
+        .map(|(_, source)| *source)
+}
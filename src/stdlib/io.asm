@@ -0,0 +1,96 @@
+; lc3asm standard library: std/io
+;
+; STD_PRINT_INT R0 is written in decimal, with a minus sign if negative
+; STD_READ_INT  R0 <- decimal integer read from the keyboard, optionally starting with a minus
+;               sign and ending at the first other character, e.g. a newline. Characters are
+;               echoed, and the value wraps around on overflow
+
+STD_PRINT_INT   ST      R0, STD_PI_R0
+                ST      R1, STD_PI_R1
+                ST      R2, STD_PI_R2
+                ST      R3, STD_PI_R3
+                ST      R7, STD_PI_R7
+                ADD     R2, R0, #0          ; value left to write
+                BRzp    STD_PI_DIGITS
+                LD      R0, STD_PI_MINUS
+                OUT
+STD_PI_DIGITS   LEA     R3, STD_PI_END      ; digits are written backward from the end
+STD_PI_LOOP     ADD     R0, R2, #0
+                AND     R1, R1, #0
+                ADD     R1, R1, #10
+                JSR     STD_DIV
+                ADD     R2, R0, #0
+                ADD     R1, R1, #0          ; remainders of negative values are negative
+                BRzp    STD_PI_DIGIT
+                NOT     R1, R1
+                ADD     R1, R1, #1
+STD_PI_DIGIT    LD      R0, STD_PI_ZERO
+                ADD     R1, R1, R0
+                ADD     R3, R3, #-1
+                STR     R1, R3, #0
+                ADD     R2, R2, #0
+                BRnp    STD_PI_LOOP
+                ADD     R0, R3, #0
+                PUTS
+                LD      R0, STD_PI_R0
+                LD      R1, STD_PI_R1
+                LD      R2, STD_PI_R2
+                LD      R3, STD_PI_R3
+                LD      R7, STD_PI_R7
+                RET
+STD_PI_MINUS    .FILL   x2D
+STD_PI_ZERO     .FILL   x30
+STD_PI_R0       .BLKW   #1
+STD_PI_R1       .BLKW   #1
+STD_PI_R2       .BLKW   #1
+STD_PI_R3       .BLKW   #1
+STD_PI_R7       .BLKW   #1
+STD_PI_BUFFER   .BLKW   #5
+STD_PI_END      .FILL   x0000
+
+STD_READ_INT    ST      R1, STD_RI_R1
+                ST      R2, STD_RI_R2
+                ST      R3, STD_RI_R3
+                ST      R4, STD_RI_R4
+                ST      R7, STD_RI_R7
+                AND     R1, R1, #0          ; 1 if negative
+                AND     R2, R2, #0          ; value
+                GETC
+                OUT
+                LD      R3, STD_RI_MINUS
+                ADD     R3, R0, R3
+                BRnp    STD_RI_DIGIT
+                ADD     R1, R1, #1
+STD_RI_LOOP     GETC
+                OUT
+STD_RI_DIGIT    LD      R3, STD_RI_ZERO
+                ADD     R0, R0, R3
+                BRn     STD_RI_END
+                ADD     R3, R0, #-9
+                BRp     STD_RI_END
+                ADD     R3, R2, R2          ; value * 10 = value * 2 + value * 8
+                ADD     R4, R3, R3
+                ADD     R4, R4, R4
+                ADD     R2, R3, R4
+                ADD     R2, R2, R0
+                BRnzp   STD_RI_LOOP
+STD_RI_END      ADD     R0, R2, #0
+                ADD     R1, R1, #0
+                BRz     STD_RI_RET
+                NOT     R0, R0
+                ADD     R0, R0, #1
+STD_RI_RET      LD      R1, STD_RI_R1
+                LD      R2, STD_RI_R2
+                LD      R3, STD_RI_R3
+                LD      R4, STD_RI_R4
+                LD      R7, STD_RI_R7
+                RET
+STD_RI_MINUS    .FILL   #-45                ; -'-'
+STD_RI_ZERO     .FILL   #-48                ; -'0'
+STD_RI_R1       .BLKW   #1
+STD_RI_R2       .BLKW   #1
+STD_RI_R3       .BLKW   #1
+STD_RI_R4       .BLKW   #1
+STD_RI_R7       .BLKW   #1
+
+.INCLUDE <std/math>
@@ -0,0 +1,79 @@
+; lc3asm standard library: std/math
+;
+; STD_MUL   R0 <- R0 * R1, wrapping around on overflow
+; STD_DIV   R0 <- R0 / R1 rounded toward zero, R1 <- remainder with the sign of R0.
+;           Dividing by zero returns 0 with R0 as the remainder
+
+STD_MUL         ST      R2, STD_MUL_R2
+                ST      R3, STD_MUL_R3
+                ST      R4, STD_MUL_R4
+                AND     R2, R2, #0          ; product
+                AND     R3, R3, #0
+                ADD     R3, R3, #1          ; bit of R1 to test
+STD_MUL_LOOP    AND     R4, R1, R3
+                BRz     STD_MUL_NEXT
+                ADD     R2, R2, R0
+STD_MUL_NEXT    ADD     R0, R0, R0
+                ADD     R3, R3, R3
+                BRnp    STD_MUL_LOOP        ; until the bit is shifted out
+                ADD     R0, R2, #0
+                LD      R2, STD_MUL_R2
+                LD      R3, STD_MUL_R3
+                LD      R4, STD_MUL_R4
+                RET
+STD_MUL_R2      .BLKW   #1
+STD_MUL_R3      .BLKW   #1
+STD_MUL_R4      .BLKW   #1
+
+; Counts how many times the divisor fits in the dividend, both made negative as -32768 has no
+; positive counterpart.
+STD_DIV         ST      R2, STD_DIV_R2
+                ST      R3, STD_DIV_R3
+                ST      R4, STD_DIV_R4
+                ST      R5, STD_DIV_R5
+                AND     R4, R4, #0          ; -quotient
+                ADD     R2, R0, #0          ; -|dividend|
+                BRnz    STD_DIV_A
+                NOT     R2, R2
+                ADD     R2, R2, #1
+STD_DIV_A       ADD     R3, R1, #0          ; |divisor|
+                BRz     STD_DIV_ZERO
+                BRp     STD_DIV_LOOP
+                NOT     R3, R3
+                ADD     R3, R3, #1
+STD_DIV_LOOP    ADD     R2, R2, #0
+                BRz     STD_DIV_SIGN
+                ADD     R5, R2, R3
+                BRp     STD_DIV_SIGN        ; less than the divisor remains
+                ADD     R2, R5, #0
+                ADD     R4, R4, #-1
+                BRnzp   STD_DIV_LOOP
+STD_DIV_SIGN    ADD     R1, R1, #0
+                BRn     STD_DIV_NEGDIV
+                ADD     R0, R0, #0
+                BRn     STD_DIV_QNEG
+                BRnzp   STD_DIV_QPOS
+STD_DIV_NEGDIV  ADD     R0, R0, #0
+                BRn     STD_DIV_QPOS
+STD_DIV_QNEG    ADD     R5, R4, #0
+                BRnzp   STD_DIV_REM
+STD_DIV_QPOS    NOT     R5, R4
+                ADD     R5, R5, #1
+STD_DIV_REM     ADD     R1, R2, #0          ; -|remainder|
+                ADD     R0, R0, #0
+                BRn     STD_DIV_RET
+                NOT     R1, R1
+                ADD     R1, R1, #1
+STD_DIV_RET     ADD     R0, R5, #0
+                BRnzp   STD_DIV_DONE
+STD_DIV_ZERO    ADD     R1, R0, #0
+                AND     R0, R0, #0
+STD_DIV_DONE    LD      R2, STD_DIV_R2
+                LD      R3, STD_DIV_R3
+                LD      R4, STD_DIV_R4
+                LD      R5, STD_DIV_R5
+                RET
+STD_DIV_R2      .BLKW   #1
+STD_DIV_R3      .BLKW   #1
+STD_DIV_R4      .BLKW   #1
+STD_DIV_R5      .BLKW   #1
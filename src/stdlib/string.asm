@@ -0,0 +1,50 @@
+; lc3asm standard library: std/string
+;
+; Strings are zero-terminated, one character per word, as written by .STRINGZ.
+;
+; STD_STRLEN    R0 <- number of characters of the string at R0
+; STD_STRCMP    R0 <- difference of the first differing characters of the strings at R0 and
+;               R1, negative if the first string sorts before the second, zero if both are equal
+
+STD_STRLEN      ST      R1, STD_STRLEN_R1
+                ST      R2, STD_STRLEN_R2
+                ADD     R1, R0, #0
+STD_STRLEN_LOOP LDR     R2, R1, #0
+                BRz     STD_STRLEN_END
+                ADD     R1, R1, #1
+                BRnzp   STD_STRLEN_LOOP
+STD_STRLEN_END  NOT     R0, R0
+                ADD     R0, R0, #1
+                ADD     R0, R1, R0          ; end - start
+                LD      R1, STD_STRLEN_R1
+                LD      R2, STD_STRLEN_R2
+                RET
+STD_STRLEN_R1   .BLKW   #1
+STD_STRLEN_R2   .BLKW   #1
+
+STD_STRCMP      ST      R2, STD_STRCMP_R2
+                ST      R3, STD_STRCMP_R3
+                ST      R4, STD_STRCMP_R4
+                ST      R5, STD_STRCMP_R5
+                ADD     R2, R0, #0
+                ADD     R3, R1, #0
+STD_STRCMP_LOOP LDR     R4, R2, #0
+                LDR     R5, R3, #0
+                NOT     R5, R5
+                ADD     R5, R5, #1
+                ADD     R0, R4, R5
+                BRnp    STD_STRCMP_END
+                ADD     R4, R4, #0
+                BRz     STD_STRCMP_END      ; both strings ended
+                ADD     R2, R2, #1
+                ADD     R3, R3, #1
+                BRnzp   STD_STRCMP_LOOP
+STD_STRCMP_END  LD      R2, STD_STRCMP_R2
+                LD      R3, STD_STRCMP_R3
+                LD      R4, STD_STRCMP_R4
+                LD      R5, STD_STRCMP_R5
+                RET
+STD_STRCMP_R2   .BLKW   #1
+STD_STRCMP_R3   .BLKW   #1
+STD_STRCMP_R4   .BLKW   #1
+STD_STRCMP_R5   .BLKW   #1
@@ -0,0 +1,231 @@
+use super::*;
+use lc3::vm::VM;
+
+/// Steps after which a test program is considered stuck, e.g. waiting for more input.
+const MAX_STEPS: usize = 1_000_000;
+
+/// Assembles given program and runs it until it reaches label `DONE`, returning its output with
+/// the machine.
+///
+/// The operating system overwrites registers when halting, so programs end with
+/// `DONE BRnzp DONE` instead of `HALT`.
+fn run(source: &str, input: &str) -> Result<(String, VM), Error> {
+    let output = Assembler::new().assemble(source)?;
+    let done = output.symbols["DONE"];
+    let mut vm = VM::new();
+    vm.load_u8(&output.object_bytes());
+    let mut input = input.as_bytes();
+    let mut output = Vec::new();
+    for _ in 0..MAX_STEPS {
+        if vm.pc == done {
+            break;
+        }
+        vm.step(&mut input, &mut output);
+    }
+    assert_eq!(vm.pc, done, "The program did not finish");
+    Ok((String::from_utf8(output).unwrap(), vm))
+}
+
+/// Calls `routine` with given arguments, and returns R0 and R1.
+fn call(module: &str, routine: &str, r0: i16, r1: i16) -> Result<(i16, i16), Error> {
+    let source = format!(
+        ".ORIG x3000\nLD R0, A\nLD R1, B\nJSR {}\nDONE BRnzp DONE\nA .FILL #{}\nB .FILL #{}\n\
+         .INCLUDE <{}>\n.END",
+        routine, r0, r1, module
+    );
+    let (_, vm) = run(&source, "")?;
+    Ok((vm.register[0], vm.register[1]))
+}
+
+#[test]
+fn test_mul() -> Result<(), Error> {
+    for &(a, b) in &[
+        (6, 7),
+        (-6, 7),
+        (-6, -7),
+        (0, 123),
+        (181, 181),
+        (1000, 1000),
+    ] {
+        assert_eq!(
+            call("std/math", "STD_MUL", a, b)?,
+            (a.wrapping_mul(b), b),
+            "{} * {}",
+            a,
+            b
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_div() -> Result<(), Error> {
+    for &(a, b) in &[
+        (7, 2),
+        (-7, 2),
+        (7, -2),
+        (-7, -2),
+        (6, 3),
+        (1, 10),
+        (32767, 1),
+        (-32768, 1),
+        (-32768, -32768),
+        (12345, -32768),
+    ] {
+        assert_eq!(
+            call("std/math", "STD_DIV", a, b)?,
+            (a / b, a % b),
+            "{} / {}",
+            a,
+            b
+        );
+    }
+    assert_eq!(call("std/math", "STD_DIV", 5, 0)?, (0, 5));
+    Ok(())
+}
+
+#[test]
+fn test_print_int() -> Result<(), Error> {
+    for &value in &[0, 7, 10, -1, 12345, 32767, -32768] {
+        let (output, vm) = run(
+            &format!(
+                ".ORIG x3000\nLD R0, A\nLD R5, A\nJSR STD_PRINT_INT\nDONE BRnzp DONE\nA .FILL #{}\n\
+                 .INCLUDE <std/io>\n.END",
+                value
+            ),
+            "",
+        )?;
+        assert_eq!(output, value.to_string());
+        assert_eq!(vm.register[0], value, "R0 is preserved");
+        assert_eq!(vm.register[5], value, "R5 is preserved");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_read_int() -> Result<(), Error> {
+    for &(input, value) in &[
+        ("42\n", 42),
+        ("-17\n", -17),
+        ("0\n", 0),
+        ("32767\n", 32767),
+        ("-32768\n", -32768),
+        ("12a", 12),
+        ("\n", 0),
+    ] {
+        let (output, vm) = run(
+            ".ORIG x3000\nJSR STD_READ_INT\nDONE BRnzp DONE\n.INCLUDE <std/io>\n.END",
+            input,
+        )?;
+        assert_eq!(output, input, "Input is echoed");
+        assert_eq!(vm.register[0], value, "{:?}", input);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_read_and_print() -> Result<(), Error> {
+    // std/math is included by std/io as well
+    let source = "\
+.ORIG x3000
+        JSR     STD_READ_INT
+        ADD     R1, R0, #0
+        JSR     STD_READ_INT
+        JSR     STD_MUL
+        JSR     STD_PRINT_INT
+        DONE BRnzp DONE
+.INCLUDE <std/math>
+.INCLUDE <std/io>
+.END";
+    let (output, _) = run(source, "-12\n34\n")?;
+    assert_eq!(output, "-12\n34\n-408");
+    Ok(())
+}
+
+#[test]
+fn test_strings() -> Result<(), Error> {
+    let compare = |a: &str, b: &str| -> Result<(i16, i16), Error> {
+        let source = format!(
+            ".ORIG x3000\nLEA R0, A\nLEA R1, B\nJSR STD_STRCMP\nADD R2, R0, #0\nLEA R0, A\n\
+             JSR STD_STRLEN\nADD R1, R2, #0\nDONE BRnzp DONE\nA .STRINGZ {:?}\nB .STRINGZ {:?}\n\
+             .INCLUDE <std/string>\n.END",
+            a, b
+        );
+        let (_, vm) = run(&source, "")?;
+        Ok((vm.register[1], vm.register[0]))
+    };
+    assert_eq!(compare("apple", "apple")?, (0, 5));
+    assert_eq!(
+        compare("apple", "apply")?,
+        (i16::from(b'e') - i16::from(b'y'), 5)
+    );
+    assert_eq!(compare("b", "abc")?, (1, 1));
+    assert_eq!(compare("ab", "abc")?, (-i16::from(b'c'), 2));
+    assert_eq!(compare("", "")?, (0, 0));
+    Ok(())
+}
+
+#[test]
+fn test_includes() -> Result<(), Error> {
+    let output = Assembler::new()
+        .assemble(".ORIG x3000\nHALT\n.INCLUDE <std/io>\n.include   <std/io>\n.END")?;
+    let alone = Assembler::new().assemble(".ORIG x3000\nHALT\n.INCLUDE <std/io>\n.END")?;
+    assert_eq!(output.segments, alone.segments);
+    let err = Assembler::new()
+        .assemble(".ORIG x3000\n.INCLUDE <std/fs>\n.END")
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Unknown library module std/fs"),
+        "{}",
+        err
+    );
+    // Modules only use portable constructs besides including each other
+    for (name, _) in stdlib::MODULES {
+        let output = Assembler::new()
+            .strict(true)
+            .assemble(format!(".ORIG x3000\nHALT\n.INCLUDE <{}>\n.END", name))?;
+        for diagnostic in output.diagnostics {
+            assert_eq!(
+                diagnostic.message, ".INCLUDE pseudo-operation is not portable",
+                "{}",
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_error_locations() -> Result<(), Error> {
+    let err = Assembler::new()
+        .assemble(".ORIG x3000\n.INCLUDE <std/io>\nADD R0, R0, #99\n.END")
+        .unwrap_err();
+    let diagnostic = err.diagnostic();
+    let location = diagnostic.location.unwrap();
+    assert_eq!((location.line, location.column), (3, 13));
+    assert_eq!(diagnostic.file, None);
+
+    let output = Assembler::new()
+        .assemble(".ORIG x3000\nJSR STD_PRINT_INT\n.INCLUDE <std/io>\nA BR A\n.END")?;
+    assert_eq!(output.files, ["<input>", "<std/io>", "<std/math>"]);
+    let warning = &output.diagnostics[0];
+    assert_eq!(warning.file, None);
+    assert_eq!(warning.location.map(|location| location.line), Some(4));
+    let routine = output.symbols["STD_PRINT_INT"];
+    let location = output.image.source_map()[&routine];
+    assert_eq!(output.files[location.file], "<std/io>");
+    let listed = output
+        .listing
+        .iter()
+        .find(|line| line.address == routine)
+        .unwrap();
+    assert_eq!(listed.line, location.line);
+    assert_eq!(
+        stdlib::module("std/io")
+            .unwrap()
+            .lines()
+            .nth(location.line - 1),
+        Some(listed.source.as_str())
+    );
+    Ok(())
+}
@@ -1,24 +1,125 @@
-use crate::Rule;
+use crate::object::ObjectError;
+use crate::{pair_error_message, Assembler, Error, Rule};
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Error as FmtError, Write};
+use pest::error::Error as PestError;
+use pest::error::ErrorVariant as PestErrorVariant;
 use pest::iterators::Pair;
-use std::collections::BTreeMap;
-use std::fmt::{Write, Error as FmtError};
 
-const TABLE_HEADER: & str = r#"//Symbol Name		Page Address
+const TABLE_HEADER: &str = r#"//Symbol Name		Page Address
 //----------------	------------
 "#;
-const SPACES: & str = "                              ";
+const SPACES: &str = "                              ";
 
-pub(crate) type SymbolTable<'i> = BTreeMap<String, (usize, Pair<'i, Rule>)>;
+/// Maximum number of characters in a label.
+pub const MAX_LABEL_LENGTH: usize = 20;
 
-pub fn table_to_string(sym: SymbolTable<'_>, entry: usize) -> Result<String, FmtError> {
+/// Mnemonics of both targets, pseudo-instructions, registers, trap aliases and pseudo-operation
+/// names which cannot be used as labels.
+pub const RESERVED_WORDS: &[&str] = &[
+    "ADD", "AND", "NOT", "BR", "BRN", "BRZ", "BRP", "BRNZ", "BRNP", "BRZP", "BRNZP", "JMP", "JSR",
+    "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR", "RET", "RTI", "TRAP", "NOP", "LDB",
+    "STB", "LDW", "STW", "XOR", "LSHF", "RSHFL", "RSHFA", "PUSH", "POP", "MOV", "CLR", "INC",
+    "DEC", "SUB", "NEG", "CALL", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT", "R0", "R1", "R2",
+    "R3", "R4", "R5", "R6", "R7", "ORIG", "END", "BLKW", "FILL", "STRINGZ", "EXTERNAL", "TRAPDEF",
+];
+
+/// Checks whether given label pair is allowed to be declared.
+pub(crate) fn check_label(label: &Pair<Rule>, options: &Assembler) -> Result<(), Error> {
+    check_label_name(label.as_str(), options)
+        .map_err(|message| pair_error_message!(label, "{}", message).into())
+}
+
+/// Checks whether given name is allowed to be declared as a label, returning the reason if not.
+pub(crate) fn check_label_name(name: &str, options: &Assembler) -> Result<(), String> {
+    let mut chars = name.chars();
+    let is_identifier = chars.next().is_some_and(|c| c == '_' || c.is_alphabetic())
+        && chars.all(|c| c == '_' || c.is_alphanumeric());
+    if !is_identifier {
+        return Err(format!("{} is not a valid label", name));
+    }
+    if is_number_literal(name) {
+        return Err(format!(
+            "{} is a number literal and cannot be used as a label",
+            name
+        ));
+    }
+    if RESERVED_WORDS
+        .iter()
+        .any(|word| word.eq_ignore_ascii_case(name))
+    {
+        return Err(format!(
+            "{} is a reserved word and cannot be used as a label",
+            name
+        ));
+    }
+    if !options.unicode_labels && !name.is_ascii() {
+        return Err(format!(
+            "Label {} contains non-ASCII characters, which are not enabled",
+            name
+        ));
+    }
+    if name.chars().count() > MAX_LABEL_LENGTH {
+        return Err(format!(
+            "Label {} is longer than {} characters",
+            name, MAX_LABEL_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Returns whether given identifier is read as a prefixed number literal, e.g. `xF` or `b1`.
+fn is_number_literal(name: &str) -> bool {
+    let radix = match name.as_bytes().first() {
+        Some(b'x') | Some(b'X') => 16,
+        Some(b'b') | Some(b'B') => 2,
+        Some(b'o') | Some(b'O') => 8,
+        _ => return false,
+    };
+    let digits = &name[1..];
+    !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/// Formats symbols with their absolute addresses in `.sym` file format.
+pub fn format_symbols(symbols: &BTreeMap<String, u16>) -> Result<String, FmtError> {
     let mut s = String::from(TABLE_HEADER);
-    for (key, (idx, _)) in sym {
-        let space_size = 28 - key.len() - 4;
-        writeln!(s, "//\t{}{}{:04X}", key, &SPACES[0..space_size], entry + idx)?;
+    for (key, address) in symbols {
+        let space_size = 24usize.saturating_sub(key.len()).max(1);
+        writeln!(s, "//\t{}{}{:04X}", key, &SPACES[0..space_size], address)?;
     }
     Ok(s)
 }
 
+/// Reads symbols with their absolute addresses from a table in `.sym` file format.
+///
+/// Lines other than symbol entries(`//\tNAME  3000`), e.g. the header, are skipped.
+pub fn parse_symbols(table: &str) -> Result<BTreeMap<String, u16>, ObjectError> {
+    let mut symbols = BTreeMap::new();
+    for (index, line) in table.lines().enumerate() {
+        let entry = match line.strip_prefix("//\t") {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut fields = entry.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(address), None) => {
+                let address = u16::from_str_radix(address, 16)
+                    .map_err(|_| ObjectError::InvalidSymbol { line: index + 1 })?;
+                symbols.insert(name.to_owned(), address);
+            }
+            _ => return Err(ObjectError::InvalidSymbol { line: index + 1 }),
+        }
+    }
+    Ok(symbols)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,8 +147,9 @@ SAVE2	.FILL	x0000
 	.END"#,
         )?
         .collect::<Vec<_>>();
-        let (symbols, size, entry) = crate::first_pass(&parsed)?;
-        let table_str = table_to_string(symbols, entry)?;
+        let options = crate::Assembler::new();
+        let (ctx, _size) = crate::first_pass(&parsed, &options)?;
+        let table_str = format_symbols(&ctx.symbols.absolute())?;
         assert_eq!(
             table_str,
             r#"//Symbol Name		Page Address
@@ -58,6 +160,82 @@ SAVE2	.FILL	x0000
 //	TEST                    0403
 "#
         );
+        assert_eq!(parse_symbols(&table_str)?, ctx.symbols.absolute());
+        assert_eq!(
+            parse_symbols("//\tTEST\n"),
+            Err(ObjectError::InvalidSymbol { line: 1 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_identifiers() -> Result<(), Error> {
+        let output = crate::Assembler::new().assemble(
+            ".ORIG x3000\nLOOP_END BRnzp print_num2\nprint_num2 RET\n_tmp .FILL #0\n.END",
+        )?;
+        assert_eq!(output.symbols.get("LOOP_END"), Some(&0x3000));
+        assert_eq!(output.symbols.get("print_num2"), Some(&0x3001));
+        assert_eq!(output.symbols.get("_tmp"), Some(&0x3002));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_labels() {
+        for source in &[
+            ".ORIG x3000\nR1 ADD R0, R0, #1\n.END",
+            ".ORIG x3000\nadd\n.END",
+            ".ORIG x3000\nBRnzp\n.END",
+            ".ORIG x3000\n.EXTERNAL PUTS\n.END",
+        ] {
+            let err = crate::assemble(source).unwrap_err();
+            assert!(err.to_string().contains("reserved word"), "{}", err);
+        }
+        for word in [
+            "PUSH", "POP", "MOV", "CLR", "INC", "DEC", "SUB", "NEG", "CALL", "LDB", "STB", "LDW",
+            "STW", "XOR", "LSHF", "RSHFL", "RSHFA",
+        ] {
+            for source in [
+                format!(".ORIG x3000\n{} ADD R0, R0, R0\n.END", word),
+                format!(".ORIG x3000\n{}: HALT\n.END", word.to_lowercase()),
+            ] {
+                let err = crate::assemble(&source).unwrap_err();
+                assert!(
+                    err.to_string().contains("reserved word"),
+                    "{}: {}",
+                    word,
+                    err
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_number_labels() -> Result<(), Error> {
+        for source in [
+            ".ORIG x3000\nb1 .FILL b1\n.END",
+            ".ORIG x3000\nB1 .FILL B1\n.END",
+            ".ORIG x3000\no7 HALT\n.END",
+            ".ORIG x3000\nxF: HALT\n.END",
+            ".ORIG x3000\n.TRAPDEF b1_0, x26\n.END",
+        ] {
+            let err = crate::assemble(source).unwrap_err();
+            assert!(err.to_string().contains("number literal"), "{}", err);
+        }
+        let output = crate::Assembler::new()
+            .assemble(".ORIG x3000\nb12 HALT\no8 HALT\nxG HALT\nb_1 HALT\n.END")?;
+        assert_eq!(output.symbols.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_restrictions() -> Result<(), Error> {
+        let source = ".ORIG x3000\nÉTAPE HALT\n.END";
+        crate::Assembler::new().assemble(source).unwrap_err();
+        crate::Assembler::new()
+            .unicode_labels(true)
+            .assemble(source)?;
+        crate::assemble(".ORIG x3000\nABCDEFGHIJKLMNOPQRST HALT\n.END")?;
+        crate::assemble(".ORIG x3000\nABCDEFGHIJKLMNOPQRSTU HALT\n.END").unwrap_err();
         Ok(())
     }
 }
@@ -0,0 +1,209 @@
+//! Provides [Target] architectures sharing the assembly language front end.
+use crate::isa::{self, Format};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Architecture the assembler produces code for.
+///
+/// Both targets share the syntax, pseudo-operations and pseudo-instructions, and differ in:
+///
+/// |                          | `Lc3`                     | `Lc3b`                                   |
+/// |--------------------------|---------------------------|------------------------------------------|
+/// | Instructions             | [isa::INSTRUCTIONS]       | [isa::LC3B_INSTRUCTIONS]                 |
+/// | Addresses                | one per word              | one per byte, `.ORIG` must be even       |
+/// | Labels, `.FILL label`    | word address              | byte address                             |
+/// | `.STRINGZ`               | one character per word    | one character per byte, little-endian    |
+/// | Object file              | big-endian words          | one `0x`-prefixed hexadecimal word per line |
+/// | `PUSH`, `POP`, `CALL`    | move `R6` by 1            | move `R6` by 2                           |
+///
+/// PC-relative offsets count words on both targets, so `BR`, `JSR` and `LEA` are written alike.
+/// `LD` and `ST` do not exist on LC-3b, whose `LDI` and `STI` take a base register and offset
+/// instead of a PC-relative offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Target {
+    /// LC-3, with 16-bit words as the unit of addressing.
+    #[default]
+    Lc3,
+    /// LC-3b, with bytes as the unit of addressing.
+    Lc3b,
+}
+
+impl Target {
+    /// Returns every instruction of the target.
+    pub fn instructions(self) -> &'static [Format] {
+        match self {
+            Target::Lc3 => isa::INSTRUCTIONS,
+            Target::Lc3b => isa::LC3B_INSTRUCTIONS,
+        }
+    }
+
+    /// Returns formats of the target with given mnemonic, ignoring case.
+    pub fn formats(self, mnemonic: &str) -> impl Iterator<Item = &'static Format> + '_ {
+        self.instructions()
+            .iter()
+            .filter(move |format| format.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// Returns the number of addresses each word occupies.
+    pub(crate) fn word_size(self) -> usize {
+        match self {
+            Target::Lc3 => 1,
+            Target::Lc3b => 2,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Target::Lc3 => "LC-3",
+            Target::Lc3b => "LC-3b",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assembler, Error};
+
+    const PROGRAM: &str = "\
+.ORIG x3000
+        LEA     R0, MSG
+        LDB     R1, R0, #1
+        LSHF    R2, R1, #3
+        RSHFA   R3, R2, #1
+        XOR     R4, R3, R2
+        NOT     R4, R4
+        STW     R4, R0, #2
+        BRz     DONE
+DONE    HALT
+ADDR    .FILL   DONE
+MSG     .STRINGZ \"Hi!\"
+TAIL    .BLKW   1
+.END";
+
+    #[test]
+    fn test_lc3b() -> Result<(), Error> {
+        let output = Assembler::new().target(Target::Lc3b).assemble(PROGRAM)?;
+        assert_eq!(
+            output.segments[0].words,
+            vec![
+                0xE009, 0x2201, 0xD443, 0xD6B1, 0x98C2, 0x993F, 0x7802, 0x0400, 0xF025, 0x3010,
+                0x6948, 0x0021, 0x0000
+            ]
+        );
+        assert_eq!(output.symbols["DONE"], 0x3010);
+        assert_eq!(output.symbols["MSG"], 0x3014);
+        assert_eq!(output.symbols["TAIL"], 0x3018);
+        assert!(output
+            .object_bytes()
+            .starts_with(b"0x3000\n0xE009\n0x2201\n"));
+
+        let options = Assembler::new()
+            .target(Target::Lc3b)
+            .define("EVEN", 0x3010)
+            .define("ODD", 0x3011);
+        let output = options.assemble(".ORIG x3000\nBR EVEN\n.END")?;
+        assert_eq!(output.segments[0].words, [0x0E07]);
+        let err = options.assemble(".ORIG x3000\nBR ODD\n.END").unwrap_err();
+        assert!(err.to_string().contains("not aligned to a word"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lc3b_encodings() -> Result<(), Error> {
+        // Each instruction with its encoding from the LC-3b ISA, appendix A of Patt & Patel
+        let instructions = [
+            ("ADD R1, R2, R3", 0x1283),
+            ("ADD R1, R2, #-1", 0x12BF),
+            ("AND R1, R2, R3", 0x5283),
+            ("AND R1, R2, #5", 0x52A5),
+            ("BRnp TARGET", 0x0A13),
+            ("JMP R3", 0xC0C0),
+            ("RET", 0xC1C0),
+            ("JSR TARGET", 0x4810),
+            ("JSRR R4", 0x4100),
+            ("LDB R1, R2, #-3", 0x22BD),
+            ("LDW R1, R2, #3", 0x6283),
+            ("LDI R1, R2, #4", 0xA284),
+            ("STI R1, R2, #-4", 0xB2BC),
+            ("LEA R5, TARGET", 0xEA0A),
+            ("NOT R1, R2", 0x92BF),
+            ("XOR R1, R2, R3", 0x9283),
+            ("XOR R1, R2, #7", 0x92A7),
+            ("RTI", 0x8000),
+            ("LSHF R1, R2, #3", 0xD283),
+            ("RSHFL R1, R2, #4", 0xD294),
+            ("RSHFA R1, R2, #15", 0xD2BF),
+            ("STB R1, R2, #-1", 0x32BF),
+            ("STW R1, R2, #-2", 0x72BE),
+            ("TRAP x25", 0xF025),
+            ("TARGET NOP", 0x0000),
+            ("GETC", 0xF020),
+            ("OUT", 0xF021),
+            ("PUTS", 0xF022),
+            ("IN", 0xF023),
+            ("PUTSP", 0xF024),
+            ("HALT", 0xF025),
+        ];
+        let source = instructions
+            .iter()
+            .map(|(instruction, _)| *instruction)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let output = Assembler::new()
+            .target(Target::Lc3b)
+            .assemble(format!(".ORIG x3000\n{}\n.END", source))?;
+        let expected = instructions
+            .iter()
+            .map(|(_, word)| *word)
+            .collect::<Vec<u16>>();
+        assert_eq!(output.segments[0].words, expected);
+        assert_eq!(output.symbols["TARGET"], 0x3030);
+
+        let covered = instructions
+            .iter()
+            .map(|(instruction, _)| {
+                let instruction = instruction.trim_start_matches("TARGET ");
+                match instruction.split_whitespace().next().unwrap() {
+                    branch if branch.starts_with("BR") => "BR",
+                    mnemonic => mnemonic,
+                }
+            })
+            .collect::<Vec<_>>();
+        for format in isa::LC3B_INSTRUCTIONS {
+            assert!(covered.contains(&&*format.mnemonic), "{}", format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_instructions() {
+        let err = Assembler::new().assemble(PROGRAM).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("LDB is not an instruction of LC-3"),
+            "{}",
+            err
+        );
+        let err = Assembler::new()
+            .target(Target::Lc3b)
+            .assemble(".ORIG x3000\nLD R0, #0\n.END")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("LD is not an instruction of LC-3b"));
+        Assembler::new()
+            .target(Target::Lc3b)
+            .assemble(".ORIG x3000\nLDI R0, LABEL\nLABEL HALT\n.END")
+            .unwrap_err();
+        Assembler::new()
+            .target(Target::Lc3b)
+            .assemble(".ORIG x3001\nHALT\n.END")
+            .unwrap_err();
+    }
+}
@@ -1,104 +1,137 @@
-use bitstream_io::write::BitWriter;
-use bitstream_io::{BigEndian, Numeric, SignedNumeric};
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::num::ParseIntError;
 use pest::iterators::Pair;
-use std::io::Result as IOResult;
-use std::io::Write;
-use std::num::ParseIntError;
-
-pub fn parse_number_literal(s: &str) -> Result<i64, ParseIntError> {
-    s.parse().or_else(|_| match s.chars().nth(0) {
-        Some('#') => s[1..].parse(),
-        Some('x') | Some('X') => i64::from_str_radix(&s[1..], 16),
-        _ => panic!("Invalid decimal literal {} received", s),
-    })
+
+/// Error returned by [parse_number_literal].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiteralError {
+    /// The literal does not start with a known prefix
+    UnknownPrefix,
+    /// The digits are not valid for the radix of the literal
+    InvalidDigits,
+    /// The character literal does not contain exactly one character
+    InvalidCharacter,
+    /// The character does not fit in a 16-bit word
+    CharacterOverflow,
 }
 
-pub fn parse_register_literal(s: &str) -> Result<i64, ParseIntError> {
-    match s.to_ascii_lowercase().chars().nth(0) {
-        Some('r') => i64::from_str_radix(&s[1..], 16),
-        _ => panic!("Invalid register literal {} received", s),
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LiteralError::UnknownPrefix => "unknown number literal prefix",
+            LiteralError::InvalidDigits => "invalid digits for the radix of the literal",
+            LiteralError::InvalidCharacter => "character literal must contain a single character",
+            LiteralError::CharacterOverflow => "character does not fit in a 16-bit word",
+        })
     }
 }
 
-pub enum PCOffsetTarget {
-    Symbol(String),
-    ExplicitOffset(i64),
-}
+#[cfg(feature = "std")]
+impl std::error::Error for LiteralError {}
 
-pub fn parse_pc_pair(target_pair: &Pair<super::Rule>) -> PCOffsetTarget {
-    let pair_str = target_pair.as_str();
-
-    match pair_str.parse() {
-        Ok(offset) => PCOffsetTarget::ExplicitOffset(offset), // TODO: Determine whether this is allowed
-        Err(_) => match pair_str.chars().nth(0) {
-            Some('#') => PCOffsetTarget::ExplicitOffset(pair_str[1..].parse().unwrap()),
-            _ => PCOffsetTarget::Symbol(pair_str.to_owned()),
-        },
+/// Parses a number literal into its value.
+///
+/// Accepted forms are decimal(`#10`, `10`), hexadecimal(`xA`, `0xA`), binary(`b1010`, `0b1010`),
+/// octal(`o12`, `0o12`) and character(`'A'`, `'\n'`) literals. Non-decimal literals may also be
+/// prefixed with `#`, a minus sign may follow the radix prefix(`x-A`) and digits may be separated
+/// with underscores(`b0000_1010`).
+pub fn parse_number_literal(s: &str) -> Result<i64, LiteralError> {
+    if s.starts_with('\'') {
+        return parse_character_literal(s);
+    }
+    let s = s.strip_prefix('#').unwrap_or(s);
+    let (radix, digits) = match s.as_bytes() {
+        [b'0', b'x', ..] | [b'0', b'X', ..] => (16, &s[2..]),
+        [b'0', b'b', ..] | [b'0', b'B', ..] => (2, &s[2..]),
+        [b'0', b'o', ..] | [b'0', b'O', ..] => (8, &s[2..]),
+        [b'x', ..] | [b'X', ..] => (16, &s[1..]),
+        [b'b', ..] | [b'B', ..] => (2, &s[1..]),
+        [b'o', ..] | [b'O', ..] => (8, &s[1..]),
+        [b'-', ..] | [b'0'..=b'9', ..] => (10, s),
+        _ => return Err(LiteralError::UnknownPrefix),
+    };
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(LiteralError::InvalidDigits);
     }
+    let digits = digits.replace('_', "");
+    i64::from_str_radix(&digits, radix).map_err(|_| LiteralError::InvalidDigits)
 }
 
-/// Wrapper struct for [BitWriter] which extends some functionality
-/// e.g. counting total bytes written without consuming itself.
-pub struct BitVecWriter<W>
-where
-    W: Write,
-{
-    wr: BitWriter<W, BigEndian>,
-    bits_pushed: u32,
+fn parse_character_literal(s: &str) -> Result<i64, LiteralError> {
+    let inner = s
+        .strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .ok_or(LiteralError::InvalidCharacter)?;
+    let unescaped = unescape(inner).ok_or(LiteralError::InvalidCharacter)?;
+    let mut chars = unescaped.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if u32::from(c) <= 0xFFFF => Ok(i64::from(u32::from(c))),
+        (Some(_), None) => Err(LiteralError::CharacterOverflow),
+        _ => Err(LiteralError::InvalidCharacter),
+    }
 }
 
-impl<W> BitVecWriter<W>
-where
-    W: Write,
-{
-    pub fn new(wr: W) -> Self {
-        BitVecWriter {
-            wr: BitWriter::new(wr),
-            bits_pushed: 0,
+/// Replaces the escape sequences of string and character literals, e.g. `\n` and `\u00A9`.
+/// Returns `None` for an unknown or incomplete escape sequence.
+pub(crate) fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
         }
+        unescaped.push(match chars.next()? {
+            'b' => '\u{8}',
+            'f' => '\u{C}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ '\'' | c @ '"' | c @ '\\' | c @ '/' => c,
+            'u' => {
+                let digits = chars.as_str().get(..4)?;
+                if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                    return None;
+                }
+                chars = chars.as_str()[4..].chars();
+                char::from_u32(u32::from_str_radix(digits, 16).ok()?)?
+            }
+            _ => return None,
+        });
     }
+    Some(unescaped)
+}
 
-    /// Wrapping method for [BitWriter::write]
-    pub fn write<U>(&mut self, bits: u32, value: U) -> IOResult<()>
-    where
-        U: Numeric,
-    {
-        self.wr.write(bits, value).map(|_| {
-            self.bits_pushed += bits;
-        })
-    }
-
-    /// Wrapping method for [BitWriter::write]
-    pub fn write_signed<S>(&mut self, bits: u32, value: S) -> IOResult<()>
-    where
-        S: SignedNumeric,
-    {
-        self.wr.write_signed(bits, value).map(|_| {
-            self.bits_pushed += bits;
-        })
+pub fn parse_register_literal(s: &str) -> Result<i64, ParseIntError> {
+    match s.to_ascii_lowercase().chars().next() {
+        Some('r') => i64::from_str_radix(&s[1..], 16),
+        _ => panic!("Invalid register literal {} received", s),
     }
+}
 
-    /// Wrapping method for [BitWriter::write_bit]
-    pub fn write_bit(&mut self, bit: bool) -> IOResult<()> {
-        self.wr.write_bit(bit).map(|_| {
-            self.bits_pushed += 1;
-        })
-    }
+pub enum PCOffsetTarget {
+    Symbol(String),
+    ExplicitOffset(i64),
+}
 
-    /// Returns a pair of `(bytes_written, remaining_bits_count)`
-    pub fn count_written(&self) -> (u32, u8) {
-        (self.bits_pushed / 8, (self.bits_pushed % 8) as u8)
-    }
+pub fn parse_pc_pair(target_pair: &Pair<super::Rule>) -> Result<PCOffsetTarget, LiteralError> {
+    let pair_str = target_pair.as_str();
 
-    pub fn into_inner(self) -> BitWriter<W, BigEndian> {
-        self.wr
+    match target_pair.as_rule() {
+        super::Rule::label => Ok(PCOffsetTarget::Symbol(pair_str.to_owned())),
+        // TODO: Determine whether explicit offsets without # are allowed
+        _ => parse_number_literal(pair_str).map(PCOffsetTarget::ExplicitOffset),
     }
 }
 
 #[macro_export]
 macro_rules! collect_inner {
     ($pair:expr) => {
-        &$pair.into_inner().collect::<Vec<_>>()[..]
+        &$pair.into_inner().collect::<alloc::vec::Vec<_>>()[..]
     };
 }
 
@@ -107,106 +140,47 @@ macro_rules! pair_error_message {
     ($pair:expr, $($arg:tt)*) => {
         PestError::new_from_span(
             PestErrorVariant::<Rule>::CustomError {
-                message: format!($($arg)*)
+                message: alloc::format!($($arg)*)
             },
             $pair.as_span()
         )
     };
 }
 
-#[macro_export]
-macro_rules! write_fields {
-    ($wr:expr) => {};
-
-    ($wr:expr $(,[$($more:tt)+])*,) => {
-        write_fields!($wr $(,[$($more)+])*);
-    };
-
-    ($wr:expr, [const; $bits:expr, $value:expr] $(,[$($more:tt)+])*) => {
-        $wr.write($bits, $value)?;
-        write_fields!($wr $(,[$($more)+])*);
-    };
-
-    ($wr:expr, [bool; $value:expr] $(,[$($more:tt)+])*) => {
-        $wr.write_bit($value)?;
-        write_fields!($wr $(,[$($more)+])*);
-    };
-
-    ($wr:expr, [number $name:ident; $bits:expr, $pair:expr] $(,[$($more:tt)+])*) => {
-        let $name = util::parse_number_literal($pair.as_str())?;
-        write_fields!($wr,
-            [$name; $bits, $pair, $name]
-            $(,[$($more)+])*,
-        );
-    };
-
-    ($wr:expr, [number_signed $name:ident; $bits:expr, $pair:expr] $(,[$($more:tt)+])*) => {
-        let $name = util::parse_number_literal($pair.as_str())?;
-        write_fields!($wr,
-            [signed $name; $bits, $pair, $name]
-            $(,[$($more)+])*,
-        );
-    };
-
-    ($wr:expr, [register $name:ident; $pair:expr] $(,[$($more:tt)+])*) => {
-        let $name = util::parse_register_literal($pair.as_str())?;
-        write_fields!($wr,
-            [$name; 3, $pair, $name]
-            $(,[$($more)+])*,
-        );
-    };
+/// Returns the candidate closest to given word ignoring case, if it is a likely misspelling of
+/// it, e.g. `ADD` for `ADDD`.
+pub(crate) fn closest<'a>(
+    word: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let word = word.to_ascii_uppercase();
+    let limit = (word.len() / 3).max(1);
+    candidates
+        .map(|candidate| {
+            (
+                edit_distance(&word, &candidate.to_ascii_uppercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
 
-    ($wr:expr, [pcoffset; $bits:expr, $pair:expr, $table:expr] $(,[$($more:tt)+])*) => {
-        let _current_offset = ($wr.count_written().0-2) / 2;
-        match util::parse_pc_pair($pair) {
-            util::PCOffsetTarget::Symbol(_sym) => {
-                if let Some((_offset, _)) = $table.get(&_sym) {
-                    write_fields!(
-                        $wr,
-                        [signed pc_offset; $bits, $pair, *_offset as i32 - _current_offset as i32 - 1],
-                    );
-                } else {
-                    return Err(pair_error_message!(
-                        $pair,
-                        "Cannot find symbol {}, available symbols: {}",
-                        _sym,
-                        $table.keys().map(String::to_owned).collect::<Vec<_>>().join(", "),
-                    ).into())
-                }
-            },
-            util::PCOffsetTarget::ExplicitOffset(_offset) => {
-                write_fields!($wr, [signed pc_offset; $bits, $pair, _offset]);
-            }
+/// Returns the number of characters to insert, remove or replace to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
         }
-        write_fields!($wr $(,[$($more)+])*);
-    };
-
-    ($wr:expr, [$name:ident; $bits:expr, $pair:expr, $value:expr] $(,[$($more:tt)+])*) => {
-        write_fields!($wr, [$name; $bits, $pair, $value, write] $(,[$($more)+])*);
-    };
-
-    ($wr:expr, [signed $name:ident; $bits:expr, $pair:expr, $value:expr] $(,[$($more:tt)+])*) => {
-        write_fields!($wr, [$name; $bits, $pair, $value, write_signed] $(,[$($more)+])*);
-    };
-
-    ($wr:expr,
-        [$name:ident; $bits:expr, $pair:expr, $value:expr, $func:ident]
-        $(,[$($more:tt)+])*) => {
-        $wr.$func($bits, $value).map_err(|e| -> Error {
-            if e.kind() == IOErrorKind::InvalidInput {
-                pair_error_message!(
-                    $pair,
-                    "Value {} overflows for given field {}",
-                    $value,
-                    stringify!($name)
-                )
-                .into()
-            } else {
-                e.into()
-            }
-        })?;
-        write_fields!($wr $(,[$($more)+])*);
-    };
+    }
+    row[b.len()]
 }
 
 #[cfg(test)]
@@ -214,23 +188,78 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_bitvecwriter() {
-        let mut buf = Vec::new();
-        let mut bvw = BitVecWriter::new(&mut buf);
-        bvw.write(8, 3).unwrap();
-        assert_eq!(bvw.count_written(), (1, 0));
-        bvw.write(3, 5).unwrap();
-        assert_eq!(bvw.count_written(), (1, 3));
-        bvw.write_bit(true).unwrap();
-        bvw.write(4, 5).unwrap();
-        assert_eq!(bvw.count_written(), (2, 0));
-        assert_eq!(bvw.into_inner().into_writer(), &[3, 0b1011_0101]);
+    fn test_closest() {
+        let mnemonics = ["ADD", "AND", "LDR", "LEA", "PUTS", "PUTSP"];
+        assert_eq!(closest("addd", mnemonics.iter().copied()), Some("ADD"));
+        assert_eq!(closest("LDRR", mnemonics.iter().copied()), Some("LDR"));
+        assert_eq!(closest("PUTZ", mnemonics.iter().copied()), Some("PUTS"));
+        assert_eq!(closest("FROB", mnemonics.iter().copied()), None);
+        assert_eq!(edit_distance("KITTEN", "SITTING"), 3);
+    }
+
+    #[test]
+    fn test_parse_number_literal() {
+        let cases: &[(&str, i64)] = &[
+            ("#10", 10),
+            ("10", 10),
+            ("#-10", -10),
+            ("-10", -10),
+            ("x1F", 0x1F),
+            ("X-1f", -0x1F),
+            ("0x1F", 0x1F),
+            ("#x1F", 0x1F),
+            ("b0101", 0b0101),
+            ("0B0101", 0b0101),
+            ("b-1", -1),
+            ("o17", 0o17),
+            ("0o17", 0o17),
+            ("b0101_1010", 0b0101_1010),
+            ("x30_00", 0x3000),
+            ("1_000", 1000),
+            ("'A'", 65),
+            ("'\\n'", 10),
+            ("'\\''", 39),
+            ("'\\u00A9'", 0xA9),
+            ("'가'", 0xAC00),
+        ];
+        for (literal, value) in cases {
+            assert_eq!(parse_number_literal(literal), Ok(*value), "{}", literal);
+        }
     }
 
     #[test]
-    fn test_bvw_overflow() {
-        let mut buf = Vec::new();
-        let mut bvw = BitVecWriter::new(&mut buf);
-        bvw.write(4, 16).unwrap_err();
+    fn test_parse_number_literal_errors() {
+        let cases: &[(&str, LiteralError)] = &[
+            ("LOOP", LiteralError::UnknownPrefix),
+            ("", LiteralError::UnknownPrefix),
+            ("xG", LiteralError::InvalidDigits),
+            ("b012", LiteralError::InvalidDigits),
+            ("x", LiteralError::InvalidDigits),
+            ("x_1", LiteralError::InvalidDigits),
+            ("1__0", LiteralError::InvalidDigits),
+            ("''", LiteralError::InvalidCharacter),
+            ("'AB'", LiteralError::InvalidCharacter),
+            ("'😀'", LiteralError::CharacterOverflow),
+        ];
+        for (literal, error) in cases {
+            assert_eq!(
+                parse_number_literal(literal),
+                Err(error.clone()),
+                "{}",
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(
+            unescape(r#"a\"\\\/\b\f\n\r\t\0\u00A9"#).as_deref(),
+            Some("a\"\\/\u{8}\u{C}\n\r\t\0\u{A9}")
+        );
+        assert_eq!(unescape(r"\'").as_deref(), Some("'"));
+        for invalid in &[r"\", r"\x41", r"\u00A", r"\u00G0", r"\uD800"] {
+            assert_eq!(unescape(invalid), None, "{}", invalid);
+        }
     }
 }
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const PROGRAM: &str = ".ORIG x3000\nHALT\n.END\n";
+
+/// Returns an empty directory for the files of given test.
+fn directory(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lc3asm-cli-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn lc3asm(args: &[&PathBuf]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_lc3asm"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs lc3asm with given arguments, writing given input to its standard input.
+fn lc3asm_stdin(args: &[&str], input: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc3asm"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Asserts that lc3asm exits with an error naming both outputs written to standard output.
+fn assert_stdout_conflict(args: &[&str], first: &str, second: &str) {
+    let result = lc3asm_stdin(args, PROGRAM);
+    assert!(!result.status.success(), "{:?}", args);
+    assert!(result.stdout.is_empty(), "{:?}", args);
+    let message = format!(
+        "Cannot write both {} and {} to standard output",
+        first, second
+    );
+    assert!(
+        String::from_utf8_lossy(&result.stderr).contains(&message),
+        "{:?}: {:?}",
+        args,
+        result
+    );
+}
+
+#[test]
+fn test_positional_output() {
+    let dir = directory("positional");
+    let input = dir.join("in.asm");
+    fs::write(&input, PROGRAM).unwrap();
+    let output = dir.join("out.obj");
+    let result = lc3asm(&[&input, &output]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(fs::read(&output).unwrap(), [0x30, 0x00, 0xF0, 0x25]);
+    assert!(dir.join("out.sym").is_file());
+    assert!(!dir.join("in.obj").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_output_option() {
+    let dir = directory("option");
+    let input = dir.join("in.asm");
+    fs::write(&input, PROGRAM).unwrap();
+    let output = dir.join("out.obj");
+    let flag = PathBuf::from("-o");
+    let result = lc3asm(&[&input, &flag, &output]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(fs::read(&output).unwrap(), [0x30, 0x00, 0xF0, 0x25]);
+
+    let result = lc3asm(&[&input, &dir.join("other.obj"), &flag, &output]);
+    assert!(!result.status.success());
+    assert!(!dir.join("other.obj").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_text_outputs_to_stdout() {
+    let dir = directory("text-outputs");
+    let object = dir.join("out.obj");
+    let object = object.to_str().unwrap();
+    let result = lc3asm_stdin(&["-", object, "--listing", "-"], PROGRAM);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(String::from_utf8_lossy(&result.stdout).contains("xF025"));
+    assert_eq!(fs::read(object).unwrap(), [0x30, 0x00, 0xF0, 0x25]);
+
+    assert_stdout_conflict(&["-", "--listing", "-"], "object", "listing");
+    assert_stdout_conflict(&["-", "--source-map", "-"], "object", "source map");
+    assert_stdout_conflict(
+        &["-", object, "--listing", "-", "--source-map", "-"],
+        "listing",
+        "source map",
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_embedding_outputs_to_stdout() {
+    let dir = directory("embedding-outputs");
+    let object = dir.join("out.obj");
+    let object = object.to_str().unwrap();
+    for flag in ["--c-header", "--rust-module"] {
+        let result = lc3asm_stdin(&["-", object, flag, "-"], PROGRAM);
+        assert!(result.status.success(), "{:?}", result);
+        assert!(String::from_utf8_lossy(&result.stdout).contains("0xF025"));
+    }
+
+    assert_stdout_conflict(&["-", "--c-header", "-"], "object", "C header");
+    assert_stdout_conflict(&["-", "--rust-module", "-"], "object", "Rust module");
+    assert_stdout_conflict(
+        &["-", object, "--c-header", "-", "--rust-module", "-"],
+        "C header",
+        "Rust module",
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_stdin() {
+    let result = lc3asm_stdin(&["-"], PROGRAM);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(result.stdout, [0x30, 0x00, 0xF0, 0x25]);
+
+    let dir = directory("stdin");
+    let object = dir.join("out.obj");
+    let result = lc3asm_stdin(&["-", object.to_str().unwrap()], PROGRAM);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stdout.is_empty());
+    assert_eq!(fs::read(&object).unwrap(), [0x30, 0x00, 0xF0, 0x25]);
+    assert!(dir.join("out.sym").is_file());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_stdout() {
+    let dir = directory("stdout");
+    let input = dir.join("in.asm");
+    fs::write(&input, ".ORIG x3000\nLOOP HALT\n.END\n").unwrap();
+    let input = input.to_str().unwrap();
+
+    let result = lc3asm_stdin(&[input, "-o", "-"], "");
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(result.stdout, [0x30, 0x00, 0xF0, 0x25]);
+    assert!(!dir.join("in.obj").exists());
+    assert!(!dir.join("in.sym").exists());
+
+    let result = lc3asm_stdin(&[input, "--sym", "-"], "");
+    assert!(result.status.success(), "{:?}", result);
+    assert!(String::from_utf8_lossy(&result.stdout).contains("LOOP"));
+    assert_eq!(
+        fs::read(dir.join("in.obj")).unwrap(),
+        [0x30, 0x00, 0xF0, 0x25]
+    );
+
+    let result = lc3asm_stdin(&[input, "--no-sym", "-o", "-"], "");
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(result.stdout, [0x30, 0x00, 0xF0, 0x25]);
+
+    assert_stdout_conflict(&[input, "-o", "-", "--sym", "-"], "object", "symbol table");
+    assert_stdout_conflict(&["-", "--sym", "-"], "object", "symbol table");
+    let result = lc3asm_stdin(&[input, "--no-sym", "--sym", "-"], "");
+    assert!(!result.status.success());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_no_sym() {
+    let dir = directory("no-sym");
+    let input = dir.join("in.asm");
+    fs::write(&input, PROGRAM).unwrap();
+    let result = lc3asm_stdin(&[input.to_str().unwrap(), "--no-sym"], "");
+    assert!(result.status.success(), "{:?}", result);
+    assert!(dir.join("in.obj").is_file());
+    assert!(!dir.join("in.sym").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_trap_alias() {
+    let source = ".ORIG x3000\nPUTNUM\n.END\n";
+    let result = lc3asm_stdin(&["-", "--trap", "PUTNUM=x26"], source);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(result.stdout, [0x30, 0x00, 0xF0, 0x26]);
+
+    let result = lc3asm_stdin(&["-", "--trap", "ADD=x26"], source);
+    assert!(!result.status.success());
+    assert!(
+        String::from_utf8_lossy(&result.stderr)
+            .contains("ADD is a reserved word and cannot be used as a label"),
+        "{:?}",
+        result
+    );
+}